@@ -0,0 +1,72 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use tokio_util::sync::CancellationToken;
+
+/// 对话请求的服务端 ID，用于取消与续传
+///
+/// 由服务端为每个请求生成（UUIDv7，含 74 位随机数）并写入响应头，客户端无法指定，避免猜测或冲突的 ID 影响其他请求。
+pub const COMPLETION_ID_HEADER: &str = "x-completion-id";
+
+/// 登记的请求
+struct Entry {
+    /// 发起请求的客户端凭据；未启用客户端鉴权时匿名请求共用服务端凭据，此时 ID 本身即取消凭证
+    scope: String,
+    token: CancellationToken,
+}
+
+/// 进行中请求的取消登记表，以服务端生成的对话 ID 为键
+#[derive(Default)]
+pub struct CancelRegistry {
+    entries: Mutex<HashMap<String, Entry>>,
+}
+
+impl CancelRegistry {
+    /// 登记一个进行中的请求，返回的守卫在请求结束（被丢弃）时自动注销
+    pub fn register(self: &Arc<Self>, completion_id: String, scope: String) -> CancelGuard {
+        let token = CancellationToken::new();
+        self.entries.lock().unwrap().insert(
+            completion_id.clone(),
+            Entry {
+                scope,
+                token: token.clone(),
+            },
+        );
+        CancelGuard {
+            registry: self.clone(),
+            completion_id,
+            token,
+        }
+    }
+
+    /// 取消指定请求，请求不存在、已结束或不属于该客户端时返回 `false`
+    pub fn cancel(&self, completion_id: &str, scope: &str) -> bool {
+        match self.entries.lock().unwrap().get(completion_id) {
+            Some(entry) if entry.scope == scope => {
+                entry.token.cancel();
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// 进行中请求的登记守卫
+pub struct CancelGuard {
+    registry: Arc<CancelRegistry>,
+    completion_id: String,
+    pub token: CancellationToken,
+}
+
+impl Drop for CancelGuard {
+    fn drop(&mut self) {
+        // ID 由服务端为每个请求单独生成，不会被其他请求重复登记
+        self.registry
+            .entries
+            .lock()
+            .unwrap()
+            .remove(&self.completion_id);
+    }
+}
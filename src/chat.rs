@@ -0,0 +1,419 @@
+use axum::{body::Bytes, http::StatusCode};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use utoipa::ToSchema;
+
+/// 对话请求
+///
+/// 只声明代理读取或改写的字段，其余字段（`temperature`、`stream_options` 等）保存在 `extra` 中，
+/// 序列化时原样写回，转发给上游的内容不会丢失。
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct ChatCompletionRequest {
+    pub model: String,
+    pub messages: Vec<Message>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stream: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub n: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_completion_tokens: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tools: Option<Vec<Tool>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_choice: Option<ToolChoice>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl ChatCompletionRequest {
+    /// 解析请求体，不是对话请求时返回 `None`，由上游校验并报错
+    pub fn parse(body: &[u8]) -> Option<Self> {
+        serde_json::from_slice(body).ok()
+    }
+
+    pub fn to_bytes(&self) -> Result<Bytes, (StatusCode, String)> {
+        serde_json::to_vec(self)
+            .map(Bytes::from)
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+    }
+
+    /// 输出 token 上限，兼容 `max_tokens` 与 `max_completion_tokens`
+    pub fn max_output_tokens(&self) -> Option<u64> {
+        self.max_tokens.or(self.max_completion_tokens)
+    }
+}
+
+/// 工具定义
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct Tool {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub function: FunctionDefinition,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// 函数工具的定义
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct FunctionDefinition {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// 参数的 JSON Schema
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parameters: Option<Value>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// 工具选择：`none`、`auto`、`required` 或指定的函数
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+#[serde(untagged)]
+pub enum ToolChoice {
+    Mode(String),
+    Function(NamedToolChoice),
+    /// 其他形式原样保留
+    Other(Value),
+}
+
+impl ToolChoice {
+    /// 指定调用的函数名
+    pub fn function_name(&self) -> Option<&str> {
+        match self {
+            Self::Function(choice) => Some(&choice.function.name),
+            _ => None,
+        }
+    }
+}
+
+/// 指定调用的函数
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct NamedToolChoice {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub function: FunctionName,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct FunctionName {
+    pub name: String,
+}
+
+/// 工具调用，流式响应中的增量只带部分字段
+#[derive(Clone, Debug, Default, Serialize, Deserialize, ToSchema)]
+pub struct ToolCall {
+    /// 流式增量中工具调用的序号
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub index: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(default, rename = "type", skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+    #[serde(default)]
+    pub function: FunctionCall,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// 被调用的函数与参数，参数按 OpenAI 约定为 JSON 字符串
+#[derive(Clone, Debug, Default, Serialize, Deserialize, ToSchema)]
+pub struct FunctionCall {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub arguments: Option<String>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// 对话消息，也用于非流式响应中的 `choices[].message`
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct Message {
+    pub role: String,
+    #[serde(default, skip_serializing_if = "Content::is_absent")]
+    pub content: Content,
+    /// 助手消息中的工具调用
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<Vec<ToolCall>>,
+    /// 工具结果消息对应的调用 ID
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl Message {
+    pub fn new(role: &str, content: impl Into<String>) -> Self {
+        Self {
+            role: role.to_string(),
+            content: Content::Text(content.into()),
+            tool_calls: None,
+            tool_call_id: None,
+            extra: Map::new(),
+        }
+    }
+
+    /// 提取消息的文本内容，多段内容以换行连接
+    pub fn text(&self) -> String {
+        match &self.content {
+            Content::Text(text) => text.clone(),
+            Content::Parts(parts) => parts
+                .iter()
+                .filter_map(|part| part.text.as_deref())
+                .collect::<Vec<_>>()
+                .join("\n"),
+            Content::Other(_) | Content::Absent => String::new(),
+        }
+    }
+}
+
+/// 消息内容
+#[derive(Clone, Debug, Default, Serialize, Deserialize, ToSchema)]
+#[serde(untagged)]
+pub enum Content {
+    Text(String),
+    /// 多段内容，如文本与图片混合
+    Parts(Vec<ContentPart>),
+    /// 其他形式原样保留，如带工具调用的助手消息中的 `null`
+    Other(Value),
+    /// 请求中没有 `content` 字段
+    #[default]
+    #[serde(skip)]
+    Absent,
+}
+
+impl Content {
+    fn is_absent(&self) -> bool {
+        matches!(self, Self::Absent)
+    }
+}
+
+/// 多段内容中的一段
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct ContentPart {
+    #[serde(rename = "type")]
+    pub kind: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// 对话响应或流式响应的数据块
+///
+/// 与请求相同，只声明代理读取或改写的字段，其余字段保存在 `extra` 中原样写回。
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct ChatCompletionResponse {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    /// 错误事件等没有 `choices` 时为 `None`，只含用量的数据块为空数组
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub choices: Option<Vec<Choice>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<Usage>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl ChatCompletionResponse {
+    pub fn parse(body: &[u8]) -> Option<Self> {
+        serde_json::from_slice(body).ok()
+    }
+
+    pub fn to_json(&self) -> Option<String> {
+        serde_json::to_string(self).ok()
+    }
+
+    pub fn choices(&self) -> &[Choice] {
+        self.choices.as_deref().unwrap_or_default()
+    }
+
+    pub fn choices_mut(&mut self) -> &mut [Choice] {
+        self.choices.as_deref_mut().unwrap_or_default()
+    }
+
+    /// 流式数据块中第一个 choice 的正文增量
+    pub fn delta_content(&self) -> Option<&str> {
+        self.choices().first()?.delta.as_ref()?.content.as_deref()
+    }
+}
+
+/// 响应中的一个候选结果：非流式响应带 `message`，流式数据块带 `delta`
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct Choice {
+    #[serde(default)]
+    pub index: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<Message>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub delta: Option<Delta>,
+    /// 流式响应中未结束的 choice 为 `null`，始终写回
+    #[serde(default)]
+    pub finish_reason: Option<String>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl Choice {
+    pub fn is_finished(&self) -> bool {
+        self.finish_reason.is_some()
+    }
+}
+
+/// 流式数据块中的增量
+#[derive(Clone, Debug, Default, Serialize, Deserialize, ToSchema)]
+pub struct Delta {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub role: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content: Option<String>,
+    /// 思考模型的推理内容
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reasoning_content: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<Vec<ToolCall>>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// token 用量，其余用量字段（如 `prompt_tokens_details`）保存在 `extra` 中
+#[derive(Clone, Debug, Default, Serialize, Deserialize, ToSchema)]
+pub struct Usage {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompt_tokens: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub completion_tokens: Option<u64>,
+    /// 命中上游上下文缓存的输入 token 数
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompt_cache_hit_tokens: Option<u64>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn round_trip<T: Serialize + serde::de::DeserializeOwned>(value: &Value) -> Value {
+        let typed: T = serde_json::from_value(value.clone()).unwrap();
+        serde_json::to_value(typed).unwrap()
+    }
+
+    #[test]
+    fn request_round_trips_tools_and_unknown_fields() {
+        let request = json!({
+            "model": "deepseek-chat",
+            "messages": [
+                { "role": "system", "content": "be brief", "name": "sys" },
+                {
+                    "role": "assistant",
+                    "content": null,
+                    "tool_calls": [{
+                        "id": "call_1",
+                        "type": "function",
+                        "function": { "name": "lookup", "arguments": "{\"q\":1}" },
+                    }],
+                },
+                { "role": "tool", "tool_call_id": "call_1", "content": "42" },
+                { "role": "user", "content": [{ "type": "text", "text": "hi", "cache": true }] },
+            ],
+            "tools": [{
+                "type": "function",
+                "function": {
+                    "name": "lookup",
+                    "description": "find things",
+                    "parameters": { "type": "object" },
+                    "strict": true,
+                },
+            }],
+            "tool_choice": { "type": "function", "function": { "name": "lookup" } },
+            "temperature": 0.2,
+            "stream_options": { "include_usage": true },
+        });
+        assert_eq!(round_trip::<ChatCompletionRequest>(&request), request);
+
+        let parsed: ChatCompletionRequest = serde_json::from_value(request).unwrap();
+        assert_eq!(
+            parsed
+                .tool_choice
+                .as_ref()
+                .and_then(ToolChoice::function_name),
+            Some("lookup")
+        );
+        assert_eq!(parsed.messages[2].tool_call_id.as_deref(), Some("call_1"));
+        assert!(parsed.extra.contains_key("temperature"));
+    }
+
+    #[test]
+    fn tool_choice_modes_round_trip() {
+        for choice in [json!("auto"), json!("none"), json!({ "type": "custom" })] {
+            assert_eq!(round_trip::<ToolChoice>(&choice), choice);
+        }
+        let parsed: ToolChoice = serde_json::from_value(json!("required")).unwrap();
+        assert!(matches!(parsed, ToolChoice::Mode(mode) if mode == "required"));
+    }
+
+    #[test]
+    fn response_round_trips_choices_and_unknown_fields() {
+        let response = json!({
+            "id": "c1",
+            "object": "chat.completion",
+            "created": 1760000000,
+            "model": "deepseek-chat",
+            "system_fingerprint": "fp",
+            "choices": [{
+                "index": 0,
+                "message": {
+                    "role": "assistant",
+                    "content": "hi",
+                    "reasoning_content": "thinking",
+                },
+                "logprobs": null,
+                "finish_reason": "stop",
+            }],
+            "usage": {
+                "prompt_tokens": 5,
+                "completion_tokens": 2,
+                "prompt_cache_hit_tokens": 1,
+                "prompt_tokens_details": { "cached_tokens": 1 },
+            },
+        });
+        assert_eq!(round_trip::<ChatCompletionResponse>(&response), response);
+    }
+
+    #[test]
+    fn stream_chunks_round_trip() {
+        let chunk = json!({
+            "id": "c1",
+            "object": "chat.completion.chunk",
+            "choices": [{
+                "index": 1,
+                "delta": {
+                    "content": "he",
+                    "tool_calls": [{ "index": 0, "function": { "arguments": "{\"a\"" } }],
+                    "extra_field": 1,
+                },
+                "finish_reason": null,
+            }],
+        });
+        assert_eq!(round_trip::<ChatCompletionResponse>(&chunk), chunk);
+
+        let parsed: ChatCompletionResponse = serde_json::from_value(chunk).unwrap();
+        assert_eq!(parsed.choices()[0].index, 1);
+        assert!(!parsed.choices()[0].is_finished());
+
+        // 只含用量的数据块保留空的 choices，错误事件没有 choices
+        let usage = json!({ "choices": [], "usage": { "total_tokens": 3 } });
+        assert_eq!(round_trip::<ChatCompletionResponse>(&usage), usage);
+        let error = json!({ "error": { "message": "boom" } });
+        assert_eq!(round_trip::<ChatCompletionResponse>(&error), error);
+    }
+}
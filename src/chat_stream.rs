@@ -0,0 +1,401 @@
+use std::time::{Duration, Instant};
+
+use axum::{body::Bytes, http::HeaderMap};
+use futures::{Stream, StreamExt, stream::BoxStream};
+use serde_json::{Value, json};
+
+use crate::{
+    cancel::CancelGuard,
+    chat::{ChatCompletionRequest, ChatCompletionResponse, Choice, Message},
+    metrics::CHAT_TIME_TO_FIRST_TOKEN,
+    normalize,
+    redaction::StreamRestorer,
+    sse::{SseEvent, SseParser},
+    tool_emulation::StreamEmulator,
+};
+
+/// 上游 DeepSeek 对话前缀续写（beta）接口地址
+const UPSTREAM_PREFIX_COMPLETIONS_URL: &str = "https://api.deepseek.com/beta/chat/completions";
+
+/// 从上游数据块中记下的元信息，用于构造代理自身追加的数据块
+#[derive(Default)]
+struct ChunkMeta {
+    id: Option<String>,
+    model: Option<String>,
+    created: Option<u64>,
+}
+
+impl ChunkMeta {
+    fn observe(&mut self, chunk: &ChatCompletionResponse) {
+        if self.id.is_some() {
+            return;
+        }
+        self.id = chunk.id.clone();
+        self.model = chunk.model.clone();
+        self.created = chunk.created;
+    }
+
+    /// 以 `cancelled` 结束原因收尾的数据块
+    fn cancelled_chunk(&self, request_id: &str) -> Bytes {
+        let chunk = json!({
+            "id": self.id.as_deref().unwrap_or(request_id),
+            "object": "chat.completion.chunk",
+            "created": self.created.unwrap_or_default(),
+            "model": self.model.as_deref().unwrap_or_default(),
+            "choices": [{ "index": 0, "delta": {}, "finish_reason": "cancelled" }],
+        });
+        Bytes::from(format!("data: {}\n\ndata: [DONE]\n\n", chunk))
+    }
+
+    /// 补发暂存正文的数据块
+    fn choices_chunk(&self, request_id: &str, choices: Vec<Choice>) -> Bytes {
+        let chunk = json!({
+            "id": self.id.as_deref().unwrap_or(request_id),
+            "object": "chat.completion.chunk",
+            "created": self.created.unwrap_or_default(),
+            "model": self.model.as_deref().unwrap_or_default(),
+            "choices": choices,
+        });
+        Bytes::from(format!("data: {}\n\n", chunk))
+    }
+
+    /// 上游中断且无法续写时的错误事件，附带已发出的正文
+    fn error_event(&self, request_id: &str, message: &str, partial: &str) -> Bytes {
+        let event = json!({
+            "id": self.id.as_deref().unwrap_or(request_id),
+            "error": {
+                "message": format!("上游响应中断: {}", message),
+                "type": "upstream_stream_error",
+                "partial_content": partial,
+            },
+        });
+        Bytes::from(format!("data: {}\n\ndata: [DONE]\n\n", event))
+    }
+
+    /// 上游在流中返回错误时转换后的错误事件，`error` 为统一格式的错误对象
+    fn upstream_error_event(&self, request_id: &str, error: Value) -> Bytes {
+        let event = json!({
+            "id": self.id.as_deref().unwrap_or(request_id),
+            "error": error,
+        });
+        Bytes::from(format!("data: {}\n\ndata: [DONE]\n\n", event))
+    }
+}
+
+/// 上游流中断后续写所需的原始请求
+///
+/// 续写使用 DeepSeek 的对话前缀续写接口，把已生成的正文作为 `prefix: true` 的 assistant 消息重新请求，
+/// 新的输出接在已发出的正文之后转发。
+pub struct Continuation {
+    pub client: reqwest::Client,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+    /// 剩余的续写次数
+    pub retries: u32,
+}
+
+struct ChatStream {
+    upstream: BoxStream<'static, reqwest::Result<Bytes>>,
+    parser: SseParser,
+    meta: ChunkMeta,
+    guard: CancelGuard,
+    request_id: String,
+    restorer: Option<StreamRestorer>,
+    emulator: Option<StreamEmulator>,
+    /// 上游不是默认上游时把数据块转换为统一格式
+    normalize: bool,
+    continuation: Option<Continuation>,
+    /// 上游生成的正文，续写时作为前缀
+    prefix: String,
+    /// 已发给客户端的正文，无法续写时随错误事件返回
+    partial: String,
+    /// 只有单个 choice 的普通正文才能续写，出现思考内容或工具调用后不再续写
+    resumable: bool,
+    /// 发出上游请求的时间，收到首个 token 后置为 `None`
+    started: Option<Instant>,
+    /// 等待上游首个数据块期间发送保活注释，收到数据后置为 `None`
+    keepalive: Option<tokio::time::Interval>,
+    /// 是否已收到上游的 `data: [DONE]`
+    upstream_done: bool,
+    done: bool,
+}
+
+/// 数据块的 delta 中是否包含正文或思考内容
+fn has_token(chunk: &ChatCompletionResponse) -> bool {
+    chunk.choices().iter().any(|choice| {
+        choice.delta.as_ref().is_some_and(|delta| {
+            [&delta.content, &delta.reasoning_content]
+                .into_iter()
+                .any(|text| text.as_deref().is_some_and(|text| !text.is_empty()))
+        })
+    })
+}
+
+/// 数据块中的正文，以及是否只含单个 choice 的普通正文
+fn content_delta(chunk: &ChatCompletionResponse) -> (String, bool) {
+    let mut content = String::new();
+    let mut plain = true;
+    for choice in chunk.choices() {
+        if choice.index != 0 {
+            plain = false;
+        }
+        let Some(delta) = &choice.delta else {
+            continue;
+        };
+        let reasoning = delta
+            .reasoning_content
+            .as_deref()
+            .is_some_and(|text| !text.is_empty());
+        if reasoning || delta.tool_calls.is_some() {
+            plain = false;
+        }
+        if let Some(text) = &delta.content {
+            content.push_str(text);
+        }
+    }
+    (content, plain)
+}
+
+impl ChatStream {
+    /// 上游结束时补发模拟工具调用暂存的正文
+    fn flush_emulator(&mut self) -> Option<Bytes> {
+        let choices = self.emulator.as_mut()?.finish();
+        if choices.is_empty() {
+            return None;
+        }
+        for choice in &choices {
+            if let Some(content) = choice
+                .delta
+                .as_ref()
+                .and_then(|delta| delta.content.as_deref())
+            {
+                self.partial.push_str(content);
+            }
+        }
+        Some(self.meta.choices_chunk(&self.request_id, choices))
+    }
+
+    /// 处理一个完整的上游事件，把要转发的字节追加到 `output`
+    ///
+    /// 统一格式时，上游的错误事件转换为 `{"error": ...}` 后以 `[DONE]` 结束流，
+    /// `message` 以外的具名事件（如 `event: ping`）不转发。
+    fn forward_event(&mut self, event: SseEvent, output: &mut Vec<u8>) {
+        if self.normalize {
+            if let Some(error) = event
+                .data
+                .as_deref()
+                .and_then(|data| normalize::stream_error(event.event.as_deref(), data))
+            {
+                self.done = true;
+                tracing::warn!("请求 {} 上游返回错误事件: {}", self.request_id, error);
+                if let Some(flushed) = self.flush_emulator() {
+                    output.extend_from_slice(&flushed);
+                }
+                output.extend_from_slice(&self.meta.upstream_error_event(&self.request_id, error));
+                return;
+            }
+            if event.event.as_deref().is_some_and(|name| name != "message") {
+                return;
+            }
+        }
+        if event.data.as_deref() == Some("[DONE]") {
+            self.upstream_done = true;
+            if let Some(flushed) = self.flush_emulator() {
+                output.extend_from_slice(&flushed);
+            }
+        }
+        let chunk = event
+            .data
+            .as_deref()
+            .and_then(|data| ChatCompletionResponse::parse(data.as_bytes()));
+        if let Some(mut chunk) = chunk {
+            let normalized = self.normalize && normalize::normalize_chunk(&mut chunk);
+            self.meta.observe(&chunk);
+            if let Some(started) = self.started
+                && has_token(&chunk)
+            {
+                self.started = None;
+                let model = self.meta.model.clone().unwrap_or_default();
+                metrics::histogram!(CHAT_TIME_TO_FIRST_TOKEN, "model" => model)
+                    .record(started.elapsed().as_secs_f64());
+            }
+            let (content, plain) = content_delta(&chunk);
+            self.prefix.push_str(&content);
+            self.resumable &= plain;
+            let rewrite = normalized || self.restorer.is_some() || self.emulator.is_some();
+            if let Some(restorer) = self.restorer.as_mut() {
+                restorer.restore(&mut chunk);
+            }
+            if let Some(emulator) = self.emulator.as_mut() {
+                emulator.process(&mut chunk);
+            }
+            if rewrite && let Some(rewritten) = chunk.to_json() {
+                self.partial.push_str(&content_delta(&chunk).0);
+                output.extend_from_slice(format!("data: {}\n\n", rewritten).as_bytes());
+                return;
+            }
+            self.partial.push_str(&content);
+        }
+        output.extend_from_slice(&event.raw);
+    }
+
+    /// 以已生成的正文为前缀重新请求上游，成功时返回新的上游流
+    async fn resume_upstream(&mut self) -> Option<BoxStream<'static, reqwest::Result<Bytes>>> {
+        if !self.resumable {
+            return None;
+        }
+        let continuation = self
+            .continuation
+            .as_mut()
+            .filter(|continuation| continuation.retries > 0)?;
+        continuation.retries -= 1;
+
+        let mut request = ChatCompletionRequest::parse(&continuation.body)?;
+        if !self.prefix.is_empty() {
+            let mut message = Message::new("assistant", self.prefix.clone());
+            message
+                .extra
+                .insert("prefix".to_string(), Value::Bool(true));
+            request.messages.push(message);
+        }
+        tracing::warn!(
+            "请求 {} 上游响应中断，以已生成的 {} 字节正文续写",
+            self.request_id,
+            self.prefix.len()
+        );
+        let response = continuation
+            .client
+            .post(UPSTREAM_PREFIX_COMPLETIONS_URL)
+            .headers(continuation.headers.clone())
+            .json(&request)
+            .send()
+            .await;
+        match response {
+            Ok(response) if response.status().is_success() => Some(response.bytes_stream().boxed()),
+            Ok(response) => {
+                tracing::warn!("请求 {} 续写失败: {}", self.request_id, response.status());
+                None
+            }
+            Err(e) => {
+                tracing::warn!("请求 {} 续写失败: {}", self.request_id, e);
+                None
+            }
+        }
+    }
+}
+
+/// 包装上游的 SSE 响应流
+///
+/// 按完整事件转发（不完整的事件暂存到下一次读取），这样被取消时可以在事件边界上
+/// 追加一个 `finish_reason: "cancelled"` 的数据块并结束流；丢弃上游流即关闭上游连接，停止生成。
+/// 启用可还原脱敏时，数据块中的占位符在这里还原为原文；模拟工具调用时在这里把代码块改写为 `tool_calls`；
+/// `normalize` 为 `true` 时先把其他上游的数据块转换为统一格式，并统一错误事件与 `[DONE]` 结尾。
+/// 上游中途断开时，可续写则接上续写的输出，否则以带已发出正文的错误事件结束，而不是直接截断。
+/// 思考模型可能很久才返回首个数据块，期间按 `keepalive` 间隔发送 `: ping` 注释，避免中间代理断开空闲连接。
+#[allow(clippy::too_many_arguments)]
+pub fn chat_stream(
+    upstream: impl Stream<Item = reqwest::Result<Bytes>> + Send + 'static,
+    guard: CancelGuard,
+    request_id: String,
+    restorer: Option<StreamRestorer>,
+    emulator: Option<StreamEmulator>,
+    normalize: bool,
+    continuation: Option<Continuation>,
+    started: Instant,
+    keepalive: Option<Duration>,
+) -> impl Stream<Item = reqwest::Result<Bytes>> {
+    let state = ChatStream {
+        upstream: upstream.boxed(),
+        parser: SseParser::default(),
+        meta: ChunkMeta::default(),
+        guard,
+        request_id,
+        restorer,
+        emulator,
+        normalize,
+        continuation,
+        prefix: String::new(),
+        partial: String::new(),
+        resumable: true,
+        started: Some(started),
+        keepalive: keepalive
+            .map(|period| tokio::time::interval_at(tokio::time::Instant::now() + period, period)),
+        upstream_done: false,
+        done: false,
+    };
+
+    futures::stream::unfold(state, |mut state| async move {
+        if state.done {
+            return None;
+        }
+
+        tokio::select! {
+            biased;
+            _ = state.guard.token.cancelled() => {
+                state.done = true;
+                tracing::info!("请求 {} 已被取消，停止上游生成", state.request_id);
+                let chunk = state.meta.cancelled_chunk(&state.request_id);
+                Some((Ok(chunk), state))
+            }
+            _ = async {
+                match state.keepalive.as_mut() {
+                    Some(keepalive) => keepalive.tick().await,
+                    None => std::future::pending().await,
+                }
+            } => {
+                Some((Ok(Bytes::from_static(b": ping\n\n")), state))
+            }
+            chunk = state.upstream.next() => match chunk {
+                Some(Ok(bytes)) => {
+                    state.keepalive = None;
+                    let mut output = Vec::with_capacity(bytes.len());
+                    for event in state.parser.push(&bytes) {
+                        state.forward_event(event, &mut output);
+                        if state.done {
+                            break;
+                        }
+                    }
+                    Some((Ok(Bytes::from(output)), state))
+                }
+                Some(Err(e)) => {
+                    // 未转发的不完整事件随旧连接一起丢弃
+                    state.parser = SseParser::default();
+                    if let Some(upstream) = state.resume_upstream().await {
+                        state.upstream = upstream;
+                        return Some((Ok(Bytes::new()), state));
+                    }
+                    state.done = true;
+                    tracing::warn!("请求 {} 上游响应中断: {}", state.request_id, e);
+                    let event = state.meta.error_event(&state.request_id, &e.to_string(), &state.partial);
+                    Some((Ok(event), state))
+                }
+                None => {
+                    if state.normalize {
+                        // 统一格式：末尾缺少空行的事件照常处理，上游没有发送 `[DONE]` 时补上
+                        let mut output = Vec::new();
+                        if let Some(event) = state.parser.finish_event() {
+                            state.forward_event(event, &mut output);
+                        }
+                        // 转换后的错误事件已带 `[DONE]`
+                        if !state.done && !state.upstream_done {
+                            if let Some(flushed) = state.flush_emulator() {
+                                output.extend_from_slice(&flushed);
+                            }
+                            output.extend_from_slice(b"data: [DONE]\n\n");
+                        }
+                        state.done = true;
+                        return Some((Ok(Bytes::from(output)), state));
+                    }
+                    state.done = true;
+                    let rest = state.parser.finish().unwrap_or_default();
+                    let rest = match state.flush_emulator() {
+                        Some(flushed) => [flushed, rest].concat().into(),
+                        None => rest,
+                    };
+                    Some((Ok(rest), state))
+                }
+            },
+        }
+    })
+    .filter(|chunk| futures::future::ready(!matches!(chunk, Ok(bytes) if bytes.is_empty())))
+}
@@ -0,0 +1,165 @@
+use clap::{Parser, Subcommand};
+
+use crate::{
+    abuse, concurrency, context, cors, experiments,
+    health::{self, UPSTREAM_MODELS_URL},
+    http_client, idempotency, ip_acl, jwt, keys, load_shed, models, overrides, pipeline, plugins,
+    pricing, queue, quota, redaction, resume, retry, scheduler, scripts, tls,
+};
+
+#[derive(Parser)]
+#[command(version, about = "DeepSeek API 代理服务")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// 启动 HTTP 服务（默认）
+    Serve,
+    /// 校验环境变量配置后退出
+    CheckConfig,
+    /// 使用配置的密钥请求上游，验证密钥是否有效
+    TestUpstream,
+}
+
+/// 逐项解析配置并输出结果，全部有效时返回 `true`
+pub fn check_config() -> bool {
+    let checks: [(&str, anyhow::Result<()>); 29] = [
+        (
+            "DEEPSEEK_API_KEY",
+            std::env::var("DEEPSEEK_API_KEY")
+                .map(drop)
+                .map_err(|_| anyhow::anyhow!("未设置")),
+        ),
+        (
+            "CONCURRENCY_LIMITS",
+            concurrency::ConcurrencyLimits::from_env().map(drop),
+        ),
+        (
+            "上游连接",
+            http_client::HttpClientConfig::from_env().map(drop),
+        ),
+        ("连接预热", http_client::PoolWarmup::from_env().map(drop)),
+        (
+            "REQUEST_STAGES",
+            pipeline::RequestPipeline::from_env().map(drop),
+        ),
+        ("上下文压缩", context::ContextConfig::from_env().map(drop)),
+        ("CORS", cors::CorsConfig::from_env().map(drop)),
+        (
+            "EXPERIMENTS",
+            experiments::Experiments::from_env().map(drop),
+        ),
+        (
+            "幂等键",
+            idempotency::IdempotencyStore::from_env().map(drop),
+        ),
+        ("网络访问控制", ip_acl::IpAcl::from_env().map(drop)),
+        ("CLIENT_KEYS_FILE", keys::ClientKeys::from_env().map(drop)),
+        ("OIDC", jwt::JwtAuth::from_env().map(drop)),
+        ("滥用检测", abuse::AbuseDetector::from_env().map(drop)),
+        (
+            "上游覆盖",
+            overrides::UpstreamOverrides::from_env().map(drop),
+        ),
+        ("模型目录", models::ModelCatalog::from_env().map(drop)),
+        ("上游重试", retry::UpstreamRetry::from_env().map(drop)),
+        ("PRICE_TABLE", pricing::PriceTable::from_env().map(drop)),
+        ("token 配额", quota::TokenQuota::from_env().map(drop)),
+        ("WASM_PLUGINS", plugins::Plugins::from_env().map(drop)),
+        ("ROUTE_SCRIPTS", scripts::RouteScripts::from_env().map(drop)),
+        ("流式续传", resume::ResumeStore::from_env().map(drop)),
+        (
+            "PII_REDACTION",
+            redaction::RedactionMode::from_env().map(drop),
+        ),
+        ("降载阈值", load_shed::LoadShedder::from_env().map(drop)),
+        ("上游调度", scheduler::FairScheduler::from_env().map(drop)),
+        (
+            "HEALTH_PROBE_INTERVAL_SECS",
+            health::ProviderHealth::from_env().map(drop),
+        ),
+        ("NATS 队列", queue::QueueConfig::from_env().map(drop)),
+        (
+            "RESPONSE_LOG_MAX_BYTES",
+            std::env::var("RESPONSE_LOG_MAX_BYTES")
+                .ok()
+                .map_or(Ok(()), |value| {
+                    value.parse::<usize>().map(drop).map_err(Into::into)
+                }),
+        ),
+        (
+            "STREAM_ERROR_RETRIES",
+            std::env::var("STREAM_ERROR_RETRIES")
+                .ok()
+                .map_or(Ok(()), |value| {
+                    value.parse::<u32>().map(drop).map_err(Into::into)
+                }),
+        ),
+        (
+            "SSE_KEEPALIVE_SECS",
+            std::env::var("SSE_KEEPALIVE_SECS")
+                .ok()
+                .map_or(Ok(()), |value| {
+                    value.parse::<u64>().map(drop).map_err(Into::into)
+                }),
+        ),
+    ];
+
+    let mut ok = true;
+    for (name, result) in checks {
+        match result {
+            Ok(()) => println!("✅ {}", name),
+            Err(e) => {
+                ok = false;
+                println!("❌ {}: {}", name, e);
+            }
+        }
+    }
+    // TLS 证书只检查路径是否成对配置，加载失败会在启动时报告
+    match tls::TlsPaths::from_env() {
+        Some(_) => println!("✅ TLS 证书已配置"),
+        None => println!("ℹ️ 未配置 TLS 证书，使用明文 HTTP"),
+    }
+    ok
+}
+
+/// 使用配置的密钥请求上游模型列表，密钥有效时返回 `true`
+pub async fn test_upstream() -> bool {
+    let Ok(api_key) = std::env::var("DEEPSEEK_API_KEY") else {
+        println!("❌ DeepSeek: 未设置 DEEPSEEK_API_KEY");
+        return false;
+    };
+    let client = match http_client::HttpClientConfig::from_env()
+        .and_then(http_client::HttpClientConfig::build)
+    {
+        Ok(client) => client,
+        Err(e) => {
+            println!("❌ 上游连接配置无效: {}", e);
+            return false;
+        }
+    };
+    let response = client
+        .get(UPSTREAM_MODELS_URL)
+        .bearer_auth(api_key)
+        .send()
+        .await;
+    match response {
+        Ok(response) if response.status().is_success() => {
+            println!("✅ DeepSeek: {}", response.status());
+            true
+        }
+        Ok(response) => {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            println!("❌ DeepSeek: {} {}", status, body);
+            false
+        }
+        Err(e) => {
+            println!("❌ DeepSeek: {}", e);
+            false
+        }
+    }
+}
@@ -0,0 +1,76 @@
+use std::{collections::HashMap, sync::Arc};
+
+use axum::{
+    body::Body,
+    extract::{MatchedPath, Request, State},
+    http::{HeaderValue, StatusCode, header::RETRY_AFTER},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use futures::StreamExt;
+use tokio::sync::Semaphore;
+
+use crate::AppState;
+
+/// 按路由划分的上游并发限制
+///
+/// 从 `CONCURRENCY_LIMITS` 环境变量读取，格式为 `路由=并发数`，多个以逗号分隔，
+/// 例如 `/chat/completions=64,/translate=16`。未配置的路由不限制并发。
+#[derive(Default)]
+pub struct ConcurrencyLimits {
+    semaphores: HashMap<String, Arc<Semaphore>>,
+}
+
+impl ConcurrencyLimits {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let Ok(config) = std::env::var("CONCURRENCY_LIMITS") else {
+            return Ok(Self::default());
+        };
+
+        let mut semaphores = HashMap::new();
+        for entry in config.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            let (route, limit) = entry
+                .rsplit_once('=')
+                .ok_or_else(|| anyhow::anyhow!("CONCURRENCY_LIMITS 格式错误: {}", entry))?;
+            let limit: usize = limit.trim().parse()?;
+            semaphores.insert(route.trim().to_string(), Arc::new(Semaphore::new(limit)));
+        }
+        Ok(Self { semaphores })
+    }
+
+    pub fn get(&self, route: &str) -> Option<Arc<Semaphore>> {
+        self.semaphores.get(route).cloned()
+    }
+}
+
+/// 并发限制中间件：路由已满时返回 429，许可一直持有到响应体（含流式响应）传输结束
+pub async fn limit_concurrency(
+    State(state): State<AppState>,
+    matched_path: MatchedPath,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(semaphore) = state.concurrency_limits.get(matched_path.as_str()) else {
+        return next.run(request).await;
+    };
+
+    let Ok(permit) = semaphore.try_acquire_owned() else {
+        tracing::warn!("路由 {} 并发已满，拒绝请求", matched_path.as_str());
+        let mut response = (
+            StatusCode::TOO_MANY_REQUESTS,
+            "capacity exceeded, retry later",
+        )
+            .into_response();
+        response
+            .headers_mut()
+            .insert(RETRY_AFTER, HeaderValue::from_static("1"));
+        return response;
+    };
+
+    let (parts, body) = next.run(request).await.into_parts();
+    let stream = body.into_data_stream().map(move |chunk| {
+        let _permit = &permit;
+        chunk
+    });
+    Response::from_parts(parts, Body::from_stream(stream))
+}
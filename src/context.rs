@@ -0,0 +1,260 @@
+use std::{collections::HashMap, str::FromStr};
+
+use axum::{
+    body::Bytes,
+    http::{HeaderMap, HeaderValue, StatusCode, header::AUTHORIZATION},
+};
+use serde_json::{Value, json};
+
+use crate::{
+    AppState,
+    chat::{ChatCompletionRequest, Message},
+};
+
+/// 按请求选择压缩策略的请求头
+pub const CONTEXT_COMPRESSION_HEADER: &str = "x-context-compression";
+
+/// 未配置时模型的上下文窗口（token）
+const DEFAULT_CONTEXT_WINDOW: usize = 131_072;
+
+/// 请求未指定 `max_tokens` 时为输出预留的 token 数
+const DEFAULT_RESERVED_OUTPUT_TOKENS: usize = 8_192;
+
+/// 为摘要消息预留的 token 数
+const SUMMARY_RESERVED_TOKENS: usize = 2_048;
+
+const SUMMARY_PROMPT: &str = "请将以下对话历史概括为一段简洁的摘要，保留关键事实、用户偏好、已得出的结论和尚未解决的问题。只输出摘要内容。";
+
+/// 上下文超出窗口时的压缩策略
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CompressionStrategy {
+    /// 不处理，原样转发
+    None,
+    /// 丢弃较早的对话轮次
+    Truncate,
+    /// 用廉价模型概括较早的对话轮次，保留最近的轮次原文
+    Summarize,
+}
+
+impl FromStr for CompressionStrategy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "none" => Ok(Self::None),
+            "truncate" => Ok(Self::Truncate),
+            "summarize" => Ok(Self::Summarize),
+            other => Err(anyhow::anyhow!("未知的上下文压缩策略: {}", other)),
+        }
+    }
+}
+
+/// 上下文压缩配置
+pub struct ContextConfig {
+    /// 默认策略，读取 `CONTEXT_COMPRESSION`，默认 `none`
+    pub default_strategy: CompressionStrategy,
+    /// 各模型的上下文窗口，读取 `MODEL_CONTEXT_WINDOWS`，格式为 `模型=token 数`，逗号分隔
+    pub context_windows: HashMap<String, usize>,
+    /// 生成摘要使用的模型，读取 `CONTEXT_SUMMARY_MODEL`，默认 `deepseek-chat`
+    pub summary_model: String,
+}
+
+impl ContextConfig {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let default_strategy = match std::env::var("CONTEXT_COMPRESSION") {
+            Ok(value) => value.parse()?,
+            Err(_) => CompressionStrategy::None,
+        };
+
+        let mut context_windows = HashMap::new();
+        if let Ok(config) = std::env::var("MODEL_CONTEXT_WINDOWS") {
+            for entry in config.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+                let (model, window) = entry
+                    .rsplit_once('=')
+                    .ok_or_else(|| anyhow::anyhow!("MODEL_CONTEXT_WINDOWS 格式错误: {}", entry))?;
+                context_windows.insert(model.trim().to_string(), window.trim().parse()?);
+            }
+        }
+
+        let summary_model =
+            std::env::var("CONTEXT_SUMMARY_MODEL").unwrap_or_else(|_| "deepseek-chat".to_string());
+
+        Ok(Self {
+            default_strategy,
+            context_windows,
+            summary_model,
+        })
+    }
+
+    /// 请求头中指定的策略优先于默认策略
+    pub fn strategy_for(
+        &self,
+        headers: &HeaderMap,
+    ) -> Result<CompressionStrategy, (StatusCode, String)> {
+        match headers.get(CONTEXT_COMPRESSION_HEADER) {
+            Some(value) => value
+                .to_str()
+                .map_err(|e| e.to_string())
+                .and_then(|value| value.parse().map_err(|e: anyhow::Error| e.to_string()))
+                .map_err(|e| (StatusCode::BAD_REQUEST, e)),
+            None => Ok(self.default_strategy),
+        }
+    }
+
+    pub fn context_window(&self, model: &str) -> usize {
+        self.context_windows
+            .get(model)
+            .copied()
+            .unwrap_or(DEFAULT_CONTEXT_WINDOW)
+    }
+}
+
+/// 粗略估算 token 数：ASCII 约 4 个字符一个 token，其余字符（中文等）约一个字符一个 token
+fn estimate_tokens(text: &str) -> usize {
+    let (ascii, other) = text.chars().fold((0, 0), |(ascii, other), c| {
+        if c.is_ascii() {
+            (ascii + 1, other)
+        } else {
+            (ascii, other + 1)
+        }
+    });
+    ascii / 4 + other + 1
+}
+
+pub fn estimate_message_tokens(message: &Message) -> usize {
+    estimate_tokens(&serde_json::to_string(message).unwrap_or_default()) + 4
+}
+
+/// 上下文超出模型窗口时按策略压缩消息列表，未超出或无法解析时原样返回请求体
+///
+/// 摘要请求与对话请求发往同一上游并使用同一密钥，覆盖了上游的请求不会把密钥发往默认上游。
+pub async fn compress_request(
+    state: &AppState,
+    upstream_url: &str,
+    authorization: &HeaderValue,
+    strategy: CompressionStrategy,
+    body: Bytes,
+) -> Result<Bytes, (StatusCode, String)> {
+    let config = &state.context;
+    let Some(mut request) = ChatCompletionRequest::parse(&body) else {
+        return Ok(body);
+    };
+    let reserved_output = request
+        .max_output_tokens()
+        .map(|tokens| tokens as usize)
+        .unwrap_or(DEFAULT_RESERVED_OUTPUT_TOKENS);
+    let budget = config
+        .context_window(&request.model)
+        .saturating_sub(reserved_output);
+    let messages = &mut request.messages;
+    let total: usize = messages.iter().map(estimate_message_tokens).sum();
+    if total <= budget {
+        return Ok(body);
+    }
+
+    // 开头的系统消息始终保留
+    let system_end = messages
+        .iter()
+        .position(|message| message.role != "system")
+        .unwrap_or(messages.len());
+    let system_tokens: usize = messages[..system_end]
+        .iter()
+        .map(estimate_message_tokens)
+        .sum();
+
+    // 从末尾向前保留尽可能多的原文轮次
+    let mut recent_budget = budget
+        .saturating_sub(system_tokens)
+        .saturating_sub(SUMMARY_RESERVED_TOKENS);
+    let mut split = messages.len();
+    while split > system_end {
+        let tokens = estimate_message_tokens(&messages[split - 1]);
+        if tokens > recent_budget {
+            break;
+        }
+        recent_budget -= tokens;
+        split -= 1;
+    }
+
+    // 保留部分从用户消息开始，避免拆开工具调用与其结果
+    while split < messages.len() && messages[split].role != "user" {
+        split += 1;
+    }
+    if split == messages.len() {
+        // 最后一轮本身就超出预算时至少保留最后一条用户消息
+        split = messages
+            .iter()
+            .rposition(|message| message.role == "user")
+            .unwrap_or(messages.len() - 1)
+            .max(system_end);
+    }
+    if split <= system_end {
+        return Ok(body);
+    }
+
+    let older: Vec<Message> = messages.drain(system_end..split).collect();
+    tracing::info!(
+        "上下文约 {} tokens 超出预算 {}，按 {:?} 策略压缩 {} 条较早的消息",
+        total,
+        budget,
+        strategy,
+        older.len()
+    );
+
+    if strategy == CompressionStrategy::Summarize {
+        let summary = summarize(state, upstream_url, authorization, &older).await?;
+        messages.insert(
+            system_end,
+            Message::new("system", format!("以下是此前对话的摘要：\n{}", summary)),
+        );
+    }
+
+    request.to_bytes()
+}
+
+/// 调用摘要模型概括较早的消息
+async fn summarize(
+    state: &AppState,
+    upstream_url: &str,
+    authorization: &HeaderValue,
+    messages: &[Message],
+) -> Result<String, (StatusCode, String)> {
+    let transcript = messages
+        .iter()
+        .map(|message| format!("{}: {}", message.role, message.text()))
+        .collect::<Vec<_>>()
+        .join("\n\n");
+
+    let response = state
+        .http_client
+        .post(upstream_url)
+        .header(AUTHORIZATION, authorization)
+        .json(&json!({
+            "model": state.context.summary_model,
+            "messages": [
+                { "role": "system", "content": SUMMARY_PROMPT },
+                { "role": "user", "content": transcript },
+            ],
+        }))
+        .send()
+        .await
+        .map_err(|e| (StatusCode::BAD_GATEWAY, e.to_string()))?;
+
+    let status = response.status();
+    let value: Value = response
+        .json()
+        .await
+        .map_err(|e| (StatusCode::BAD_GATEWAY, e.to_string()))?;
+    if !status.is_success() {
+        return Err((
+            StatusCode::BAD_GATEWAY,
+            format!("生成上下文摘要失败: {}", value),
+        ));
+    }
+
+    value
+        .pointer("/choices/0/message/content")
+        .and_then(Value::as_str)
+        .map(str::to_string)
+        .ok_or_else(|| (StatusCode::BAD_GATEWAY, "摘要模型未返回内容".to_string()))
+}
@@ -0,0 +1,81 @@
+use std::time::Duration;
+
+use axum::http::{HeaderName, HeaderValue};
+use tower_http::cors::{AllowHeaders, AllowMethods, AllowOrigin, CorsLayer};
+
+use crate::{
+    abuse::ABUSE_SIGNALS_HEADER, cancel::COMPLETION_ID_HEADER, diagnostics,
+    experiments::EXPERIMENT_HEADER, idempotency::IDEMPOTENT_REPLAYED_HEADER,
+    logging::REQUEST_ID_HEADER, quota::QUOTA_WARNING_HEADER,
+};
+
+/// 预检结果的默认缓存时间
+const DEFAULT_MAX_AGE_SECS: u64 = 86400;
+
+/// CORS 配置
+///
+/// 读取 `CORS_ALLOWED_ORIGINS`（逗号分隔，未配置时允许任意来源）、`CORS_MAX_AGE_SECS`（预检结果缓存秒数）
+/// 与 `CORS_ALLOW_PRIVATE_NETWORK`（为 `true` 时响应 Private Network Access 预检，允许公网页面访问局域网部署）。
+/// 预检请求的方法与请求头原样回显，`*` 不涵盖 `Authorization`，回显才能让浏览器携带密钥。
+pub struct CorsConfig {
+    /// 允许的来源，`None` 表示任意来源
+    origins: Option<Vec<HeaderValue>>,
+    max_age: Duration,
+    private_network: bool,
+}
+
+impl CorsConfig {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let origins = match std::env::var("CORS_ALLOWED_ORIGINS") {
+            Ok(config) if config.trim() != "*" => Some(
+                config
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|s| !s.is_empty())
+                    .map(HeaderValue::from_str)
+                    .collect::<Result<_, _>>()?,
+            ),
+            _ => None,
+        };
+        let max_age = match std::env::var("CORS_MAX_AGE_SECS") {
+            Ok(value) => value.parse()?,
+            Err(_) => DEFAULT_MAX_AGE_SECS,
+        };
+        let private_network = std::env::var("CORS_ALLOW_PRIVATE_NETWORK")
+            .map(|value| value == "true")
+            .unwrap_or(false);
+        Ok(Self {
+            origins,
+            max_age: Duration::from_secs(max_age),
+            private_network,
+        })
+    }
+
+    pub fn layer(&self) -> CorsLayer {
+        let origin = match &self.origins {
+            Some(origins) => AllowOrigin::list(origins.iter().cloned()),
+            None => AllowOrigin::any(),
+        };
+        // 代理自身添加的响应头，浏览器默认不允许脚本读取
+        let expose = [
+            REQUEST_ID_HEADER,
+            COMPLETION_ID_HEADER,
+            EXPERIMENT_HEADER,
+            IDEMPOTENT_REPLAYED_HEADER,
+            ABUSE_SIGNALS_HEADER,
+            QUOTA_WARNING_HEADER,
+        ]
+        .into_iter()
+        .chain(diagnostics::DIAGNOSTIC_HEADERS)
+        .map(HeaderName::from_static)
+        .collect::<Vec<_>>();
+
+        CorsLayer::new()
+            .allow_origin(origin)
+            .allow_methods(AllowMethods::mirror_request())
+            .allow_headers(AllowHeaders::mirror_request())
+            .expose_headers(expose)
+            .max_age(self.max_age)
+            .allow_private_network(self.private_network)
+    }
+}
@@ -0,0 +1,63 @@
+use std::time::Duration;
+
+use axum::http::{HeaderMap, HeaderValue};
+
+use crate::chat::ChatCompletionResponse;
+
+/// 上游实际使用的模型
+pub const UPSTREAM_MODEL_HEADER: &str = "x-upstream-model";
+
+/// 从发出请求到收到上游响应头的耗时（毫秒）
+pub const UPSTREAM_LATENCY_HEADER: &str = "x-upstream-latency-ms";
+
+/// 输入 token 数
+pub const TOKENS_PROMPT_HEADER: &str = "x-tokens-prompt";
+
+/// 输出 token 数
+pub const TOKENS_COMPLETION_HEADER: &str = "x-tokens-completion";
+
+/// 是否命中上游上下文缓存，`HIT` 或 `MISS`
+pub const CACHE_HEADER: &str = "x-cache";
+
+/// 代理添加的全部诊断头
+pub const DIAGNOSTIC_HEADERS: [&str; 5] = [
+    UPSTREAM_MODEL_HEADER,
+    UPSTREAM_LATENCY_HEADER,
+    TOKENS_PROMPT_HEADER,
+    TOKENS_COMPLETION_HEADER,
+    CACHE_HEADER,
+];
+
+/// 写入上游响应耗时
+pub fn insert_latency(headers: &mut HeaderMap, latency: Duration) {
+    headers.insert(
+        UPSTREAM_LATENCY_HEADER,
+        HeaderValue::from(latency.as_millis() as u64),
+    );
+}
+
+/// 从非流式响应体中取出模型与用量写入响应头，响应体不是对话结果时不写入
+pub fn insert_usage(headers: &mut HeaderMap, body: &[u8]) {
+    let Some(response) = ChatCompletionResponse::parse(body) else {
+        return;
+    };
+    if let Some(value) = response
+        .model
+        .and_then(|model| HeaderValue::from_str(&model).ok())
+    {
+        headers.insert(UPSTREAM_MODEL_HEADER, value);
+    }
+    let Some(usage) = response.usage else {
+        return;
+    };
+    if let Some(prompt) = usage.prompt_tokens {
+        headers.insert(TOKENS_PROMPT_HEADER, HeaderValue::from(prompt));
+    }
+    if let Some(completion) = usage.completion_tokens {
+        headers.insert(TOKENS_COMPLETION_HEADER, HeaderValue::from(completion));
+    }
+    if let Some(cache_hit) = usage.prompt_cache_hit_tokens {
+        let cache = if cache_hit > 0 { "HIT" } else { "MISS" };
+        headers.insert(CACHE_HEADER, HeaderValue::from_static(cache));
+    }
+}
@@ -0,0 +1,133 @@
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+
+use crate::metrics::DNS_RESOLUTION_SECONDS;
+
+/// DNS 缓存的查询数，标签 `result`（`hit` / `negative_hit` / `miss` / `stale`）
+const DNS_CACHE_LOOKUPS_TOTAL: &str = "dns_cache_lookups_total";
+
+/// 解析结果的默认缓存时间
+const DEFAULT_CACHE_TTL_SECS: u64 = 60;
+
+/// 解析失败的默认缓存时间
+const DEFAULT_NEGATIVE_TTL_SECS: u64 = 5;
+
+/// 缓存的解析结果
+struct Entry {
+    expires: Instant,
+    result: Result<Vec<SocketAddr>, String>,
+}
+
+/// 带缓存的上游域名解析
+///
+/// 解析成功的结果缓存 `DNS_CACHE_TTL_SECS` 秒（默认 60，设为 0 关闭缓存），解析失败缓存
+/// `DNS_NEGATIVE_TTL_SECS` 秒（默认 5），避免故障期间每个请求都等待解析超时。缓存过期后重新解析失败时
+/// 沿用上一次成功的结果，DNS 的短暂故障不会直接导致上游请求失败。返回的 IPv4 与 IPv6 地址由连接器按
+/// Happy Eyeballs 交替尝试。
+pub struct CachingResolver {
+    ttl: Duration,
+    negative_ttl: Duration,
+    cache: Arc<Mutex<HashMap<String, Entry>>>,
+}
+
+impl CachingResolver {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let ttl = match std::env::var("DNS_CACHE_TTL_SECS") {
+            Ok(value) => value.parse()?,
+            Err(_) => DEFAULT_CACHE_TTL_SECS,
+        };
+        let negative_ttl = match std::env::var("DNS_NEGATIVE_TTL_SECS") {
+            Ok(value) => value.parse()?,
+            Err(_) => DEFAULT_NEGATIVE_TTL_SECS,
+        };
+        Ok(Self {
+            ttl: Duration::from_secs(ttl),
+            negative_ttl: Duration::from_secs(negative_ttl),
+            cache: Arc::default(),
+        })
+    }
+}
+
+impl Resolve for CachingResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let host = name.as_str().to_string();
+        let ttl = self.ttl;
+        let negative_ttl = self.negative_ttl;
+        let cache = self.cache.clone();
+        Box::pin(async move {
+            let cached =
+                cache.lock().unwrap().get(&host).and_then(|entry| {
+                    (entry.expires > Instant::now()).then(|| entry.result.clone())
+                });
+            if let Some(result) = cached {
+                let label = if result.is_ok() {
+                    "hit"
+                } else {
+                    "negative_hit"
+                };
+                metrics::counter!(DNS_CACHE_LOOKUPS_TOTAL, "result" => label).increment(1);
+                return result.map(into_addrs).map_err(Into::into);
+            }
+
+            let started = Instant::now();
+            let resolved = tokio::net::lookup_host((host.as_str(), 0))
+                .await
+                .map(|addrs| addrs.collect::<Vec<_>>())
+                .map_err(|e| e.to_string());
+            let outcome = if resolved.is_ok() { "ok" } else { "error" };
+            metrics::histogram!(DNS_RESOLUTION_SECONDS, "result" => outcome)
+                .record(started.elapsed().as_secs_f64());
+
+            let mut cache = cache.lock().unwrap();
+            let result = match resolved {
+                Ok(addrs) => {
+                    metrics::counter!(DNS_CACHE_LOOKUPS_TOTAL, "result" => "miss").increment(1);
+                    if !ttl.is_zero() {
+                        cache.insert(
+                            host,
+                            Entry {
+                                expires: Instant::now() + ttl,
+                                result: Ok(addrs.clone()),
+                            },
+                        );
+                    }
+                    Ok(addrs)
+                }
+                Err(e) => match cache.get_mut(&host) {
+                    // 解析失败时沿用过期的成功结果，并按失败缓存时间推迟下次解析
+                    Some(entry) if entry.result.is_ok() => {
+                        tracing::warn!(host, "域名解析失败，沿用过期的解析结果: {}", e);
+                        metrics::counter!(DNS_CACHE_LOOKUPS_TOTAL, "result" => "stale")
+                            .increment(1);
+                        entry.expires = Instant::now() + negative_ttl;
+                        entry.result.clone()
+                    }
+                    _ => {
+                        metrics::counter!(DNS_CACHE_LOOKUPS_TOTAL, "result" => "miss").increment(1);
+                        if !ttl.is_zero() && !negative_ttl.is_zero() {
+                            cache.insert(
+                                host,
+                                Entry {
+                                    expires: Instant::now() + negative_ttl,
+                                    result: Err(e.clone()),
+                                },
+                            );
+                        }
+                        Err(e)
+                    }
+                },
+            };
+            result.map(into_addrs).map_err(Into::into)
+        })
+    }
+}
+
+fn into_addrs(addrs: Vec<SocketAddr>) -> Addrs {
+    Box::new(addrs.into_iter())
+}
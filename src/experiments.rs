@@ -0,0 +1,234 @@
+use std::time::Duration;
+
+use axum::{
+    body::Bytes,
+    http::{HeaderMap, HeaderValue, StatusCode, header::AUTHORIZATION},
+};
+use futures::Stream;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    chat::{ChatCompletionRequest, Content, Message},
+    handlers::chat_completions::client_id,
+    logging::REQUEST_ID_HEADER,
+    pricing::observe_usage,
+};
+
+/// 标记请求所属实验与分组的响应头
+pub const EXPERIMENT_HEADER: &str = "x-experiment";
+
+/// 按实验与分组统计的对话请求数，标签 `experiment`、`variant`、`status`
+const EXPERIMENT_REQUESTS_TOTAL: &str = "experiment_requests_total";
+
+/// 按实验与分组统计的上游响应耗时（到收到响应头为止），标签 `experiment`、`variant`
+const EXPERIMENT_LATENCY_SECONDS: &str = "experiment_latency_seconds";
+
+/// 按实验与分组统计的 token 用量，标签 `experiment`、`variant`、`kind`（`prompt` / `completion`）
+const EXPERIMENT_TOKENS_TOTAL: &str = "experiment_tokens_total";
+
+/// 匿名请求用于分组的用户标识头
+pub const EXPERIMENT_USER_HEADER: &str = "x-user-id";
+
+/// 单个实验：匹配的请求按比例分流到替换了模型或系统提示词的实验组
+#[derive(Deserialize)]
+struct Experiment {
+    name: String,
+    /// 只对该模型的请求生效，未配置时匹配所有请求
+    match_model: Option<String>,
+    /// 进入实验组的百分比（0-100）
+    percent: u8,
+    /// 实验组改用的模型
+    model: Option<String>,
+    /// 实验组改用的系统提示词，替换首条 system 消息，没有时插入到最前
+    system_prompt: Option<String>,
+}
+
+/// 请求的分组结果
+#[derive(Clone)]
+pub struct Assignment {
+    experiment: String,
+    treatment: bool,
+}
+
+/// 灰度与 A/B 实验
+///
+/// 读取 `EXPERIMENTS`（JSON 数组），按顺序取第一个匹配的实验，按 [`bucket_key`] 的哈希分组，
+/// 同一客户端始终落在同一组。对照组原样转发，实验组改写模型或系统提示词。
+#[derive(Default)]
+pub struct Experiments(Vec<Experiment>);
+
+impl Experiments {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let Ok(config) = std::env::var("EXPERIMENTS") else {
+            return Ok(Self::default());
+        };
+        let experiments: Vec<Experiment> = serde_json::from_str(&config)?;
+        for experiment in &experiments {
+            if experiment.percent > 100 {
+                anyhow::bail!("实验 {} 的 percent 不能超过 100", experiment.name);
+            }
+            if experiment.model.is_none() && experiment.system_prompt.is_none() {
+                anyhow::bail!("实验 {} 需要配置 model 或 system_prompt", experiment.name);
+            }
+        }
+        Ok(Self(experiments))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// 为请求分组，命中实验组时返回改写后的请求体
+    ///
+    /// `client` 为分组依据，见 [`bucket_key`]。
+    pub fn assign(
+        &self,
+        client: &str,
+        body: Bytes,
+    ) -> Result<(Bytes, Option<Assignment>), (StatusCode, String)> {
+        let Some(mut request) = ChatCompletionRequest::parse(&body) else {
+            return Ok((body, None));
+        };
+        let Some(experiment) = self
+            .0
+            .iter()
+            .find(|e| e.match_model.as_deref().is_none_or(|m| m == request.model))
+        else {
+            return Ok((body, None));
+        };
+
+        // 按实验名称与客户端哈希，不受客户端可控的请求头影响，重启后分组不变
+        let digest = Sha256::new()
+            .chain_update(experiment.name.as_bytes())
+            .chain_update([0])
+            .chain_update(client.as_bytes())
+            .finalize();
+        let bucket = u64::from_be_bytes(digest[..8].try_into().unwrap()) % 100;
+        let treatment = bucket < u64::from(experiment.percent);
+        let assignment = Assignment {
+            experiment: experiment.name.clone(),
+            treatment,
+        };
+        if !treatment {
+            return Ok((body, Some(assignment)));
+        }
+
+        if let Some(model) = &experiment.model {
+            request.model = model.clone();
+        }
+        if let Some(prompt) = &experiment.system_prompt {
+            match request
+                .messages
+                .iter_mut()
+                .find(|message| message.role == "system")
+            {
+                Some(message) => message.content = Content::Text(prompt.clone()),
+                None => request.messages.insert(0, Message::new("system", prompt)),
+            }
+        }
+        Ok((request.to_bytes()?, Some(assignment)))
+    }
+}
+
+/// 分组依据
+///
+/// 携带凭据的请求按凭据摘要分组；没有凭据时所有请求共用服务端凭据，改按 `x-user-id` 分组，
+/// 也没有时按 `x-request-id` 逐请求分组，避免匿名流量全部落在同一组。
+pub fn bucket_key(headers: &HeaderMap, scope: &str) -> String {
+    if headers.contains_key(AUTHORIZATION) {
+        return client_id(scope);
+    }
+    let header = |name| headers.get(name).and_then(|value| value.to_str().ok());
+    if let Some(user) = header(EXPERIMENT_USER_HEADER).filter(|user| !user.is_empty()) {
+        return format!("user:{user}");
+    }
+    match header(REQUEST_ID_HEADER) {
+        Some(request_id) => format!("request:{request_id}"),
+        None => client_id(scope),
+    }
+}
+
+impl Assignment {
+    fn variant(&self) -> &'static str {
+        if self.treatment {
+            "treatment"
+        } else {
+            "control"
+        }
+    }
+
+    /// 响应头取值，形如 `name=treatment`
+    pub fn header_value(&self) -> Option<HeaderValue> {
+        HeaderValue::from_str(&format!("{}={}", self.experiment, self.variant())).ok()
+    }
+
+    /// 记录上游响应状态与耗时
+    pub fn record(&self, status: StatusCode, latency: Duration) {
+        metrics::counter!(
+            EXPERIMENT_REQUESTS_TOTAL,
+            "experiment" => self.experiment.clone(),
+            "variant" => self.variant(),
+            "status" => status.as_u16().to_string(),
+        )
+        .increment(1);
+        metrics::histogram!(
+            EXPERIMENT_LATENCY_SECONDS,
+            "experiment" => self.experiment.clone(),
+            "variant" => self.variant(),
+        )
+        .record(latency.as_secs_f64());
+    }
+
+    /// 旁路读取响应中的用量，按分组计入 token 数
+    pub fn record_usage<S, E>(
+        self,
+        stream: S,
+        is_event_stream: bool,
+    ) -> impl Stream<Item = Result<Bytes, E>>
+    where
+        S: Stream<Item = Result<Bytes, E>>,
+    {
+        observe_usage(stream, is_event_stream, move |response| {
+            let Some(usage) = &response.usage else {
+                return;
+            };
+            for (kind, tokens) in [
+                ("prompt", usage.prompt_tokens),
+                ("completion", usage.completion_tokens),
+            ] {
+                metrics::counter!(
+                    EXPERIMENT_TOKENS_TOTAL,
+                    "experiment" => self.experiment.clone(),
+                    "variant" => self.variant(),
+                    "kind" => kind,
+                )
+                .increment(tokens.unwrap_or(0));
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buckets_anonymous_requests_by_user_then_request_id() {
+        let scope = "Bearer server-key";
+        let mut headers = HeaderMap::new();
+        headers.insert(REQUEST_ID_HEADER, HeaderValue::from_static("req-1"));
+        assert_eq!(bucket_key(&headers, scope), "request:req-1");
+
+        headers.insert(EXPERIMENT_USER_HEADER, HeaderValue::from_static("alice"));
+        assert_eq!(bucket_key(&headers, scope), "user:alice");
+
+        // 携带凭据时忽略客户端可控的标识头
+        headers.insert(AUTHORIZATION, HeaderValue::from_static("Bearer client"));
+        assert_eq!(
+            bucket_key(&headers, "Bearer client"),
+            client_id("Bearer client")
+        );
+        assert_eq!(bucket_key(&HeaderMap::new(), scope), client_id(scope));
+    }
+}
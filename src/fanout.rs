@@ -0,0 +1,429 @@
+use axum::{
+    body::Bytes,
+    http::{HeaderMap, HeaderValue, Method, StatusCode, header::CONTENT_LENGTH},
+};
+use futures::{StreamExt, future::try_join_all};
+use std::sync::{Arc, Mutex};
+use tokio::sync::OwnedSemaphorePermit;
+
+use serde_json::{Map, Value, json};
+
+use crate::{
+    AppState,
+    chat::{ChatCompletionRequest, ChatCompletionResponse, Usage},
+    retry::{UpstreamBody, UpstreamRetry},
+    scheduler,
+    sse::SseParser,
+    upstream::UpstreamResponse,
+};
+
+/// 单个请求允许扇出的最大并行数
+const MAX_FANOUT_N: u64 = 16;
+
+/// 需要由代理扇出 `n` 的模型列表
+///
+/// 读取 `N_FANOUT_MODELS`，逗号分隔，`*` 表示所有模型。列表中的模型收到 `n > 1` 的请求时，
+/// 代理并行发出 n 个 `n = 1` 的请求并合并为一个 `choices` 数组；其余模型原样转发 `n`。
+pub struct FanoutModels(Vec<String>);
+
+impl FanoutModels {
+    pub fn from_env() -> Self {
+        let models = std::env::var("N_FANOUT_MODELS")
+            .map(|config| {
+                config
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|s| !s.is_empty())
+                    .map(str::to_string)
+                    .collect()
+            })
+            .unwrap_or_default();
+        Self(models)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn contains(&self, model: &str) -> bool {
+        self.0.iter().any(|m| m == "*" || m == model)
+    }
+
+    /// 判断请求是否需要扇出，需要时返回 n 和去掉 `n` 后的单次请求体
+    pub fn plan(&self, body: &Bytes) -> Result<Option<(u64, Bytes)>, (StatusCode, String)> {
+        let Some(mut request) = ChatCompletionRequest::parse(body) else {
+            return Ok(None);
+        };
+        let n = request.n.unwrap_or(1);
+        if n <= 1 || !self.contains(&request.model) {
+            return Ok(None);
+        }
+        if n > MAX_FANOUT_N {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!("n 不能超过 {}", MAX_FANOUT_N),
+            ));
+        }
+
+        request.n = None;
+        Ok(Some((n, request.to_bytes()?)))
+    }
+}
+
+/// 扇出的其余各路占用的并发与调度名额，随合并后的响应体释放
+pub struct LegPermits {
+    _concurrency: Option<OwnedSemaphorePermit>,
+    _scheduler: Vec<scheduler::Permit>,
+}
+
+/// 为扇出的其余 n - 1 路获取路由并发名额与上游调度名额，首路的名额已由中间件获取
+///
+/// 并发名额不足时立即返回 429；调度名额按客户端排队，超时返回 429。
+pub async fn acquire_leg_permits(
+    state: &AppState,
+    route: Option<&str>,
+    client: &str,
+    n: u64,
+) -> Result<LegPermits, (StatusCode, String)> {
+    let legs = n.saturating_sub(1);
+    let concurrency = match route.and_then(|route| state.concurrency_limits.get(route)) {
+        Some(semaphore) => Some(semaphore.try_acquire_many_owned(legs as u32).map_err(|_| {
+            (
+                StatusCode::TOO_MANY_REQUESTS,
+                "capacity exceeded, retry later".to_string(),
+            )
+        })?),
+        None => None,
+    };
+    let scheduler = match &state.scheduler {
+        Some(scheduler) => scheduler.acquire_many(client, legs).await.ok_or((
+            StatusCode::TOO_MANY_REQUESTS,
+            "upstream busy, retry later".to_string(),
+        ))?,
+        None => Vec::new(),
+    };
+    Ok(LegPermits {
+        _concurrency: concurrency,
+        _scheduler: scheduler,
+    })
+}
+
+/// 并行发出 n 个请求并合并响应
+///
+/// 流式响应按到达顺序交错输出各路数据块，`choices[].index` 改写为所属请求的序号、`id` 统一，
+/// 各路的 `usage` 从数据块中移除，全部结束后合并为一个只含 `usage` 的数据块，最后输出 `data: [DONE]`；
+/// 非流式响应等待全部完成后合并 `choices` 与 `usage`。合并规则见 [`merge_usage`]。
+/// 每一路都按 [`UpstreamRetry`] 的配置重试，任一路最终返回错误状态时直接返回该路的响应。
+#[allow(clippy::too_many_arguments)]
+pub async fn send(
+    client: &reqwest::Client,
+    retry: &UpstreamRetry,
+    method: Method,
+    url: &str,
+    headers: HeaderMap,
+    body: Bytes,
+    n: u64,
+    request_id: &str,
+    permits: LegPermits,
+) -> Result<UpstreamResponse, String> {
+    let requests = (0..n).map(|_| {
+        retry.send(
+            client,
+            method.clone(),
+            url,
+            headers.clone(),
+            UpstreamBody::Memory(body.clone()),
+        )
+    });
+    let responses = try_join_all(requests).await?;
+
+    if let Some(index) = responses.iter().position(|r| !r.status().is_success()) {
+        return Ok(responses.into_iter().nth(index).unwrap().into());
+    }
+
+    let mut merged_headers = responses[0].headers().clone();
+    merged_headers.remove(CONTENT_LENGTH);
+    let is_event_stream = merged_headers
+        .get(axum::http::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("text/event-stream"));
+    let id = format!("chatcmpl-{}", request_id);
+
+    if is_event_stream {
+        let totals = Arc::new(Mutex::new(StreamTotals::default()));
+        let streams: Vec<_> = responses
+            .into_iter()
+            .enumerate()
+            .map(|(index, response)| {
+                let id = id.clone();
+                let totals = totals.clone();
+                let mut parser = SseParser::default();
+                response
+                    .bytes_stream()
+                    .map(move |chunk| {
+                        let chunk = chunk?;
+                        let mut output = Vec::new();
+                        for event in parser.push(&chunk) {
+                            match event.data.as_deref() {
+                                Some("[DONE]") => {}
+                                Some(data) => match reindex_chunk(data, index, &id, &totals) {
+                                    Some(Some(data)) => output.extend_from_slice(
+                                        format!("data: {}\n\n", data).as_bytes(),
+                                    ),
+                                    Some(None) => {}
+                                    None => output.extend_from_slice(&event.raw),
+                                },
+                                None => output.extend_from_slice(&event.raw),
+                            }
+                        }
+                        Ok(Bytes::from(output))
+                    })
+                    .boxed()
+            })
+            .collect();
+        let done = futures::stream::once(async move {
+            let totals = std::mem::take(&mut *totals.lock().unwrap());
+            let mut output = String::new();
+            if !totals.usage.is_empty() {
+                let chunk = json!({
+                    "id": id,
+                    "object": "chat.completion.chunk",
+                    "model": totals.model,
+                    "choices": [],
+                    "usage": totals.usage,
+                });
+                output.push_str(&format!("data: {}\n\n", chunk));
+            }
+            output.push_str("data: [DONE]\n\n");
+            Ok(Bytes::from(output))
+        });
+        let body = futures::stream::select_all(streams)
+            .chain(done)
+            .map(move |chunk| {
+                let _permits = &permits;
+                chunk
+            })
+            .boxed();
+
+        return Ok(UpstreamResponse {
+            status: StatusCode::OK,
+            headers: merged_headers,
+            body,
+        });
+    }
+
+    let mut completions = Vec::with_capacity(responses.len());
+    for response in responses {
+        completions.push(
+            response
+                .json::<ChatCompletionResponse>()
+                .await
+                .map_err(|e| e.to_string())?,
+        );
+    }
+    let merged = merge_completions(completions, &id)
+        .to_json()
+        .unwrap_or_default();
+    merged_headers.insert(
+        axum::http::header::CONTENT_TYPE,
+        HeaderValue::from_static("application/json"),
+    );
+    Ok(UpstreamResponse {
+        status: StatusCode::OK,
+        headers: merged_headers,
+        body: futures::stream::once(async move { Ok(Bytes::from(merged)) }).boxed(),
+    })
+}
+
+/// 流式扇出中各路累计的用量
+#[derive(Default)]
+struct StreamTotals {
+    model: Option<String>,
+    usage: Map<String, Value>,
+}
+
+/// 改写流式数据块的 `id` 与 `choices[].index`，并把 `usage` 移入累计用量
+///
+/// 不是 JSON 时返回 `None`，原样输出；移除 `usage` 后 `choices` 为空时返回 `Some(None)`，不再输出。
+fn reindex_chunk(
+    data: &str,
+    index: usize,
+    id: &str,
+    totals: &Mutex<StreamTotals>,
+) -> Option<Option<String>> {
+    let mut chunk = ChatCompletionResponse::parse(data.as_bytes())?;
+    chunk.id = Some(id.to_string());
+    if let Some(usage) = chunk.usage.take() {
+        let mut totals = totals.lock().unwrap();
+        if totals.model.is_none() {
+            totals.model = chunk.model.clone();
+        }
+        merge_usage(&mut totals.usage, &usage_fields(&usage));
+    }
+    if chunk.choices.as_ref().is_some_and(Vec::is_empty) {
+        return Some(None);
+    }
+    for choice in chunk.choices_mut() {
+        choice.index = index as u64;
+    }
+    Some(chunk.to_json())
+}
+
+/// 用量的全部字段，包括 `extra` 中的嵌套明细
+fn usage_fields(usage: &Usage) -> Map<String, Value> {
+    match serde_json::to_value(usage) {
+        Ok(Value::Object(fields)) => fields,
+        _ => Map::new(),
+    }
+}
+
+/// 把一路响应的 `usage` 累加到合并结果
+///
+/// 数值字段逐项相加，嵌套对象（如 `prompt_tokens_details`、`completion_tokens_details`）递归合并。
+/// 扇出的每一路都是独立的上游请求，`prompt_tokens` 为各路输入之和，即实际计费的用量，
+/// 约为原生 `n` 请求的 n 倍。
+fn merge_usage(target: &mut Map<String, Value>, source: &Map<String, Value>) {
+    for (key, field) in source {
+        match field {
+            Value::Number(count) => {
+                let total = match (target.get(key).and_then(Value::as_u64), count.as_u64()) {
+                    (Some(total), Some(count)) => json!(total + count),
+                    (None, Some(count)) => json!(count),
+                    _ => json!(
+                        target.get(key).and_then(Value::as_f64).unwrap_or(0.0)
+                            + count.as_f64().unwrap_or(0.0)
+                    ),
+                };
+                target.insert(key.clone(), total);
+            }
+            Value::Object(fields) => {
+                if let Value::Object(nested) = target
+                    .entry(key.clone())
+                    .or_insert_with(|| Value::Object(Map::new()))
+                {
+                    merge_usage(nested, fields);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// 合并多个非流式响应：`choices` 依次编号，`usage` 按 [`merge_usage`] 合并
+fn merge_completions(completions: Vec<ChatCompletionResponse>, id: &str) -> ChatCompletionResponse {
+    let mut merged = completions[0].clone();
+    let mut choices = Vec::new();
+    let mut usage = Map::new();
+
+    for (index, completion) in completions.into_iter().enumerate() {
+        for mut choice in completion.choices.unwrap_or_default() {
+            choice.index = index as u64;
+            choices.push(choice);
+        }
+        if let Some(fields) = &completion.usage {
+            merge_usage(&mut usage, &usage_fields(fields));
+        }
+    }
+
+    merged.id = Some(id.to_string());
+    merged.choices = Some(choices);
+    if !usage.is_empty() {
+        merged.usage = serde_json::from_value(Value::Object(usage)).ok();
+    }
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merges_numeric_and_nested_usage_fields() {
+        let mut total = Map::new();
+        let first = json!({
+            "prompt_tokens": 5,
+            "completion_tokens": 3,
+            "prompt_tokens_details": { "cached_tokens": 2 },
+        });
+        let second = json!({
+            "prompt_tokens": 5,
+            "completion_tokens": 4,
+            "prompt_tokens_details": { "cached_tokens": 1 },
+            "completion_tokens_details": { "reasoning_tokens": 7 },
+            "cost": 0.5,
+            "note": "ignored",
+        });
+        merge_usage(&mut total, first.as_object().unwrap());
+        merge_usage(&mut total, second.as_object().unwrap());
+        assert_eq!(
+            Value::Object(total),
+            json!({
+                "prompt_tokens": 10,
+                "completion_tokens": 7,
+                "prompt_tokens_details": { "cached_tokens": 3 },
+                "completion_tokens_details": { "reasoning_tokens": 7 },
+                "cost": 0.5,
+            })
+        );
+    }
+
+    #[test]
+    fn reindexes_chunks_and_collects_usage() {
+        let totals = Mutex::new(StreamTotals::default());
+        let data = r#"{"id":"upstream","model":"deepseek-chat","choices":[{"index":0,"delta":{"content":"hi"}}]}"#;
+        let chunk = reindex_chunk(data, 2, "chatcmpl-1", &totals)
+            .unwrap()
+            .unwrap();
+        let chunk: Value = serde_json::from_str(&chunk).unwrap();
+        assert_eq!(chunk["id"], "chatcmpl-1");
+        assert_eq!(chunk["choices"][0]["index"], 2);
+        assert_eq!(chunk["choices"][0]["delta"]["content"], "hi");
+
+        // 只含用量的数据块计入累计后不再输出
+        let usage = r#"{"id":"upstream","model":"deepseek-chat","choices":[],"usage":{"prompt_tokens":4,"completion_tokens":1}}"#;
+        assert_eq!(reindex_chunk(usage, 0, "chatcmpl-1", &totals), Some(None));
+        assert_eq!(reindex_chunk(usage, 1, "chatcmpl-1", &totals), Some(None));
+        let totals = totals.into_inner().unwrap();
+        assert_eq!(totals.model.as_deref(), Some("deepseek-chat"));
+        assert_eq!(
+            Value::Object(totals.usage),
+            json!({ "prompt_tokens": 8, "completion_tokens": 2 })
+        );
+
+        let totals = Mutex::new(StreamTotals::default());
+        assert_eq!(reindex_chunk("not json", 0, "chatcmpl-1", &totals), None);
+    }
+
+    #[test]
+    fn keeps_logprobs_and_finish_reason_when_merging() {
+        let logprobs = json!({ "content": [{ "token": "hi", "logprob": -0.2, "bytes": [104, 105], "top_logprobs": [] }] });
+        let totals = Mutex::new(StreamTotals::default());
+        let data = json!({
+            "choices": [{ "index": 0, "delta": { "content": "hi" }, "logprobs": logprobs, "finish_reason": "length" }],
+        });
+        let chunk = reindex_chunk(&data.to_string(), 1, "chatcmpl-1", &totals)
+            .unwrap()
+            .unwrap();
+        let chunk: Value = serde_json::from_str(&chunk).unwrap();
+        assert_eq!(chunk["choices"][0]["logprobs"], logprobs);
+        assert_eq!(chunk["choices"][0]["finish_reason"], "length");
+
+        let completion = |finish_reason: &str| {
+            serde_json::from_value(json!({
+                "choices": [{
+                    "index": 0,
+                    "message": { "role": "assistant", "content": "hi" },
+                    "logprobs": logprobs,
+                    "finish_reason": finish_reason,
+                }],
+            }))
+            .unwrap()
+        };
+        let merged =
+            merge_completions(vec![completion("stop"), completion("length")], "chatcmpl-1");
+        let merged: Value = serde_json::from_str(&merged.to_json().unwrap()).unwrap();
+        assert_eq!(merged["choices"][1]["index"], 1);
+        assert_eq!(merged["choices"][1]["logprobs"], logprobs);
+        assert_eq!(merged["choices"][1]["finish_reason"], "length");
+    }
+}
@@ -1 +1,3 @@
+pub mod admin;
 pub mod chat_completions;
+pub mod translate;
@@ -0,0 +1,81 @@
+use axum::{
+    extract::{Request, State},
+    http::{StatusCode, header::AUTHORIZATION},
+    middleware::Next,
+    response::Response,
+};
+use tracing_subscriber::EnvFilter;
+
+use crate::AppState;
+
+/// 管理接口鉴权：要求 `Authorization: Bearer <ADMIN_API_KEY>`
+pub async fn require_admin(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Result<Response, (StatusCode, String)> {
+    let Some(admin_api_key) = state.admin_api_key.as_deref() else {
+        return Err((StatusCode::NOT_FOUND, "管理接口未启用".to_string()));
+    };
+
+    let authorized = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|token| token == admin_api_key);
+
+    if !authorized {
+        return Err((StatusCode::UNAUTHORIZED, "管理接口密钥无效".to_string()));
+    }
+
+    Ok(next.run(request).await)
+}
+
+/// 查询当前生效的日志过滤指令
+#[utoipa::path(
+    get,
+    path = "/admin/log-filter",
+    tag = "admin",
+    security(("admin_key" = [])),
+    responses(
+        (status = 200, description = "当前指令", body = String, content_type = "text/plain"),
+        (status = 401, description = "管理接口密钥无效", body = String),
+    ),
+)]
+pub async fn get_log_filter(State(state): State<AppState>) -> Result<String, (StatusCode, String)> {
+    state
+        .log_filter
+        .with_current(|filter| filter.to_string())
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+}
+
+/// 在运行时替换日志过滤指令，请求体为 `EnvFilter` 语法的纯文本，如 `info,free_model::handlers=trace`
+#[utoipa::path(
+    put,
+    path = "/admin/log-filter",
+    tag = "admin",
+    security(("admin_key" = [])),
+    request_body(content = String, content_type = "text/plain", example = "info,free_model::handlers=trace"),
+    responses(
+        (status = 200, description = "生效的指令", body = String, content_type = "text/plain"),
+        (status = 400, description = "指令语法错误", body = String),
+        (status = 401, description = "管理接口密钥无效", body = String),
+    ),
+)]
+pub async fn put_log_filter(
+    State(state): State<AppState>,
+    body: String,
+) -> Result<String, (StatusCode, String)> {
+    let filter =
+        EnvFilter::try_new(body.trim()).map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+    let directives = filter.to_string();
+
+    state
+        .log_filter
+        .reload(filter)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    tracing::info!("日志过滤指令已更新为: {}", directives);
+    Ok(directives)
+}
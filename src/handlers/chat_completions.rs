@@ -1,11 +1,53 @@
+use std::time::Instant;
+
 use axum::{
-    body::Body,
-    extract::{RawQuery, Request, State},
-    http::{HeaderMap, Method, StatusCode, header::AUTHORIZATION},
+    Extension,
+    body::{Body, Bytes, to_bytes},
+    extract::{MatchedPath, Path, RawQuery, Request, State},
+    http::{
+        HeaderMap, Method, StatusCode,
+        header::{AUTHORIZATION, CONTENT_LENGTH, CONTENT_TYPE},
+    },
     response::Response,
 };
+use futures::StreamExt;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    AppState,
+    abuse::ABUSE_SIGNALS_HEADER,
+    cancel::COMPLETION_ID_HEADER,
+    chat::{ChatCompletionRequest, ChatCompletionResponse},
+    chat_stream,
+    context::CONTEXT_COMPRESSION_HEADER,
+    diagnostics,
+    experiments::EXPERIMENT_HEADER,
+    fanout,
+    idempotency::{Begin, IDEMPOTENCY_KEY_HEADER, Leader},
+    keys::{self, ClientIdentity},
+    logging::REQUEST_ID_HEADER,
+    normalize,
+    overrides::UpstreamOverrides,
+    pipeline::StageContext,
+    quota::{self, QUOTA_WARNING_HEADER},
+    redaction::{RedactionMode, StreamRestorer, TokenMap},
+    retry::UpstreamBody,
+    scheduler, scripts, tee,
+    tool_emulation::{self, StreamEmulator},
+    upstream::UpstreamResponse,
+};
 
-use crate::AppState;
+/// 上游 DeepSeek Chat Completions 接口地址
+pub const UPSTREAM_CHAT_COMPLETIONS_URL: &str = "https://api.deepseek.com/chat/completions";
+
+/// 请求在上游响应前被取消时返回的状态码（沿用 nginx 的 499 约定）
+const CLIENT_CLOSED_REQUEST: StatusCode = match StatusCode::from_u16(499) {
+    Ok(status) => status,
+    Err(_) => panic!(),
+};
+
+/// 需要解析请求体时允许缓冲的最大字节数
+const MAX_BUFFERED_BODY_BYTES: usize = 32 * 1024 * 1024;
 
 /// 请求头黑名单(需要移除的头)
 const REQUEST_HEADERS_BLOCKLIST: &[axum::http::HeaderName] = &[
@@ -17,6 +59,8 @@ const REQUEST_HEADERS_BLOCKLIST: &[axum::http::HeaderName] = &[
     axum::http::header::UPGRADE,
     axum::http::header::ORIGIN,
     axum::http::header::REFERER,
+    // 由 reqwest 与上游协商压缩并自动解压，客户端侧的压缩由 CompressionLayer 负责
+    axum::http::header::ACCEPT_ENCODING,
 ];
 
 /// 响应头黑名单(需要移除的头)
@@ -34,22 +78,82 @@ const RESPONSE_HEADERS_BLOCKLIST: &[axum::http::HeaderName] = &[
     axum::http::header::ACCESS_CONTROL_MAX_AGE,
 ];
 
+/// 对话补全
+///
+/// 转发到 DeepSeek Chat Completions，参数与响应格式与上游一致；`stream: true` 时返回 SSE。
+/// 只描述代理读取或改写的字段，其余字段原样透传。
+#[utoipa::path(
+    post,
+    path = "/chat/completions",
+    tag = "chat",
+    params(
+        ("idempotency-key" = Option<String>, Header, description = "幂等键，相同客户端的重复请求复用首个请求的响应"),
+    ),
+    request_body = ChatCompletionRequest,
+    responses(
+        (
+            status = 200,
+            description = "上游响应",
+            headers(
+                ("x-request-id" = String, description = "请求 ID"),
+                ("x-completion-id" = String, description = "服务端生成的对话 ID，用于取消与续传"),
+                ("x-upstream-latency-ms" = u64, description = "从发出请求到收到上游响应头的耗时（毫秒）"),
+                ("x-upstream-model" = String, description = "上游实际使用的模型（仅需要改写响应体的非流式响应）"),
+                ("x-tokens-prompt" = u64, description = "输入 token 数（同上）"),
+                ("x-tokens-completion" = u64, description = "输出 token 数（同上）"),
+                ("x-cache" = String, description = "是否命中上游上下文缓存（同上）"),
+                ("x-quota-warning" = String, description = "用量达到软限制时的当期用量与配额，如 `daily 8000/10000`"),
+            ),
+            content(
+                (ChatCompletionResponse = "application/json"),
+                (String = "text/event-stream"),
+            ),
+        ),
+        (status = 400, description = "请求体无效", body = String),
+        (status = 401, description = "客户端密钥或 JWT 无效", body = String),
+        (status = 403, description = "网络访问控制、客户端密钥权限、滥用检测或路由脚本拒绝", body = String),
+        (status = 404, description = "模型不存在", body = String),
+        (status = 422, description = "幂等键已用于不同的请求体", body = String),
+        (status = 429, description = "token 配额已用完", body = String),
+        (status = 499, description = "请求在上游响应前被取消", body = String),
+        (status = 502, description = "上游请求失败", body = String),
+        (status = 503, description = "服务降载", body = String),
+    ),
+)]
 pub async fn handle_chat_completions(
     State(state): State<AppState>,
     RawQuery(query): RawQuery,
     method: Method,
     headers: HeaderMap,
     body: Request,
+) -> Result<Response, (StatusCode, String)> {
+    let mut leader = None;
+    let result = forward(state, query, method, headers, body, &mut leader).await;
+    // 幂等请求在拿到上游响应前出错时，把同样的错误交给等待中的重复请求
+    if let (Err(error), Some(leader)) = (&result, leader) {
+        leader.fail(error);
+    }
+    result
+}
+
+/// 转发对话请求；携带幂等键的首个请求把记录器放入 `leader`，拿到上游响应后取出
+async fn forward(
+    state: AppState,
+    query: Option<String>,
+    method: Method,
+    headers: HeaderMap,
+    body: Request,
+    leader: &mut Option<Leader>,
 ) -> Result<Response, (StatusCode, String)> {
     let client = &state.http_client;
-    // 构建目标URL
-    let mut target_url = String::from("https://api.deepseek.com/chat/completions");
+    // 特权客户端可按请求覆盖上游地址与密钥
+    let upstream_override = state.upstream_overrides.resolve(&headers, &state.api_key)?;
 
-    // 添加查询参数
-    if let Some(query_string) = query {
-        target_url.push('?');
-        target_url.push_str(&query_string);
-    }
+    // 构建目标URL
+    let upstream_url = upstream_override
+        .as_ref()
+        .and_then(|upstream_override| upstream_override.url.clone())
+        .unwrap_or_else(|| UPSTREAM_CHAT_COMPLETIONS_URL.to_string());
 
     // 过滤请求头
     let mut request_headers = HeaderMap::new();
@@ -58,45 +162,395 @@ pub async fn handle_chat_completions(
             request_headers.insert(name.clone(), value.clone());
         }
     }
+    // 代理自身使用的控制头，不转发给上游
+    request_headers.remove(CONTEXT_COMPRESSION_HEADER);
+    request_headers.remove(IDEMPOTENCY_KEY_HEADER);
+    UpstreamOverrides::strip_headers(&mut request_headers);
 
-    // 使用 AppState 中的 API 密钥设置 Authorization 头(仅当未传入时)
-    if !request_headers.contains_key(AUTHORIZATION) {
+    // 使用 AppState 中的 API 密钥设置 Authorization 头(仅当未传入时)；启用客户端密钥时客户端密钥只用于代理鉴权，不转发
+    if keys::client_auth_enabled(&state) || !request_headers.contains_key(AUTHORIZATION) {
         let auth_value = axum::http::HeaderValue::from_str(&format!("Bearer {}", state.api_key))
             .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
         request_headers.insert(AUTHORIZATION, auth_value);
     }
+    // 按客户端凭据隔离幂等键、取消与续传
+    let scope = client_scope(
+        &headers,
+        body.extensions().get::<ClientIdentity>(),
+        &state.api_key,
+    );
+    // 扇出的其余各路按同一路由与客户端占用名额
+    let route = body
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string());
+    let scheduler_client =
+        scheduler::client_of(&headers, body.extensions().get::<ClientIdentity>());
+    // 配额用尽的请求在占用幂等键之前拒绝
+    let quota_client = state
+        .token_quota
+        .is_enabled()
+        .then(|| quota::quota_client(body.extensions().get::<ClientIdentity>(), &scope));
+    let quota_warning = match &quota_client {
+        Some(quota_client) => state.token_quota.check(quota_client)?,
+        None => None,
+    };
+    // 特权客户端的密钥只用于代理鉴权，转发时换成上游密钥
+    if let Some(upstream_override) = &upstream_override {
+        let auth_value =
+            axum::http::HeaderValue::from_str(&format!("Bearer {}", upstream_override.api_key))
+                .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        request_headers.insert(AUTHORIZATION, auth_value);
+    }
+
+    // 携带幂等键的重复请求直接复用首个请求的响应，请求体不同时拒绝
+    let body = match headers
+        .get(IDEMPOTENCY_KEY_HEADER)
+        .and_then(|value| value.to_str().ok())
+    {
+        Some(key) => {
+            let (parts, body) = body.into_parts();
+            let bytes = to_bytes(body, MAX_BUFFERED_BODY_BYTES)
+                .await
+                .map_err(|e| (StatusCode::PAYLOAD_TOO_LARGE, e.to_string()))?;
+            match state.idempotency.begin(&scope, key, &bytes)? {
+                Begin::Leader(begun) => {
+                    *leader = Some(begun);
+                    Request::from_parts(parts, Body::from(bytes))
+                }
+                Begin::Follower(replay) => return replay.into_response().await,
+            }
+        }
+        None => body,
+    };
+
+    // 请求 ID 由 SetRequestIdLayer 生成
+    let request_id = headers
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+
+    // 有请求处理阶段需要执行、启用 n-best 扇出、中断续写或配额时需要缓冲并解析请求体，否则直接流式转发
+    let mut ctx = StageContext {
+        state: &state,
+        route: "chat",
+        headers: &headers,
+        scope: &scope,
+        upstream_url,
+        authorization: request_headers[AUTHORIZATION].clone(),
+        header_rewrites: Vec::new(),
+        strategy: state.context.strategy_for(&headers)?,
+        abuse_signals: Vec::new(),
+        token_map: TokenMap::default(),
+        assignment: None,
+        emulate: false,
+    };
+    let mut fanout = None;
+    let mut continuation_body = None;
+    let mut strip_usage = false;
+    let needs_body = state.pipeline.needs_body(&ctx)
+        || !state.fanout_models.is_empty()
+        || state.stream_error_retries > 0
+        || quota_client.is_some();
+    let upstream_body = if !needs_body {
+        // 启用上游重试时按大小缓冲以便重放
+        let content_length = headers
+            .get(CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse().ok());
+        state
+            .upstream_retry
+            .buffer(body.into_body(), content_length)
+            .await?
+    } else {
+        let bytes = to_bytes(body.into_body(), MAX_BUFFERED_BODY_BYTES)
+            .await
+            .map_err(|e| (StatusCode::PAYLOAD_TOO_LARGE, e.to_string()))?;
+        let mut bytes = state.pipeline.run(&mut ctx, bytes).await?;
+        // 流式请求需要最后的用量数据块才能计入配额与实验用量
+        if quota_client.is_some() || ctx.assignment.is_some() {
+            (bytes, strip_usage) = quota::request_usage(bytes)?;
+        }
+        fanout = state.fanout_models.plan(&bytes)?;
+        // 续写依赖 DeepSeek 的前缀续写接口，覆盖了上游地址或扇出的请求不续写
+        let default_upstream = ctx.upstream_url == UPSTREAM_CHAT_COMPLETIONS_URL;
+        if state.stream_error_retries > 0 && default_upstream && fanout.is_none() {
+            continuation_body = Some(bytes.clone());
+        }
+        request_headers.remove(CONTENT_LENGTH);
+        UpstreamBody::Memory(bytes)
+    };
+    let StageContext {
+        upstream_url,
+        authorization,
+        header_rewrites,
+        abuse_signals,
+        token_map,
+        assignment,
+        emulate,
+        ..
+    } = ctx;
+    let restore = state.redaction_mode == RedactionMode::Reversible && !token_map.is_empty();
+    // 覆盖了上游地址时把响应转换为统一格式
+    let normalize = upstream_url != UPSTREAM_CHAT_COMPLETIONS_URL;
+
+    // 路由脚本可能选择了其他上游或改写请求头
+    request_headers.insert(AUTHORIZATION, authorization);
+    scripts::apply_headers(&header_rewrites, &mut request_headers);
+    let mut target_url = upstream_url;
+
+    // 添加查询参数
+    if let Some(query_string) = query {
+        target_url.push('?');
+        target_url.push_str(&query_string);
+    }
 
-    // 将请求体转换为流
-    let body_stream = body.into_body().into_data_stream();
+    // 上游流中断时按原始请求续写
+    let continuation = continuation_body.map(|body| chat_stream::Continuation {
+        client: client.clone(),
+        headers: request_headers.clone(),
+        body,
+        retries: state.stream_error_retries,
+    });
 
-    // 构建请求(流式传输请求体)
-    let request_builder = client
-        .request(method, &target_url)
-        .headers(request_headers)
-        .body(reqwest::Body::wrap_stream(body_stream));
+    // 以服务端生成的 ID 登记请求，以便客户端通过取消接口中止
+    let completion_id = uuid::Uuid::now_v7().to_string();
+    let guard = state
+        .cancel_registry
+        .register(completion_id.clone(), scope.clone());
 
-    // 发送请求
-    let response = request_builder
-        .send()
-        .await
-        .map_err(|e| (StatusCode::BAD_GATEWAY, e.to_string()))?;
+    // 发送请求，需要扇出时并行发出 n 个请求并合并响应
+    let send = async {
+        match fanout {
+            Some((n, single)) => {
+                let permits =
+                    fanout::acquire_leg_permits(&state, route.as_deref(), &scheduler_client, n)
+                        .await?;
+                fanout::send(
+                    client,
+                    &state.upstream_retry,
+                    method,
+                    &target_url,
+                    request_headers,
+                    single,
+                    n,
+                    &request_id,
+                    permits,
+                )
+                .await
+                .map_err(|e| (StatusCode::BAD_GATEWAY, e))
+            }
+            None => state
+                .upstream_retry
+                .send(client, method, &target_url, request_headers, upstream_body)
+                .await
+                .map(UpstreamResponse::from)
+                .map_err(|e| (StatusCode::BAD_GATEWAY, e)),
+        }
+    };
+
+    // 等待响应期间被取消时直接丢弃上游请求
+    let started = Instant::now();
+    let response = tokio::select! {
+        response = send => response?,
+        _ = guard.token.cancelled() => {
+            return Err((CLIENT_CLOSED_REQUEST, "请求已取消".to_string()));
+        }
+    };
 
     // 获取响应状态码
-    let status = response.status();
+    let status = response.status;
 
-    // 构建响应并过滤响应头
-    let mut builder = Response::builder().status(status);
-    for (name, value) in response.headers().iter() {
+    // 过滤响应头
+    let mut response_headers = HeaderMap::new();
+    for (name, value) in response.headers.iter() {
         if !RESPONSE_HEADERS_BLOCKLIST.contains(name) {
-            builder = builder.header(name, value);
+            response_headers.append(name, value.clone());
+        }
+    }
+
+    diagnostics::insert_latency(&mut response_headers, started.elapsed());
+    if let Ok(value) = axum::http::HeaderValue::from_str(&completion_id) {
+        response_headers.insert(COMPLETION_ID_HEADER, value);
+    }
+
+    if let Some(value) = quota_warning {
+        response_headers.insert(QUOTA_WARNING_HEADER, value);
+    }
+
+    if !abuse_signals.is_empty()
+        && let Ok(value) = axum::http::HeaderValue::from_str(&abuse_signals.join(","))
+    {
+        response_headers.insert(ABUSE_SIGNALS_HEADER, value);
+    }
+
+    // 标记实验分组并按分组统计结果
+    if let Some(assignment) = &assignment {
+        assignment.record(status, started.elapsed());
+        if let Some(value) = assignment.header_value() {
+            response_headers.insert(EXPERIMENT_HEADER, value);
         }
     }
 
+    // SSE 响应按事件转发以支持取消，其余响应缓冲后返回
+    let is_event_stream = response
+        .headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("text/event-stream"));
+    let stream = if is_event_stream {
+        let restorer = restore.then(|| StreamRestorer::new(token_map));
+        let emulator = emulate.then(StreamEmulator::default);
+        chat_stream::chat_stream(
+            response.body,
+            guard,
+            request_id.clone(),
+            restorer,
+            emulator,
+            normalize,
+            continuation,
+            started,
+            state.sse_keepalive,
+        )
+        .boxed()
+    } else if normalize || restore || emulate || state.plugins.transforms_response() {
+        // 需要改写的非流式响应整体缓冲：写入模型与用量诊断头，按需转换为统一格式、还原占位符、解析模拟的工具调用并交给插件改写；
+        // 读取期间被取消时同样丢弃上游请求
+        let mut bytes = tokio::select! {
+            bytes = response.bytes(MAX_BUFFERED_BODY_BYTES) => {
+                bytes.map_err(|e| (StatusCode::BAD_GATEWAY, e))?
+            }
+            _ = guard.token.cancelled() => {
+                return Err((CLIENT_CLOSED_REQUEST, "请求已取消".to_string()));
+            }
+        };
+        diagnostics::insert_usage(&mut response_headers, &bytes);
+        if normalize {
+            bytes = normalize::normalize_response(bytes);
+        }
+        if restore {
+            bytes = Bytes::from(token_map.restore(&String::from_utf8_lossy(&bytes)));
+        }
+        if emulate {
+            bytes = tool_emulation::rewrite_response(bytes);
+        }
+        if state.plugins.transforms_response() {
+            bytes = state.plugins.transform_response(bytes).await?;
+        }
+        response_headers.remove(axum::http::header::CONTENT_LENGTH);
+        futures::stream::once(futures::future::ready(Ok(bytes))).boxed()
+    } else {
+        // 无需改写的非流式响应直接流式转发
+        response.body
+    };
+
+    // 配置了价格时旁路计算实际费用
+    let stream = if state.price_table.is_empty() {
+        stream
+    } else {
+        state
+            .price_table
+            .clone()
+            .record_cost(stream, is_event_stream)
+            .boxed()
+    };
+
+    // 按实验分组旁路统计用量
+    let stream = match assignment {
+        Some(assignment) => assignment.record_usage(stream, is_event_stream).boxed(),
+        None => stream,
+    };
+
+    // 启用配额时旁路计入用量
+    let stream = match quota_client {
+        Some(quota_client) => state
+            .token_quota
+            .clone()
+            .record_usage(quota_client, stream, is_event_stream)
+            .boxed(),
+        None => stream,
+    };
+
+    // 移除客户端未要求的用量数据块
+    let stream = if strip_usage && is_event_stream {
+        quota::strip_usage_chunks(stream).boxed()
+    } else {
+        stream
+    };
+
+    // 按需旁路一份到日志
+    let stream = match state.response_log_max_bytes {
+        Some(max_bytes) => tee::tee_to_log(stream, max_bytes).boxed(),
+        None => stream,
+    };
+
+    // 启用续传时 SSE 响应由后台接收并缓存，客户端断开后仍在宽限期内继续生成
+    let stream = match &state.resume_store {
+        Some(store) if is_event_stream => store.track(completion_id, scope, stream).boxed(),
+        _ => stream
+            .map(|chunk| chunk.map_err(std::io::Error::other))
+            .boxed(),
+    };
+
+    // 幂等请求由记录器在后台接收响应体，供重复请求复用
+    if let Some(leader) = leader.take() {
+        return Ok(leader.record(status, response_headers, stream));
+    }
+
     // 流式传输响应体
-    let stream = response.bytes_stream();
-    let body = Body::from_stream(stream);
+    let mut response = Response::new(Body::from_stream(stream));
+    *response.status_mut() = status;
+    *response.headers_mut() = response_headers;
+    Ok(response)
+}
 
-    builder
-        .body(body)
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+/// 客户端凭据，未携带 `Authorization` 时视为使用服务端密钥；通过客户端密钥或 JWT 鉴权的客户端使用其身份
+pub fn client_scope(
+    headers: &HeaderMap,
+    identity: Option<&ClientIdentity>,
+    api_key: &str,
+) -> String {
+    if let Some(identity) = identity {
+        return identity.scope.clone();
+    }
+    match headers.get(AUTHORIZATION) {
+        Some(value) => value.to_str().unwrap_or_default().to_string(),
+        None => format!("Bearer {}", api_key),
+    }
+}
+
+/// 客户端凭据的摘要（SHA-256 前 8 字节的十六进制），按客户端记录状态时使用，内存中不保存凭据本身
+pub fn client_id(scope: &str) -> String {
+    Sha256::digest(scope.as_bytes())[..8]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// 取消进行中的对话请求：关闭上游连接停止生成，流式响应以 `cancelled` 结束原因收尾
+///
+/// 只能取消使用相同凭据发起的请求。
+#[utoipa::path(
+    post,
+    path = "/chat/completions/{completion_id}/cancel",
+    tag = "chat",
+    params(("completion_id" = String, Path, description = "对话响应头 `x-completion-id` 中的 ID")),
+    responses(
+        (status = 204, description = "已取消"),
+        (status = 404, description = "请求不存在、已结束或凭据不一致", body = String),
+    ),
+)]
+pub async fn handle_cancel(
+    State(state): State<AppState>,
+    Path(completion_id): Path<String>,
+    identity: Option<Extension<ClientIdentity>>,
+    headers: HeaderMap,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let scope = client_scope(&headers, identity.as_deref(), &state.api_key);
+    if state.cancel_registry.cancel(&completion_id, &scope) {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err((StatusCode::NOT_FOUND, "请求不存在或已结束".to_string()))
+    }
 }
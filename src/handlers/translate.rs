@@ -0,0 +1,233 @@
+use std::time::Instant;
+
+use axum::{
+    Extension, Json,
+    body::{Body, Bytes},
+    extract::State,
+    http::{HeaderMap, StatusCode, header::AUTHORIZATION, header::CONTENT_TYPE},
+    response::Response,
+};
+use futures::{StreamExt, TryStreamExt, future::ready};
+use serde::Deserialize;
+use serde_json::json;
+use utoipa::ToSchema;
+
+use crate::{
+    AppState,
+    abuse::ABUSE_SIGNALS_HEADER,
+    chat::ChatCompletionResponse,
+    experiments::{Assignment, EXPERIMENT_HEADER},
+    handlers::chat_completions::{UPSTREAM_CHAT_COMPLETIONS_URL, client_scope},
+    keys::{self, ClientIdentity},
+    pipeline::StageContext,
+    quota::{self, QUOTA_WARNING_HEADER},
+    redaction::{RedactionMode, StreamRestorer, TokenMap},
+    scripts,
+    sse::SseParser,
+};
+
+/// 未指定模型时使用的翻译模型
+const DEFAULT_TRANSLATE_MODEL: &str = "deepseek-chat";
+
+/// 术语表条目：原文中出现 `source` 时必须译为 `target`
+#[derive(Deserialize, ToSchema)]
+pub struct GlossaryTerm {
+    pub source: String,
+    pub target: String,
+}
+
+/// 翻译请求
+#[derive(Deserialize, ToSchema)]
+pub struct TranslateRequest {
+    /// 待翻译文本
+    pub text: String,
+    /// 源语言，未指定时由模型自动识别
+    #[serde(default)]
+    pub source_lang: Option<String>,
+    /// 目标语言
+    pub target_lang: String,
+    #[serde(default)]
+    pub glossary: Vec<GlossaryTerm>,
+    /// 翻译使用的模型，默认 `deepseek-chat`
+    #[serde(default)]
+    pub model: Option<String>,
+}
+
+/// 根据语言与术语表构建翻译用的系统提示词
+fn build_system_prompt(request: &TranslateRequest) -> String {
+    let source = request
+        .source_lang
+        .as_deref()
+        .unwrap_or("the detected language");
+    let mut prompt = format!(
+        "You are a professional translator. Translate the user's text from {} into {}. \
+         Preserve formatting, line breaks and markup. Output only the translation without any explanation.",
+        source, request.target_lang
+    );
+
+    if !request.glossary.is_empty() {
+        prompt.push_str("\nAlways use the following glossary:");
+        for term in &request.glossary {
+            prompt.push_str(&format!("\n- {} => {}", term.source, term.target));
+        }
+    }
+    prompt
+}
+
+/// 流式翻译：使用对话模型配合翻译提示词，以纯文本分块返回译文
+#[utoipa::path(
+    post,
+    path = "/translate",
+    tag = "chat",
+    request_body = TranslateRequest,
+    responses(
+        (status = 200, description = "分块返回的译文", body = String, content_type = "text/plain"),
+        (status = 400, description = "请求体无效", body = String),
+        (status = 401, description = "客户端密钥或 JWT 无效", body = String),
+        (status = 403, description = "客户端密钥权限、滥用检测或路由脚本拒绝", body = String),
+        (status = 404, description = "模型不存在", body = String),
+        (status = 429, description = "token 配额已用完", body = String),
+        (status = 502, description = "上游请求失败", body = String),
+    ),
+)]
+pub async fn handle_translate(
+    State(state): State<AppState>,
+    identity: Option<Extension<ClientIdentity>>,
+    headers: HeaderMap,
+    Json(request): Json<TranslateRequest>,
+) -> Result<Response, (StatusCode, String)> {
+    let payload = json!({
+        "model": request.model.as_deref().unwrap_or(DEFAULT_TRANSLATE_MODEL),
+        "stream": true,
+        "messages": [
+            { "role": "system", "content": build_system_prompt(&request) },
+            { "role": "user", "content": request.text },
+        ],
+    });
+    let body = serde_json::to_vec(&payload)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    // 优先使用客户端传入的 Authorization，否则使用服务端配置的 API 密钥；启用客户端密钥时客户端密钥不转发
+    let authorization = match headers.get(AUTHORIZATION) {
+        Some(value) if !keys::client_auth_enabled(&state) => value.clone(),
+        _ => axum::http::HeaderValue::from_str(&format!("Bearer {}", state.api_key))
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?,
+    };
+
+    // 生成的对话请求与对话接口经过相同的处理阶段：别名与白名单、路由脚本、脱敏等
+    let scope = client_scope(&headers, identity.as_deref(), &state.api_key);
+    let quota_client = state
+        .token_quota
+        .is_enabled()
+        .then(|| quota::quota_client(identity.as_deref(), &scope));
+    let quota_warning = match &quota_client {
+        Some(quota_client) => state.token_quota.check(quota_client)?,
+        None => None,
+    };
+    let mut ctx = StageContext {
+        state: &state,
+        route: "translate",
+        headers: &headers,
+        scope: &scope,
+        upstream_url: UPSTREAM_CHAT_COMPLETIONS_URL.to_string(),
+        authorization,
+        header_rewrites: Vec::new(),
+        strategy: state.context.strategy_for(&headers)?,
+        abuse_signals: Vec::new(),
+        token_map: TokenMap::default(),
+        assignment: None,
+        emulate: false,
+    };
+    let mut body = state.pipeline.run(&mut ctx, Bytes::from(body)).await?;
+    if quota_client.is_some() || ctx.assignment.is_some() {
+        body = quota::request_usage(body)?.0;
+    }
+    let StageContext {
+        upstream_url,
+        authorization,
+        header_rewrites,
+        abuse_signals,
+        token_map,
+        assignment,
+        ..
+    } = ctx;
+
+    let mut request_headers = HeaderMap::new();
+    request_headers.insert(AUTHORIZATION, authorization);
+    request_headers.insert(
+        CONTENT_TYPE,
+        axum::http::HeaderValue::from_static("application/json"),
+    );
+    scripts::apply_headers(&header_rewrites, &mut request_headers);
+
+    let started = Instant::now();
+    let response = state
+        .http_client
+        .post(upstream_url)
+        .headers(request_headers)
+        .body(body)
+        .send()
+        .await
+        .map_err(|e| (StatusCode::BAD_GATEWAY, e.to_string()))?;
+
+    // 上游报错时原样返回错误内容
+    let status = response.status();
+    if let Some(assignment) = &assignment {
+        assignment.record(status, started.elapsed());
+    }
+    if !status.is_success() {
+        let body = response
+            .text()
+            .await
+            .map_err(|e| (StatusCode::BAD_GATEWAY, e.to_string()))?;
+        return Err((status, body));
+    }
+
+    // 将上游 SSE 转换为纯文本译文流，可逆脱敏时先还原占位符；启用配额时先旁路计入用量
+    let mut parser = SseParser::default();
+    let mut restorer = (state.redaction_mode == RedactionMode::Reversible && !token_map.is_empty())
+        .then(|| StreamRestorer::new(token_map));
+    let upstream = response.bytes_stream();
+    let upstream = match quota_client {
+        Some(quota_client) => state
+            .token_quota
+            .clone()
+            .record_usage(quota_client, upstream, true)
+            .boxed(),
+        None => upstream.boxed(),
+    };
+    let upstream = match &assignment {
+        Some(assignment) => assignment.clone().record_usage(upstream, true).boxed(),
+        None => upstream,
+    };
+    let stream = upstream
+        .map_ok(move |chunk| {
+            let text: String = parser
+                .push(&chunk)
+                .into_iter()
+                .filter_map(|event| {
+                    let mut chunk = ChatCompletionResponse::parse(event.data?.as_bytes())?;
+                    if let Some(restorer) = &mut restorer {
+                        restorer.restore(&mut chunk);
+                    }
+                    chunk.delta_content().map(str::to_string)
+                })
+                .collect();
+            Bytes::from(text)
+        })
+        .try_filter(|text| ready(!text.is_empty()));
+
+    let mut builder = Response::builder().header(CONTENT_TYPE, "text/plain; charset=utf-8");
+    if !abuse_signals.is_empty() {
+        builder = builder.header(ABUSE_SIGNALS_HEADER, abuse_signals.join(","));
+    }
+    if let Some(value) = quota_warning {
+        builder = builder.header(QUOTA_WARNING_HEADER, value);
+    }
+    if let Some(value) = assignment.as_ref().and_then(Assignment::header_value) {
+        builder = builder.header(EXPERIMENT_HEADER, value);
+    }
+    builder
+        .body(Body::from_stream(stream))
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+}
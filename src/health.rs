@@ -0,0 +1,175 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use axum::{Json, extract::State};
+use reqwest::Client;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::AppState;
+
+/// 上游模型列表接口地址，开销小，用于探测可用性与校验密钥
+pub const UPSTREAM_MODELS_URL: &str = "https://api.deepseek.com/models";
+
+/// 计算成功率与延迟分位数时保留的最近探测次数
+const WINDOW_SIZE: usize = 100;
+
+/// 默认探测间隔
+const DEFAULT_PROBE_INTERVAL_SECS: u64 = 30;
+
+/// 单次探测超时
+const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+struct Sample {
+    success: bool,
+    latency: Duration,
+}
+
+#[derive(Default)]
+struct Window {
+    samples: VecDeque<Sample>,
+    last_error: Option<String>,
+    last_checked_at: Option<u64>,
+}
+
+/// 上游健康探测
+///
+/// 后台定期请求上游模型列表，按滑动窗口统计成功率与延迟分位数，
+/// 通过 `GET /status/providers` 查看。
+pub struct ProviderHealth {
+    interval: Duration,
+    window: Mutex<Window>,
+}
+
+/// 上游的探测结果
+#[derive(Serialize, ToSchema)]
+pub struct ProviderStatus {
+    name: &'static str,
+    healthy: bool,
+    /// 窗口内的探测次数
+    samples: usize,
+    success_rate: Option<f64>,
+    latency_ms: Option<LatencyPercentiles>,
+    last_error: Option<String>,
+    /// 最近一次探测的 Unix 时间戳（秒）
+    last_checked_at: Option<u64>,
+}
+
+/// 探测延迟的分位数（毫秒）
+#[derive(Serialize, ToSchema)]
+pub struct LatencyPercentiles {
+    p50: u64,
+    p90: u64,
+    p99: u64,
+}
+
+impl ProviderHealth {
+    /// 读取 `HEALTH_PROBE_INTERVAL_SECS`，默认 30 秒
+    pub fn from_env() -> anyhow::Result<Self> {
+        let interval = match std::env::var("HEALTH_PROBE_INTERVAL_SECS") {
+            Ok(value) => value.parse()?,
+            Err(_) => DEFAULT_PROBE_INTERVAL_SECS,
+        };
+        Ok(Self {
+            interval: Duration::from_secs(interval),
+            window: Mutex::default(),
+        })
+    }
+
+    /// 启动后台探测任务
+    pub fn spawn_prober(self: Arc<Self>, client: Client, api_key: String) {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(self.interval);
+            loop {
+                interval.tick().await;
+
+                let started = Instant::now();
+                let result = client
+                    .get(UPSTREAM_MODELS_URL)
+                    .bearer_auth(&api_key)
+                    .timeout(PROBE_TIMEOUT)
+                    .send()
+                    .await;
+                let latency = started.elapsed();
+                let error = match result {
+                    Ok(response) if response.status().is_success() => None,
+                    Ok(response) => Some(format!("上游返回 {}", response.status())),
+                    Err(e) => Some(e.to_string()),
+                };
+                if let Some(error) = &error {
+                    tracing::warn!(error = %error, "上游健康探测失败");
+                }
+                self.record(error, latency);
+            }
+        });
+    }
+
+    fn record(&self, error: Option<String>, latency: Duration) {
+        let mut window = self.window.lock().unwrap();
+        if window.samples.len() == WINDOW_SIZE {
+            window.samples.pop_front();
+        }
+        window.samples.push_back(Sample {
+            success: error.is_none(),
+            latency,
+        });
+        if error.is_some() {
+            window.last_error = error;
+        }
+        window.last_checked_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .ok()
+            .map(|elapsed| elapsed.as_secs());
+    }
+
+    fn status(&self) -> ProviderStatus {
+        let window = self.window.lock().unwrap();
+        let samples = window.samples.len();
+        let successes = window
+            .samples
+            .iter()
+            .filter(|sample| sample.success)
+            .count();
+        let success_rate = (samples > 0).then(|| successes as f64 / samples as f64);
+
+        // 延迟只统计成功的探测，失败多为超时或连接错误，会掩盖真实延迟
+        let mut latencies: Vec<u64> = window
+            .samples
+            .iter()
+            .filter(|sample| sample.success)
+            .map(|sample| sample.latency.as_millis() as u64)
+            .collect();
+        latencies.sort_unstable();
+        let percentile = |p: usize| latencies[(latencies.len() * p).div_ceil(100).max(1) - 1];
+        let latency_ms = (!latencies.is_empty()).then(|| LatencyPercentiles {
+            p50: percentile(50),
+            p90: percentile(90),
+            p99: percentile(99),
+        });
+
+        ProviderStatus {
+            name: "deepseek",
+            // 以最近一次探测结果判断当前是否可用
+            healthy: window.samples.back().is_some_and(|sample| sample.success),
+            samples,
+            success_rate,
+            latency_ms,
+            last_error: window.last_error.clone(),
+            last_checked_at: window.last_checked_at,
+        }
+    }
+}
+
+/// 查看上游健康状态
+#[utoipa::path(
+    get,
+    path = "/status/providers",
+    tag = "status",
+    responses((status = 200, description = "各上游的探测结果", body = Vec<ProviderStatus>)),
+)]
+pub async fn handle_provider_status(State(state): State<AppState>) -> Json<Vec<ProviderStatus>> {
+    Json(vec![state.provider_health.status()])
+}
@@ -0,0 +1,195 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::Context;
+use futures::future::join_all;
+use reqwest::{Certificate, Client, Identity, NoProxy, Proxy};
+
+use crate::{dns::CachingResolver, health::UPSTREAM_MODELS_URL};
+
+/// 单次预热请求的超时
+const WARMUP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// 读取可选的数值环境变量
+fn parse_env<T>(var: &str) -> anyhow::Result<Option<T>>
+where
+    T: std::str::FromStr,
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    std::env::var(var)
+        .ok()
+        .map(|value| {
+            value
+                .trim()
+                .parse()
+                .with_context(|| format!("{} 无效: {}", var, value))
+        })
+        .transpose()
+}
+
+/// 访问上游使用的 HTTP 客户端配置
+///
+/// `UPSTREAM_PROXY` 为 HTTP(S) 或 SOCKS5 代理地址，可在地址中携带 `用户名:密码@`，`socks5://` 在本地解析上游域名，
+/// `socks5h://` 由代理解析；`UPSTREAM_NO_PROXY` 为不走代理的主机，
+/// 逗号分隔，支持域名后缀与 CIDR。未配置 `UPSTREAM_PROXY` 时沿用系统的 `HTTPS_PROXY` / `NO_PROXY` 等环境变量。
+///
+/// 上游是使用私有 PKI 的内部网关时，`UPSTREAM_CA_BUNDLE` 指定额外信任的根证书（PEM，可包含多张），
+/// `UPSTREAM_CLIENT_CERT` / `UPSTREAM_CLIENT_KEY` 指定双向 TLS 的客户端证书与 PKCS#8 私钥（PEM），两者需同时配置。
+///
+/// 连接池：`UPSTREAM_POOL_MAX_IDLE_PER_HOST` 为每个主机保留的空闲连接数，`UPSTREAM_POOL_IDLE_TIMEOUT_SECS`
+/// 为空闲连接的保留秒数（默认 90，0 表示不过期），`UPSTREAM_TCP_KEEPALIVE_SECS` 开启 TCP keepalive，
+/// `UPSTREAM_HTTP2_ADAPTIVE_WINDOW=true` 开启 HTTP/2 自适应流控窗口。
+///
+/// 域名解析使用带缓存的解析器，见 [`CachingResolver`]。
+pub struct HttpClientConfig {
+    proxy: Option<Proxy>,
+    resolver: Arc<CachingResolver>,
+    root_certificates: Vec<Certificate>,
+    identity: Option<Identity>,
+    pool_max_idle_per_host: Option<usize>,
+    pool_idle_timeout_secs: Option<u64>,
+    tcp_keepalive_secs: Option<u64>,
+    http2_adaptive_window: bool,
+}
+
+impl HttpClientConfig {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let proxy = match std::env::var("UPSTREAM_PROXY") {
+            Ok(url) => {
+                let url = url.trim();
+                let scheme = url.split_once("://").map(|(scheme, _)| scheme);
+                if !matches!(scheme, Some("http" | "https" | "socks5" | "socks5h")) {
+                    anyhow::bail!(
+                        "UPSTREAM_PROXY 仅支持 http、https、socks5 与 socks5h 代理: {}",
+                        url
+                    );
+                }
+                let no_proxy = std::env::var("UPSTREAM_NO_PROXY")
+                    .ok()
+                    .and_then(|config| NoProxy::from_string(&config));
+                Some(
+                    Proxy::all(url)
+                        .with_context(|| format!("UPSTREAM_PROXY 无效: {}", url))?
+                        .no_proxy(no_proxy),
+                )
+            }
+            Err(_) => None,
+        };
+
+        let root_certificates = match std::env::var("UPSTREAM_CA_BUNDLE") {
+            Ok(path) => {
+                let certificates = Certificate::from_pem_bundle(&read_pem(&path)?)
+                    .with_context(|| format!("{} 不是有效的 PEM 证书", path))?;
+                if certificates.is_empty() {
+                    anyhow::bail!("{} 中没有证书", path);
+                }
+                certificates
+            }
+            Err(_) => Vec::new(),
+        };
+
+        let identity = match (
+            std::env::var("UPSTREAM_CLIENT_CERT"),
+            std::env::var("UPSTREAM_CLIENT_KEY"),
+        ) {
+            (Ok(cert_path), Ok(key_path)) => Some(
+                Identity::from_pkcs8_pem(&read_pem(&cert_path)?, &read_pem(&key_path)?)
+                    .context("客户端证书或私钥无效，私钥须为 PKCS#8 格式")?,
+            ),
+            (Err(_), Err(_)) => None,
+            _ => anyhow::bail!("UPSTREAM_CLIENT_CERT 与 UPSTREAM_CLIENT_KEY 需同时配置"),
+        };
+
+        Ok(Self {
+            proxy,
+            resolver: Arc::new(CachingResolver::from_env()?),
+            root_certificates,
+            identity,
+            pool_max_idle_per_host: parse_env("UPSTREAM_POOL_MAX_IDLE_PER_HOST")?,
+            pool_idle_timeout_secs: parse_env("UPSTREAM_POOL_IDLE_TIMEOUT_SECS")?,
+            tcp_keepalive_secs: parse_env("UPSTREAM_TCP_KEEPALIVE_SECS")?,
+            http2_adaptive_window: std::env::var("UPSTREAM_HTTP2_ADAPTIVE_WINDOW")
+                .map(|value| value == "true")
+                .unwrap_or(false),
+        })
+    }
+
+    pub fn build(self) -> anyhow::Result<Client> {
+        let mut builder = Client::builder().dns_resolver(self.resolver);
+        if let Some(proxy) = self.proxy {
+            builder = builder.proxy(proxy);
+        }
+        for certificate in self.root_certificates {
+            builder = builder.add_root_certificate(certificate);
+        }
+        if let Some(identity) = self.identity {
+            builder = builder.identity(identity);
+        }
+        if let Some(max) = self.pool_max_idle_per_host {
+            builder = builder.pool_max_idle_per_host(max);
+        }
+        if let Some(secs) = self.pool_idle_timeout_secs {
+            builder = builder.pool_idle_timeout((secs > 0).then(|| Duration::from_secs(secs)));
+        }
+        if let Some(secs) = self.tcp_keepalive_secs {
+            builder = builder.tcp_keepalive(Duration::from_secs(secs));
+        }
+        Ok(builder
+            .http2_adaptive_window(self.http2_adaptive_window)
+            .build()?)
+    }
+}
+
+/// 空闲连接预热
+///
+/// 配置 `UPSTREAM_WARMUP_INTERVAL_SECS` 后定期并行请求上游模型列表 `UPSTREAM_WARMUP_CONNECTIONS` 次（默认 1），
+/// 保持连接池中有已完成 TLS 握手的连接，空闲一段时间后的首个请求无需重新建连。间隔应小于空闲连接的保留时间。
+pub struct PoolWarmup {
+    interval: Option<Duration>,
+    connections: usize,
+}
+
+impl PoolWarmup {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let interval = parse_env::<u64>("UPSTREAM_WARMUP_INTERVAL_SECS")?
+            .filter(|secs| *secs > 0)
+            .map(Duration::from_secs);
+        let connections = parse_env("UPSTREAM_WARMUP_CONNECTIONS")?.unwrap_or(1);
+        Ok(Self {
+            interval,
+            connections,
+        })
+    }
+
+    /// 启动后台预热任务，未配置间隔时不启动
+    pub fn spawn(self, client: Client, api_key: String) {
+        let Some(period) = self.interval else {
+            return;
+        };
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            loop {
+                interval.tick().await;
+                let requests = (0..self.connections).map(|_| {
+                    client
+                        .get(UPSTREAM_MODELS_URL)
+                        .bearer_auth(&api_key)
+                        .timeout(WARMUP_TIMEOUT)
+                        .send()
+                });
+                for result in join_all(requests).await {
+                    // 只需建立连接，响应体读完后连接归还连接池
+                    match result {
+                        Ok(response) => {
+                            let _ = response.bytes().await;
+                        }
+                        Err(e) => tracing::debug!("上游连接预热失败: {}", e),
+                    }
+                }
+            }
+        });
+    }
+}
+
+fn read_pem(path: &str) -> anyhow::Result<Vec<u8>> {
+    std::fs::read(path).with_context(|| format!("读取 {} 失败", path))
+}
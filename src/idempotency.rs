@@ -0,0 +1,449 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    body::{Body, Bytes},
+    http::{HeaderMap, HeaderValue, StatusCode},
+    response::Response,
+};
+use futures::{Stream, StreamExt};
+use sha2::{Digest, Sha256};
+use tokio::sync::{mpsc, watch};
+
+/// 幂等键请求头
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+
+/// 标记响应来自重复请求复用的响应头
+pub const IDEMPOTENT_REPLAYED_HEADER: &str = "idempotent-replayed";
+
+/// 未配置时已完成结果的保留时间
+const DEFAULT_TTL_SECS: u64 = 300;
+
+/// 未配置时单个响应可记录的最大字节数
+const DEFAULT_MAX_BYTES: usize = 8 * 1024 * 1024;
+
+/// 清理过期结果的间隔
+const SWEEP_INTERVAL: Duration = Duration::from_secs(30);
+
+enum Progress {
+    /// 等待上游响应或仍在接收响应体
+    InFlight,
+    /// 响应体已完整接收，记录完成时间用于过期清理
+    Completed(Instant),
+    /// 原始请求失败或响应超过记录上限，后续的重复请求不应复用
+    Failed,
+    /// 原始请求在拿到上游响应前被拒绝，等待中的重复请求收到同样的错误
+    Rejected(StatusCode, String),
+}
+
+struct Shared {
+    response: Option<(StatusCode, HeaderMap)>,
+    chunks: Vec<Bytes>,
+    /// 已记录的响应体字节数
+    bytes: usize,
+    progress: Progress,
+}
+
+/// 同一幂等键对应的一次上游调用，响应体分块记录下来供所有订阅者读取
+struct Entry {
+    /// 请求体的 SHA-256，幂等键相同但请求体不同的请求返回 422
+    fingerprint: [u8; 32],
+    shared: Mutex<Shared>,
+    version: watch::Sender<u64>,
+}
+
+impl Entry {
+    fn update(&self, f: impl FnOnce(&mut Shared)) {
+        f(&mut self.shared.lock().unwrap());
+        self.version.send_modify(|version| *version += 1);
+    }
+
+    /// 已完成且超过保留时间
+    fn expired(&self, ttl: Duration) -> bool {
+        match self.shared.lock().unwrap().progress {
+            Progress::Completed(at) => at.elapsed() >= ttl,
+            _ => false,
+        }
+    }
+}
+
+/// 幂等请求存储
+///
+/// 同一客户端（按 Authorization 区分）携带相同 `Idempotency-Key` 的请求共享同一次上游调用：
+/// 并发的重复请求从头订阅正在进行的响应流，已完成的结果在 `IDEMPOTENCY_TTL_SECS` 秒内直接重放，过期结果由后台任务定期清理。
+/// 响应体超过 `IDEMPOTENCY_MAX_BYTES` 时不再记录，该幂等键之后的请求重新调用上游。
+pub struct IdempotencyStore {
+    entries: Mutex<HashMap<String, Arc<Entry>>>,
+    ttl: Duration,
+    max_bytes: usize,
+}
+
+/// 开始处理一个携带幂等键的请求
+pub enum Begin {
+    /// 首个请求，负责调用上游
+    Leader(Leader),
+    /// 重复请求，直接复用首个请求的响应
+    Follower(Replay),
+}
+
+impl IdempotencyStore {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let ttl = match std::env::var("IDEMPOTENCY_TTL_SECS") {
+            Ok(value) => value.parse()?,
+            Err(_) => DEFAULT_TTL_SECS,
+        };
+        let max_bytes = match std::env::var("IDEMPOTENCY_MAX_BYTES") {
+            Ok(value) => value.parse()?,
+            Err(_) => DEFAULT_MAX_BYTES,
+        };
+        Ok(Self {
+            entries: Mutex::default(),
+            ttl: Duration::from_secs(ttl),
+            max_bytes,
+        })
+    }
+
+    /// 启动后台清理任务，定期移除过期的已完成结果
+    pub fn spawn_sweeper(self: Arc<Self>) {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+            loop {
+                interval.tick().await;
+                self.entries
+                    .lock()
+                    .unwrap()
+                    .retain(|_, entry| !entry.expired(self.ttl));
+            }
+        });
+    }
+
+    /// 开始处理携带幂等键的请求，幂等键已用于不同的请求体时返回 422
+    pub fn begin(
+        self: &Arc<Self>,
+        scope: &str,
+        key: &str,
+        body: &[u8],
+    ) -> Result<Begin, (StatusCode, String)> {
+        let key = format!("{}\n{}", scope, key);
+        let fingerprint: [u8; 32] = Sha256::digest(body).into();
+        let mut entries = self.entries.lock().unwrap();
+
+        // 过期但尚未被清理的结果视为不存在
+        if let Some(entry) = entries.get(&key)
+            && !entry.expired(self.ttl)
+        {
+            if entry.fingerprint != fingerprint {
+                return Err((
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    "幂等键已用于不同的请求体".to_string(),
+                ));
+            }
+            tracing::info!("复用幂等键相同的请求结果");
+            return Ok(Begin::Follower(Replay(entry.clone())));
+        }
+
+        let entry = Arc::new(Entry {
+            fingerprint,
+            shared: Mutex::new(Shared {
+                response: None,
+                chunks: Vec::new(),
+                bytes: 0,
+                progress: Progress::InFlight,
+            }),
+            version: watch::Sender::new(0),
+        });
+        entries.insert(key.clone(), entry.clone());
+        Ok(Begin::Leader(Leader {
+            store: self.clone(),
+            key,
+            entry,
+            armed: true,
+        }))
+    }
+
+    fn remove(&self, key: &str, entry: &Arc<Entry>) {
+        let mut entries = self.entries.lock().unwrap();
+        if entries
+            .get(key)
+            .is_some_and(|current| Arc::ptr_eq(current, entry))
+        {
+            entries.remove(key);
+        }
+    }
+}
+
+/// 首个请求的记录器，在拿到上游响应前被丢弃（出错返回）时将该幂等键标记为失败
+pub struct Leader {
+    store: Arc<IdempotencyStore>,
+    key: String,
+    entry: Arc<Entry>,
+    armed: bool,
+}
+
+impl Leader {
+    /// 在后台接收上游响应体，记录后转发给首个请求，首个请求的客户端断开不影响其他重复请求；
+    /// 响应体超过记录上限时丢弃记录，只继续转发给首个请求
+    pub fn record<S, E>(mut self, status: StatusCode, headers: HeaderMap, stream: S) -> Response
+    where
+        S: Stream<Item = Result<Bytes, E>> + Send + 'static,
+        E: std::fmt::Display + Send,
+    {
+        self.armed = false;
+        self.entry
+            .update(|shared| shared.response = Some((status, headers.clone())));
+
+        let store = self.store.clone();
+        let key = std::mem::take(&mut self.key);
+        let entry = self.entry.clone();
+        let (sender, receiver) = mpsc::channel(16);
+        tokio::spawn(async move {
+            let mut stream = std::pin::pin!(stream);
+            let mut recording = true;
+            while let Some(chunk) = stream.next().await {
+                match chunk {
+                    Ok(bytes) => {
+                        if recording {
+                            let size = entry.shared.lock().unwrap().bytes + bytes.len();
+                            if size > store.max_bytes {
+                                tracing::info!(
+                                    "幂等请求的响应超过 {} 字节，不再记录",
+                                    store.max_bytes
+                                );
+                                recording = false;
+                                entry.update(|shared| {
+                                    shared.chunks = Vec::new();
+                                    shared.progress = Progress::Failed;
+                                });
+                                store.remove(&key, &entry);
+                            } else {
+                                entry.update(|shared| {
+                                    shared.bytes = size;
+                                    shared.chunks.push(bytes.clone());
+                                });
+                            }
+                        }
+                        // 首个请求的客户端已断开且不再记录时停止接收
+                        if sender.send(Ok(bytes)).await.is_err() && !recording {
+                            return;
+                        }
+                    }
+                    Err(e) => {
+                        tracing::warn!("幂等请求的上游响应中断: {}", e);
+                        entry.update(|shared| shared.progress = Progress::Failed);
+                        store.remove(&key, &entry);
+                        let _ = sender.send(Err(std::io::Error::other(e.to_string()))).await;
+                        return;
+                    }
+                }
+            }
+            if recording {
+                entry.update(|shared| shared.progress = Progress::Completed(Instant::now()));
+            }
+        });
+
+        let stream = futures::stream::unfold(receiver, |mut receiver| async move {
+            receiver.recv().await.map(|chunk| (chunk, receiver))
+        });
+        let mut response = Response::new(Body::from_stream(stream));
+        *response.status_mut() = status;
+        *response.headers_mut() = headers;
+        response
+    }
+
+    /// 记录拿到上游响应前的错误，等待中的重复请求返回同样的状态码与内容；之后的请求重新调用上游
+    pub fn fail(mut self, (status, body): &(StatusCode, String)) {
+        self.armed = false;
+        self.entry
+            .update(|shared| shared.progress = Progress::Rejected(*status, body.clone()));
+        self.store.remove(&self.key, &self.entry);
+    }
+}
+
+impl Drop for Leader {
+    fn drop(&mut self) {
+        if self.armed {
+            self.entry
+                .update(|shared| shared.progress = Progress::Failed);
+            self.store.remove(&self.key, &self.entry);
+        }
+    }
+}
+
+/// 重复请求，复用首个请求记录的响应
+pub struct Replay(Arc<Entry>);
+
+impl Replay {
+    /// 等待首个请求拿到上游响应头后构建响应，原始请求被拒绝时返回同样的错误，其他失败返回 502
+    pub async fn into_response(self) -> Result<Response, (StatusCode, String)> {
+        let mut receiver = self.0.version.subscribe();
+        let (status, headers) = loop {
+            receiver.borrow_and_update();
+            {
+                let shared = self.0.shared.lock().unwrap();
+                match &shared.progress {
+                    Progress::Failed => {
+                        return Err((
+                            StatusCode::BAD_GATEWAY,
+                            "幂等键相同的原始请求失败".to_string(),
+                        ));
+                    }
+                    Progress::Rejected(status, body) => return Err((*status, body.clone())),
+                    _ => {}
+                }
+                if let Some(response) = &shared.response {
+                    break response.clone();
+                }
+            }
+            if receiver.changed().await.is_err() {
+                return Err((
+                    StatusCode::BAD_GATEWAY,
+                    "幂等键相同的原始请求失败".to_string(),
+                ));
+            }
+        };
+
+        let mut response = build_response(self.0, status, headers);
+        response
+            .headers_mut()
+            .insert(IDEMPOTENT_REPLAYED_HEADER, HeaderValue::from_static("true"));
+        Ok(response)
+    }
+}
+
+/// 从记录中读取响应体：从第一个分块开始，跟随新到达的分块直到完成
+fn build_response(entry: Arc<Entry>, status: StatusCode, headers: HeaderMap) -> Response {
+    let receiver = entry.version.subscribe();
+    let stream = futures::stream::unfold(
+        (entry, 0usize, receiver),
+        |(entry, index, mut receiver)| async move {
+            loop {
+                receiver.borrow_and_update();
+                {
+                    let shared = entry.shared.lock().unwrap();
+                    if let Some(chunk) = shared.chunks.get(index).cloned() {
+                        drop(shared);
+                        return Some((Ok(chunk), (entry, index + 1, receiver)));
+                    }
+                    match shared.progress {
+                        Progress::InFlight => {}
+                        Progress::Completed(_) => return None,
+                        Progress::Failed | Progress::Rejected(..) => {
+                            drop(shared);
+                            let error = std::io::Error::other("原始请求的上游响应中断");
+                            return Some((Err(error), (entry, index, receiver)));
+                        }
+                    }
+                }
+                if receiver.changed().await.is_err() {
+                    return None;
+                }
+            }
+        },
+    );
+
+    let mut response = Response::new(Body::from_stream(stream));
+    *response.status_mut() = status;
+    *response.headers_mut() = headers;
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store() -> Arc<IdempotencyStore> {
+        Arc::new(IdempotencyStore {
+            entries: Mutex::default(),
+            ttl: Duration::from_secs(60),
+            max_bytes: DEFAULT_MAX_BYTES,
+        })
+    }
+
+    fn begin_leader(store: &Arc<IdempotencyStore>, body: &[u8]) -> Leader {
+        match store.begin("scope", "key", body).unwrap() {
+            Begin::Leader(leader) => leader,
+            Begin::Follower(_) => panic!("应为首个请求"),
+        }
+    }
+
+    fn follower(store: &Arc<IdempotencyStore>, body: &[u8]) -> Replay {
+        match store.begin("scope", "key", body).unwrap() {
+            Begin::Follower(replay) => replay,
+            Begin::Leader(_) => panic!("应为重复请求"),
+        }
+    }
+
+    async fn body_text(response: Response) -> String {
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        String::from_utf8(bytes.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn rejects_same_key_with_different_body() {
+        let store = store();
+        let _leader = begin_leader(&store, b"a");
+        let Err((status, _)) = store.begin("scope", "key", b"b") else {
+            panic!("请求体不同时应拒绝");
+        };
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        // 不同客户端的同名幂等键互不影响
+        assert!(matches!(
+            store.begin("other", "key", b"b"),
+            Ok(Begin::Leader(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn replays_leader_response_to_followers() {
+        let store = store();
+        let leader = begin_leader(&store, b"a");
+        let waiting = tokio::spawn(follower(&store, b"a").into_response());
+
+        let chunks: Vec<Result<Bytes, String>> =
+            vec![Ok(Bytes::from("hello ")), Ok(Bytes::from("world"))];
+        let response = leader.record(
+            StatusCode::OK,
+            HeaderMap::new(),
+            futures::stream::iter(chunks),
+        );
+        assert_eq!(body_text(response).await, "hello world");
+
+        let replayed = waiting.await.unwrap().unwrap();
+        assert_eq!(replayed.status(), StatusCode::OK);
+        assert_eq!(replayed.headers()[IDEMPOTENT_REPLAYED_HEADER], "true");
+        assert_eq!(body_text(replayed).await, "hello world");
+
+        // 完成后的请求直接重放
+        let replayed = follower(&store, b"a").into_response().await.unwrap();
+        assert_eq!(body_text(replayed).await, "hello world");
+    }
+
+    #[tokio::test]
+    async fn passes_leader_error_to_waiting_followers() {
+        let store = store();
+        let leader = begin_leader(&store, b"a");
+        let waiting = tokio::spawn(follower(&store, b"a").into_response());
+        tokio::task::yield_now().await;
+
+        leader.fail(&(StatusCode::FORBIDDEN, "滥用检测拒绝".to_string()));
+        let Err(error) = waiting.await.unwrap() else {
+            panic!("原始请求失败时重复请求应返回错误");
+        };
+        assert_eq!(error, (StatusCode::FORBIDDEN, "滥用检测拒绝".to_string()));
+
+        // 失败的幂等键之后重新调用上游；未记录错误就被丢弃时返回 502
+        let leader = begin_leader(&store, b"a");
+        let waiting = tokio::spawn(follower(&store, b"a").into_response());
+        tokio::task::yield_now().await;
+        drop(leader);
+        let Err((status, _)) = waiting.await.unwrap() else {
+            panic!("原始请求失败时重复请求应返回错误");
+        };
+        assert_eq!(status, StatusCode::BAD_GATEWAY);
+    }
+}
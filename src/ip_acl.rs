@@ -0,0 +1,298 @@
+use std::net::{IpAddr, SocketAddr};
+
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{
+        HeaderName, StatusCode,
+        header::{FORWARDED, HOST},
+    },
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use ipnet::IpNet;
+
+use crate::AppState;
+
+/// 访问控制的决策数，标签 `group`（`api` / `admin`）与 `decision`（`allow` / `deny`）
+const IP_ACL_DECISIONS_TOTAL: &str = "ip_acl_decisions_total";
+
+/// 一组 CIDR 允许/拒绝规则
+#[derive(Default)]
+struct Rules {
+    allow: Vec<IpNet>,
+    deny: Vec<IpNet>,
+}
+
+impl Rules {
+    fn from_env(prefix: &str) -> anyhow::Result<Self> {
+        Ok(Self {
+            allow: parse_cidrs(&format!("{}IP_ALLOWLIST", prefix))?,
+            deny: parse_cidrs(&format!("{}IP_DENYLIST", prefix))?,
+        })
+    }
+
+    fn is_empty(&self) -> bool {
+        self.allow.is_empty() && self.deny.is_empty()
+    }
+
+    /// 拒绝列表优先；配置了允许列表时只放行列表中的地址
+    fn permits(&self, ip: IpAddr) -> bool {
+        if self.deny.iter().any(|net| net.contains(&ip)) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|net| net.contains(&ip))
+    }
+}
+
+/// 读取逗号分隔的 CIDR 列表，单个地址视为 /32 或 /128
+fn parse_cidrs(var: &str) -> anyhow::Result<Vec<IpNet>> {
+    let Ok(config) = std::env::var(var) else {
+        return Ok(Vec::new());
+    };
+    config
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(|entry| {
+            entry
+                .parse::<IpNet>()
+                .or_else(|_| entry.parse::<IpAddr>().map(IpNet::from))
+                .map_err(|_| anyhow::anyhow!("{} 中的地址无效: {}", var, entry))
+        })
+        .collect()
+}
+
+/// 网络访问控制
+///
+/// `IP_ALLOWLIST` / `IP_DENYLIST` 作用于所有接口，`ADMIN_IP_ALLOWLIST` / `ADMIN_IP_DENYLIST` 额外作用于管理接口，
+/// 均为逗号分隔的 CIDR，在鉴权之前检查。默认按连接的对端地址判断，部署在反向代理之后时设置
+/// `TRUST_X_FORWARDED_FOR=true` 改用 `X-Forwarded-For` 中由可信代理追加的地址：`TRUSTED_PROXY_HOPS`（默认 1）
+/// 为可信代理的层数，取从右数第该层数个地址，更靠左的地址由客户端控制，不予采信。
+/// 按国家屏蔽依赖 CDN 提供的国家代码请求头：`GEO_COUNTRY_HEADER`（如 `cf-ipcountry`）与 `BLOCKED_COUNTRIES`（逗号分隔）。
+/// 配置了允许或拒绝列表时，无法确定客户端地址的请求（如来自消息队列的请求）被拒绝；
+/// 没有对端地址的请求即使带有 `X-Forwarded-For` 也不采信。
+#[derive(Default)]
+pub struct IpAcl {
+    api: Rules,
+    admin: Rules,
+    /// 可信代理的层数，为 0 时不采信 `X-Forwarded-For`
+    trusted_hops: usize,
+    country_header: Option<HeaderName>,
+    blocked_countries: Vec<String>,
+}
+
+impl IpAcl {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let country_header = std::env::var("GEO_COUNTRY_HEADER")
+            .ok()
+            .map(|name| HeaderName::try_from(name.trim()))
+            .transpose()?;
+        let blocked_countries = std::env::var("BLOCKED_COUNTRIES")
+            .map(|config| {
+                config
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|s| !s.is_empty())
+                    .map(str::to_ascii_uppercase)
+                    .collect()
+            })
+            .unwrap_or_default();
+        let trust_forwarded = std::env::var("TRUST_X_FORWARDED_FOR")
+            .map(|value| value == "true")
+            .unwrap_or(false);
+        let trusted_hops = match std::env::var("TRUSTED_PROXY_HOPS") {
+            Ok(value) => value.trim().parse()?,
+            Err(_) => 1,
+        };
+        if trust_forwarded && trusted_hops == 0 {
+            anyhow::bail!("TRUSTED_PROXY_HOPS 至少为 1");
+        }
+        Ok(Self {
+            api: Rules::from_env("")?,
+            admin: Rules::from_env("ADMIN_")?,
+            trusted_hops: if trust_forwarded { trusted_hops } else { 0 },
+            country_header,
+            blocked_countries,
+        })
+    }
+
+    /// 描述客户端来源的请求头，不是经由 HTTP 连接到达的请求（如队列消息）中的这些头由调用方伪造，需要移除
+    pub fn client_headers(&self) -> Vec<HeaderName> {
+        let mut headers = vec![
+            HeaderName::from_static("x-forwarded-for"),
+            HeaderName::from_static("x-real-ip"),
+            FORWARDED,
+            HOST,
+        ];
+        headers.extend(self.country_header.clone());
+        headers
+    }
+
+    /// 客户端地址，IPv4 映射的 IPv6 地址按 IPv4 处理
+    ///
+    /// 采信 `X-Forwarded-For` 时取最近的可信代理记录的地址；地址数少于可信代理层数说明请求未经过全部代理，
+    /// 改用对端地址。没有对端地址的请求不是经由可信代理到达的，`X-Forwarded-For` 同样不予采信。
+    fn client_ip(&self, request: &Request) -> Option<IpAddr> {
+        let peer = request
+            .extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip().to_canonical())?;
+        if self.trusted_hops > 0
+            && let Some(ip) = request
+                .headers()
+                .get_all("x-forwarded-for")
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .flat_map(|value| value.split(','))
+                .rev()
+                .nth(self.trusted_hops - 1)
+                .and_then(|ip| ip.trim().parse::<IpAddr>().ok())
+        {
+            return Some(ip.to_canonical());
+        }
+        Some(peer)
+    }
+
+    /// 请求来自被屏蔽的国家时返回国家代码
+    fn blocked_country<'a>(&self, request: &'a Request) -> Option<&'a str> {
+        let header = self.country_header.as_ref()?;
+        let country = request.headers().get(header)?.to_str().ok()?.trim();
+        self.blocked_countries
+            .iter()
+            .any(|blocked| blocked.eq_ignore_ascii_case(country))
+            .then_some(country)
+    }
+
+    async fn check(
+        &self,
+        group: &'static str,
+        rules: &Rules,
+        request: Request,
+        next: Next,
+    ) -> Response {
+        let geo = group == "api" && self.country_header.is_some();
+        if rules.is_empty() && !geo {
+            return next.run(request).await;
+        }
+        let Some(ip) = self.client_ip(&request) else {
+            if rules.is_empty() {
+                return next.run(request).await;
+            }
+            metrics::counter!(IP_ACL_DECISIONS_TOTAL, "group" => group, "decision" => "deny")
+                .increment(1);
+            tracing::warn!(
+                group,
+                "拒绝访问 {}: 无法确定客户端地址",
+                request.uri().path()
+            );
+            return (StatusCode::FORBIDDEN, "访问被拒绝").into_response();
+        };
+
+        let denied = if !rules.permits(ip) {
+            Some(format!("地址 {} 不在允许范围内", ip))
+        } else if geo && let Some(country) = self.blocked_country(&request) {
+            Some(format!("地址 {} 来自被屏蔽的国家 {}", ip, country))
+        } else {
+            None
+        };
+        let decision = if denied.is_some() { "deny" } else { "allow" };
+        metrics::counter!(IP_ACL_DECISIONS_TOTAL, "group" => group, "decision" => decision)
+            .increment(1);
+        match denied {
+            Some(reason) => {
+                tracing::warn!(group, "拒绝访问 {}: {}", request.uri().path(), reason);
+                (StatusCode::FORBIDDEN, "访问被拒绝").into_response()
+            }
+            None => next.run(request).await,
+        }
+    }
+}
+
+/// 所有接口的网络访问控制
+pub async fn check_ip(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let acl = &state.ip_acl;
+    acl.check("api", &acl.api, request, next).await
+}
+
+/// 管理接口的网络访问控制
+pub async fn check_admin_ip(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let acl = &state.ip_acl;
+    acl.check("admin", &acl.admin, request, next).await
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Body;
+
+    use super::*;
+
+    fn acl(allow: &str) -> IpAcl {
+        IpAcl {
+            api: Rules {
+                allow: vec![allow.parse().unwrap()],
+                deny: Vec::new(),
+            },
+            trusted_hops: 1,
+            ..IpAcl::default()
+        }
+    }
+
+    fn request(forwarded_for: &str, peer: Option<&str>) -> Request {
+        let mut request = Request::builder()
+            .header("x-forwarded-for", forwarded_for)
+            .body(Body::empty())
+            .unwrap();
+        if let Some(peer) = peer {
+            request
+                .extensions_mut()
+                .insert(ConnectInfo::<SocketAddr>(peer.parse().unwrap()));
+        }
+        request
+    }
+
+    #[test]
+    fn trusts_only_proxy_appended_forwarded_for() {
+        let acl = acl("10.0.0.0/8");
+        let ip = acl.client_ip(&request("1.2.3.4, 10.0.0.1", Some("192.168.0.1:80")));
+        assert_eq!(ip, Some("10.0.0.1".parse().unwrap()));
+        // 地址数少于可信代理层数时使用对端地址
+        let acl = IpAcl {
+            trusted_hops: 2,
+            ..acl
+        };
+        let ip = acl.client_ip(&request("10.0.0.1", Some("192.168.0.1:80")));
+        assert_eq!(ip, Some("192.168.0.1".parse().unwrap()));
+    }
+
+    #[test]
+    fn ignores_forwarded_for_without_peer() {
+        let acl = acl("10.0.0.0/8");
+        // 队列消息没有对端地址，伪造的 X-Forwarded-For 不能让它通过允许列表
+        let request = request("10.0.0.1", None);
+        assert_eq!(acl.client_ip(&request), None);
+    }
+
+    #[tokio::test]
+    async fn denies_queue_requests_with_forged_forwarded_for() {
+        let acl = std::sync::Arc::new(acl("10.0.0.0/8"));
+        let client_headers = acl.client_headers();
+        let app = axum::Router::new()
+            .route("/chat/completions", axum::routing::post(|| async { "ok" }))
+            .layer(axum::middleware::from_fn(move |request, next| {
+                let acl = acl.clone();
+                async move { acl.check("api", &acl.api, request, next).await }
+            }));
+        let payload = serde_json::json!({
+            "path": "/chat/completions",
+            "headers": { "X-Forwarded-For": "10.0.0.1", "X-Real-IP": "10.0.0.1" },
+            "body": {},
+        });
+        let result =
+            crate::queue::process(app, payload.to_string().as_bytes(), &client_headers).await;
+        assert_eq!(result.status, 403);
+    }
+}
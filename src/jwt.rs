@@ -0,0 +1,484 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use axum::http::StatusCode;
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use reqwest::Client;
+use ring::signature::{self, RsaPublicKeyComponents, UnparsedPublicKey};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::keys::ClientIdentity;
+
+/// 未配置时允许的时钟偏差秒数
+const DEFAULT_CLOCK_SKEW_SECS: u64 = 60;
+
+/// 未配置时 JWKS 的缓存秒数
+const DEFAULT_JWKS_CACHE_TTL_SECS: u64 = 3600;
+
+/// 遇到未知 `kid` 时两次刷新 JWKS 之间的最短间隔，避免伪造的 `kid` 频繁触发请求
+const MIN_REFRESH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// 获取 OIDC 配置与 JWKS 的超时时间
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// 当前 Unix 时间戳（秒）
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+/// JWKS 中的一个公钥
+#[derive(Deserialize)]
+struct Jwk {
+    kty: String,
+    #[serde(default)]
+    kid: Option<String>,
+    #[serde(default)]
+    n: Option<String>,
+    #[serde(default)]
+    e: Option<String>,
+    #[serde(default)]
+    crv: Option<String>,
+    #[serde(default)]
+    x: Option<String>,
+    #[serde(default)]
+    y: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct JwkSet {
+    keys: Vec<Jwk>,
+}
+
+/// 解码后可用于验签的公钥
+#[derive(Clone)]
+enum PublicKey {
+    Rsa {
+        n: Vec<u8>,
+        e: Vec<u8>,
+    },
+    /// 未压缩的椭圆曲线点，`crv` 为 `P-256` 或 `P-384`
+    Ec {
+        crv: String,
+        point: Vec<u8>,
+    },
+}
+
+impl PublicKey {
+    fn from_jwk(jwk: &Jwk) -> Option<Self> {
+        let decode = |value: &Option<String>| URL_SAFE_NO_PAD.decode(value.as_deref()?).ok();
+        match jwk.kty.as_str() {
+            "RSA" => Some(Self::Rsa {
+                n: decode(&jwk.n)?,
+                e: decode(&jwk.e)?,
+            }),
+            "EC" => {
+                let mut point = vec![0x04];
+                point.extend(decode(&jwk.x)?);
+                point.extend(decode(&jwk.y)?);
+                Some(Self::Ec {
+                    crv: jwk.crv.clone()?,
+                    point,
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// 按 JWT 头中的 `alg` 验证签名，算法与公钥类型不匹配时视为无效
+    fn verify(&self, alg: &str, message: &[u8], signature: &[u8]) -> bool {
+        match (self, alg) {
+            (Self::Rsa { n, e }, "RS256" | "RS384" | "RS512") => {
+                let params = match alg {
+                    "RS256" => &signature::RSA_PKCS1_2048_8192_SHA256,
+                    "RS384" => &signature::RSA_PKCS1_2048_8192_SHA384,
+                    _ => &signature::RSA_PKCS1_2048_8192_SHA512,
+                };
+                RsaPublicKeyComponents { n, e }
+                    .verify(params, message, signature)
+                    .is_ok()
+            }
+            (Self::Ec { crv, point }, "ES256" | "ES384") => {
+                let algorithm = match (crv.as_str(), alg) {
+                    ("P-256", "ES256") => &signature::ECDSA_P256_SHA256_FIXED,
+                    ("P-384", "ES384") => &signature::ECDSA_P384_SHA384_FIXED,
+                    _ => return false,
+                };
+                UnparsedPublicKey::new(algorithm, point)
+                    .verify(message, signature)
+                    .is_ok()
+            }
+            _ => false,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct JwtHeader {
+    alg: String,
+    #[serde(default)]
+    kid: Option<String>,
+}
+
+/// 拆分后的 JWT
+struct Token {
+    header: JwtHeader,
+    claims: Value,
+    /// 签名覆盖的 `header.payload` 部分
+    signing_input: String,
+    signature: Vec<u8>,
+}
+
+impl Token {
+    fn decode(token: &str) -> Option<Self> {
+        let mut parts = token.split('.');
+        let (header, payload, signature) = (parts.next()?, parts.next()?, parts.next()?);
+        if parts.next().is_some() {
+            return None;
+        }
+        Some(Self {
+            header: serde_json::from_slice(&URL_SAFE_NO_PAD.decode(header).ok()?).ok()?,
+            claims: serde_json::from_slice(&URL_SAFE_NO_PAD.decode(payload).ok()?).ok()?,
+            signing_input: format!("{}.{}", header, payload),
+            signature: URL_SAFE_NO_PAD.decode(signature).ok()?,
+        })
+    }
+}
+
+/// Bearer 凭据是否形如 JWT（三段 base64url）
+pub fn looks_like_jwt(credential: &str) -> bool {
+    credential.split('.').count() == 3 && credential.starts_with("eyJ")
+}
+
+#[derive(Default)]
+struct JwksCache {
+    /// `kid` 到公钥，没有 `kid` 的公钥以空字符串为键
+    keys: HashMap<String, PublicKey>,
+    fetched_at: Option<Instant>,
+    /// 由 OIDC 发现得到的 JWKS 地址
+    jwks_url: Option<String>,
+}
+
+/// JWT / OIDC 客户端鉴权
+///
+/// 配置 `OIDC_ISSUER` 与 `OIDC_AUDIENCE` 后，客户端可以用 OIDC 签发的 JWT 作为 `Authorization: Bearer` 凭据：
+/// 公钥从 `OIDC_JWKS_URL`（未配置时从 `{issuer}/.well-known/openid-configuration` 发现）获取并缓存
+/// `JWKS_CACHE_TTL_SECS` 秒，遇到未知的 `kid` 时提前刷新；校验签名（RS256/RS384/RS512/ES256/ES384）、
+/// `iss`、`aud`、`exp` 与 `nbf`，时间允许 `JWT_CLOCK_SKEW_SECS` 秒偏差。
+/// 通过后以 `sub` 作为客户端身份，`JWT_TENANT_CLAIM`（默认 `tenant`）声明作为租户。
+#[derive(Default)]
+pub struct JwtAuth {
+    issuer: Option<String>,
+    audience: String,
+    jwks_url: Option<String>,
+    clock_skew: u64,
+    cache_ttl: Duration,
+    tenant_claim: String,
+    cache: Mutex<JwksCache>,
+}
+
+impl JwtAuth {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let Ok(issuer) = std::env::var("OIDC_ISSUER") else {
+            return Ok(Self::default());
+        };
+        let audience = std::env::var("OIDC_AUDIENCE")
+            .map_err(|_| anyhow::anyhow!("配置 OIDC_ISSUER 时必须配置 OIDC_AUDIENCE"))?;
+        let jwks_url = std::env::var("OIDC_JWKS_URL").ok();
+        if let Some(url) = &jwks_url {
+            reqwest::Url::parse(url).map_err(|e| anyhow::anyhow!("OIDC_JWKS_URL 无效: {}", e))?;
+        }
+        let clock_skew = match std::env::var("JWT_CLOCK_SKEW_SECS") {
+            Ok(value) => value.parse()?,
+            Err(_) => DEFAULT_CLOCK_SKEW_SECS,
+        };
+        let cache_ttl = match std::env::var("JWKS_CACHE_TTL_SECS") {
+            Ok(value) => value.parse()?,
+            Err(_) => DEFAULT_JWKS_CACHE_TTL_SECS,
+        };
+        Ok(Self {
+            issuer: Some(issuer.trim_end_matches('/').to_string()),
+            audience,
+            jwks_url,
+            clock_skew,
+            cache_ttl: Duration::from_secs(cache_ttl),
+            tenant_claim: std::env::var("JWT_TENANT_CLAIM")
+                .unwrap_or_else(|_| "tenant".to_string()),
+            cache: Mutex::default(),
+        })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.issuer.is_some()
+    }
+
+    /// 校验 JWT 并返回客户端身份，JWT 无效时返回 401，无法获取公钥时返回 502
+    pub async fn authenticate(
+        &self,
+        client: &Client,
+        token: &str,
+    ) -> Result<ClientIdentity, (StatusCode, String)> {
+        let invalid = |reason: &str| (StatusCode::UNAUTHORIZED, format!("JWT 无效: {}", reason));
+        let token = Token::decode(token).ok_or_else(|| invalid("格式错误"))?;
+        let kid = token.header.kid.clone().unwrap_or_default();
+
+        let key = match self.cached_key(&kid, false) {
+            Some(key) => key,
+            None => {
+                self.refresh(client).await?;
+                self.cached_key(&kid, true)
+                    .ok_or_else(|| invalid("未知的签名密钥"))?
+            }
+        };
+        self.verify(&token, &key, now()).map_err(invalid)
+    }
+
+    /// 验证签名与声明
+    fn verify(
+        &self,
+        token: &Token,
+        key: &PublicKey,
+        now: u64,
+    ) -> Result<ClientIdentity, &'static str> {
+        if !key.verify(
+            &token.header.alg,
+            token.signing_input.as_bytes(),
+            &token.signature,
+        ) {
+            return Err("签名无效");
+        }
+        self.check_claims(&token.claims, now)
+    }
+
+    /// 从缓存中取公钥，缓存过期时视为未命中；`stale` 为 `true` 时忽略过期
+    fn cached_key(&self, kid: &str, stale: bool) -> Option<PublicKey> {
+        let cache = self.cache.lock().unwrap();
+        let fresh = cache
+            .fetched_at
+            .is_some_and(|at| at.elapsed() < self.cache_ttl);
+        if !fresh && !stale {
+            return None;
+        }
+        cache.keys.get(kid).cloned()
+    }
+
+    /// 重新获取 JWKS，距上次获取不足最短间隔时跳过
+    async fn refresh(&self, client: &Client) -> Result<(), (StatusCode, String)> {
+        let (recent, discovered) = {
+            let cache = self.cache.lock().unwrap();
+            let recent = cache
+                .fetched_at
+                .is_some_and(|at| at.elapsed() < MIN_REFRESH_INTERVAL);
+            (recent, cache.jwks_url.clone())
+        };
+        if recent {
+            return Ok(());
+        }
+
+        let unavailable = |e: String| {
+            tracing::warn!("获取 JWKS 失败: {}", e);
+            (StatusCode::BAD_GATEWAY, format!("获取 JWKS 失败: {}", e))
+        };
+        let fetch = |url: String| async move {
+            client
+                .get(url)
+                .timeout(FETCH_TIMEOUT)
+                .send()
+                .await
+                .and_then(reqwest::Response::error_for_status)
+                .map_err(|e| e.to_string())?
+                .json::<Value>()
+                .await
+                .map_err(|e| e.to_string())
+        };
+
+        let jwks_url = match self.jwks_url.clone().or(discovered) {
+            Some(url) => url,
+            None => {
+                let issuer = self.issuer.as_deref().unwrap_or_default();
+                let configuration = fetch(format!("{}/.well-known/openid-configuration", issuer))
+                    .await
+                    .map_err(unavailable)?;
+                configuration
+                    .get("jwks_uri")
+                    .and_then(Value::as_str)
+                    .map(str::to_string)
+                    .ok_or_else(|| unavailable("OIDC 配置中缺少 jwks_uri".to_string()))?
+            }
+        };
+        let jwks: JwkSet =
+            serde_json::from_value(fetch(jwks_url.clone()).await.map_err(unavailable)?)
+                .map_err(|e| unavailable(e.to_string()))?;
+        let keys: HashMap<String, PublicKey> = jwks
+            .keys
+            .iter()
+            .filter_map(|jwk| {
+                Some((
+                    jwk.kid.clone().unwrap_or_default(),
+                    PublicKey::from_jwk(jwk)?,
+                ))
+            })
+            .collect();
+        tracing::info!("已获取 {} 个 JWKS 公钥", keys.len());
+
+        let mut cache = self.cache.lock().unwrap();
+        cache.keys = keys;
+        cache.fetched_at = Some(Instant::now());
+        cache.jwks_url = Some(jwks_url);
+        Ok(())
+    }
+
+    /// 校验 `iss`、`aud`、`exp`、`nbf`，通过后按 `sub` 与租户声明生成客户端身份
+    fn check_claims(&self, claims: &Value, now: u64) -> Result<ClientIdentity, &'static str> {
+        let string = |name: &str| claims.get(name).and_then(Value::as_str);
+        if string("iss").map(|iss| iss.trim_end_matches('/')) != self.issuer.as_deref() {
+            return Err("iss 不匹配");
+        }
+        let audience_matches = match claims.get("aud") {
+            Some(Value::String(aud)) => *aud == self.audience,
+            Some(Value::Array(auds)) => auds.iter().any(|aud| aud.as_str() == Some(&self.audience)),
+            _ => false,
+        };
+        if !audience_matches {
+            return Err("aud 不匹配");
+        }
+        let exp = claims
+            .get("exp")
+            .and_then(Value::as_u64)
+            .ok_or("缺少 exp")?;
+        if now >= exp + self.clock_skew {
+            return Err("已过期");
+        }
+        if let Some(nbf) = claims.get("nbf").and_then(Value::as_u64)
+            && nbf > now + self.clock_skew
+        {
+            return Err("尚未生效");
+        }
+        let subject = string("sub").ok_or("缺少 sub")?;
+        Ok(ClientIdentity {
+            scope: format!("jwt:{}", subject),
+            tenant: string(&self.tenant_claim).map(str::to_string),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ring::{
+        rand::SystemRandom,
+        signature::{ECDSA_P256_SHA256_FIXED_SIGNING, EcdsaKeyPair, KeyPair},
+    };
+    use serde_json::json;
+
+    use super::*;
+
+    const ISSUER: &str = "https://issuer.example.com";
+    const AUDIENCE: &str = "free-model";
+
+    fn auth() -> JwtAuth {
+        JwtAuth {
+            issuer: Some(ISSUER.to_string()),
+            audience: AUDIENCE.to_string(),
+            jwks_url: None,
+            clock_skew: 60,
+            cache_ttl: Duration::from_secs(3600),
+            tenant_claim: "tenant".to_string(),
+            cache: Mutex::default(),
+        }
+    }
+
+    fn key_pair() -> EcdsaKeyPair {
+        let rng = SystemRandom::new();
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng).unwrap();
+        EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8.as_ref(), &rng).unwrap()
+    }
+
+    fn public_key(pair: &EcdsaKeyPair) -> PublicKey {
+        PublicKey::Ec {
+            crv: "P-256".to_string(),
+            point: pair.public_key().as_ref().to_vec(),
+        }
+    }
+
+    fn sign(pair: &EcdsaKeyPair, claims: &Value) -> String {
+        let header = URL_SAFE_NO_PAD.encode(json!({ "alg": "ES256", "kid": "k1" }).to_string());
+        let payload = URL_SAFE_NO_PAD.encode(claims.to_string());
+        let signing_input = format!("{}.{}", header, payload);
+        let signature = pair
+            .sign(&SystemRandom::new(), signing_input.as_bytes())
+            .unwrap();
+        format!("{}.{}", signing_input, URL_SAFE_NO_PAD.encode(signature))
+    }
+
+    fn claims(exp: u64) -> Value {
+        json!({ "iss": ISSUER, "aud": [AUDIENCE, "other"], "sub": "user-1", "tenant": "acme", "exp": exp })
+    }
+
+    fn verify(
+        auth: &JwtAuth,
+        key: &PublicKey,
+        token: &str,
+        now: u64,
+    ) -> Result<ClientIdentity, &'static str> {
+        auth.verify(&Token::decode(token).ok_or("格式错误")?, key, now)
+    }
+
+    #[test]
+    fn accepts_valid_token_and_maps_identity() {
+        let pair = key_pair();
+        let token = sign(&pair, &claims(1_000));
+        assert!(looks_like_jwt(&token));
+        let identity = verify(&auth(), &public_key(&pair), &token, 900).unwrap();
+        assert_eq!(identity.scope, "jwt:user-1");
+        assert_eq!(identity.tenant.as_deref(), Some("acme"));
+    }
+
+    #[test]
+    fn rejects_tampered_signature_and_other_keys() {
+        let pair = key_pair();
+        let token = sign(&pair, &claims(1_000));
+        let (signing_input, _) = token.rsplit_once('.').unwrap();
+        let forged = sign(&pair, &json!({ "sub": "admin" }));
+        let forged = format!("{}.{}", signing_input, forged.rsplit_once('.').unwrap().1);
+        assert!(verify(&auth(), &public_key(&pair), &forged, 900).is_err());
+        assert!(verify(&auth(), &public_key(&key_pair()), &token, 900).is_err());
+        // 算法与公钥类型不匹配
+        let rsa = PublicKey::Rsa {
+            n: vec![1],
+            e: vec![3],
+        };
+        assert!(verify(&auth(), &rsa, &token, 900).is_err());
+    }
+
+    #[test]
+    fn checks_issuer_and_audience() {
+        let pair = key_pair();
+        let key = public_key(&pair);
+        let mut wrong_issuer = claims(1_000);
+        wrong_issuer["iss"] = json!("https://evil.example.com");
+        assert!(verify(&auth(), &key, &sign(&pair, &wrong_issuer), 900).is_err());
+        let mut wrong_audience = claims(1_000);
+        wrong_audience["aud"] = json!("other");
+        assert!(verify(&auth(), &key, &sign(&pair, &wrong_audience), 900).is_err());
+    }
+
+    #[test]
+    fn allows_clock_skew() {
+        let pair = key_pair();
+        let key = public_key(&pair);
+        let token = sign(&pair, &claims(1_000));
+        assert!(verify(&auth(), &key, &token, 1_030).is_ok());
+        assert!(verify(&auth(), &key, &token, 1_060).is_err());
+
+        let mut not_yet = claims(10_000);
+        not_yet["nbf"] = json!(1_050);
+        let token = sign(&pair, &not_yet);
+        assert!(verify(&auth(), &key, &token, 1_000).is_ok());
+        assert!(verify(&auth(), &key, &token, 900).is_err());
+    }
+}
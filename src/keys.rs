@@ -0,0 +1,484 @@
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+use axum::{
+    Json,
+    extract::{Path, Request, State},
+    http::{StatusCode, header::AUTHORIZATION},
+    middleware::Next,
+    response::Response,
+};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use utoipa::ToSchema;
+
+use crate::{AppState, jwt};
+
+/// 生成的客户端密钥前缀
+const KEY_PREFIX: &str = "fm-";
+
+/// 可授予客户端密钥的权限，分别对应对话（含取消与续传）、翻译与只读接口（模型列表、费用估算、上游状态）
+const SCOPES: &[&str] = &["chat", "translate", "read"];
+
+/// 当前 Unix 时间戳（秒）
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+/// 密钥的 SHA-256 十六进制摘要，存储与查找都只使用摘要
+fn hash_key(key: &str) -> String {
+    Sha256::digest(key.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// 客户端密钥的元数据，不含密钥本身
+#[derive(Clone, Serialize, Deserialize, ToSchema)]
+pub struct ClientKey {
+    pub id: String,
+    /// 备注名，如使用该密钥的前端应用
+    pub name: String,
+    /// 允许访问的接口：`chat`、`translate`、`read`
+    pub scopes: Vec<String>,
+    /// 创建时间（Unix 秒）
+    pub created_at: u64,
+    /// 过期时间（Unix 秒），为空时不过期
+    pub expires_at: Option<u64>,
+}
+
+/// 通过客户端密钥或 JWT 鉴权的客户端身份，由鉴权中间件写入请求扩展
+///
+/// JWT 会随刷新变化，按客户端隔离的状态（幂等键、取消、续传、调度与各类按客户端的统计）
+/// 改用 `scope` 区分，见 [`client_scope`](crate::handlers::chat_completions::client_scope)。
+#[derive(Clone, Debug)]
+pub struct ClientIdentity {
+    /// 稳定的客户端标识，如 `key:<密钥 ID>`、`jwt:<sub>`
+    pub scope: String,
+    /// 租户
+    pub tenant: Option<String>,
+}
+
+/// 持久化的密钥记录
+#[derive(Serialize, Deserialize)]
+struct StoredKey {
+    #[serde(flatten)]
+    key: ClientKey,
+    /// 密钥的 SHA-256 摘要
+    hash: String,
+}
+
+/// 创建客户端密钥的请求
+#[derive(Deserialize, ToSchema)]
+pub struct CreateKeyRequest {
+    pub name: String,
+    /// 允许访问的接口，省略时允许全部
+    #[serde(default)]
+    pub scopes: Option<Vec<String>>,
+    /// 有效期秒数，省略时不过期
+    #[serde(default)]
+    pub ttl_secs: Option<u64>,
+}
+
+/// 新建的客户端密钥，`key` 只在创建时返回一次
+#[derive(Serialize, ToSchema)]
+pub struct CreatedKey {
+    pub key: String,
+    #[serde(flatten)]
+    pub info: ClientKey,
+}
+
+/// 客户端密钥管理
+///
+/// 配置 `CLIENT_KEYS_FILE` 后启用客户端鉴权：客户端接口必须以 `Authorization: Bearer <key>` 携带
+/// 通过管理接口创建的密钥，否则返回 401，密钥无权访问该接口时返回 403。
+/// 文件中只保存密钥的 SHA-256 摘要，每次创建或吊销后整体重写。
+#[derive(Default)]
+pub struct ClientKeys {
+    path: Option<PathBuf>,
+    /// 摘要到密钥记录
+    keys: Mutex<HashMap<String, StoredKey>>,
+}
+
+impl ClientKeys {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let Ok(path) = std::env::var("CLIENT_KEYS_FILE") else {
+            return Ok(Self::default());
+        };
+        Self::load(PathBuf::from(path))
+    }
+
+    /// 读取密钥文件，文件不存在时从空列表开始
+    fn load(path: PathBuf) -> anyhow::Result<Self> {
+        let stored: Vec<StoredKey> = match std::fs::read(&path) {
+            Ok(content) => serde_json::from_slice(&content)
+                .with_context(|| format!("解析密钥文件 {} 失败", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => {
+                return Err(e).with_context(|| format!("读取密钥文件 {} 失败", path.display()));
+            }
+        };
+        let keys = stored
+            .into_iter()
+            .map(|stored| (stored.hash.clone(), stored))
+            .collect();
+        Ok(Self {
+            path: Some(path),
+            keys: Mutex::new(keys),
+        })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.path.is_some()
+    }
+
+    /// 校验 Authorization 头中的密钥是否有效且拥有 `scope` 权限，返回以密钥 ID 标识的客户端身份
+    pub fn authorize(
+        &self,
+        authorization: Option<&str>,
+        scope: &str,
+    ) -> Result<ClientIdentity, (StatusCode, String)> {
+        let unauthorized = |message: &str| (StatusCode::UNAUTHORIZED, message.to_string());
+        let key = authorization
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or_else(|| unauthorized("缺少客户端密钥"))?;
+        let keys = self.keys.lock().unwrap();
+        let stored = keys
+            .get(&hash_key(key))
+            .ok_or_else(|| unauthorized("客户端密钥无效"))?;
+        if stored
+            .key
+            .expires_at
+            .is_some_and(|expires_at| expires_at <= now())
+        {
+            return Err(unauthorized("客户端密钥已过期"));
+        }
+        if !stored.key.scopes.iter().any(|granted| granted == scope) {
+            return Err((
+                StatusCode::FORBIDDEN,
+                format!("客户端密钥无权访问 {} 接口", scope),
+            ));
+        }
+        Ok(ClientIdentity {
+            scope: format!("key:{}", stored.key.id),
+            tenant: None,
+        })
+    }
+
+    /// 按摘要重写密钥文件，先写临时文件再替换，避免写入中断留下不完整的文件
+    fn persist(&self, keys: &HashMap<String, StoredKey>) -> anyhow::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let mut stored: Vec<&StoredKey> = keys.values().collect();
+        stored.sort_by(|a, b| a.key.id.cmp(&b.key.id));
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, serde_json::to_vec_pretty(&stored)?)?;
+        std::fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    fn create(&self, request: CreateKeyRequest) -> Result<CreatedKey, (StatusCode, String)> {
+        let scopes = request
+            .scopes
+            .unwrap_or_else(|| SCOPES.iter().map(|scope| scope.to_string()).collect());
+        if let Some(unknown) = scopes
+            .iter()
+            .find(|scope| !SCOPES.contains(&scope.as_str()))
+        {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!("未知的权限 {}，可选 {}", unknown, SCOPES.join(",")),
+            ));
+        }
+
+        let mut secret = [0u8; 32];
+        SystemRandom::new().fill(&mut secret).map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "生成密钥失败".to_string(),
+            )
+        })?;
+        let key = format!(
+            "{}{}",
+            KEY_PREFIX,
+            secret
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect::<String>()
+        );
+        let created_at = now();
+        let info = ClientKey {
+            id: uuid::Uuid::now_v7().to_string(),
+            name: request.name,
+            scopes,
+            created_at,
+            expires_at: request.ttl_secs.map(|ttl| created_at + ttl),
+        };
+
+        let hash = hash_key(&key);
+        let mut keys = self.keys.lock().unwrap();
+        keys.insert(
+            hash.clone(),
+            StoredKey {
+                key: info.clone(),
+                hash: hash.clone(),
+            },
+        );
+        if let Err(e) = self.persist(&keys) {
+            keys.remove(&hash);
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("保存密钥文件失败: {}", e),
+            ));
+        }
+        tracing::info!("已创建客户端密钥 {}（{}）", info.id, info.name);
+        Ok(CreatedKey { key, info })
+    }
+
+    fn revoke(&self, id: &str) -> Result<(), (StatusCode, String)> {
+        let mut keys = self.keys.lock().unwrap();
+        let Some(hash) = keys
+            .iter()
+            .find(|(_, stored)| stored.key.id == id)
+            .map(|(hash, _)| hash.clone())
+        else {
+            return Err((StatusCode::NOT_FOUND, "密钥不存在".to_string()));
+        };
+        let stored = keys.remove(&hash);
+        if let Err(e) = self.persist(&keys) {
+            keys.extend(stored.map(|stored| (hash, stored)));
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("保存密钥文件失败: {}", e),
+            ));
+        }
+        tracing::info!("已吊销客户端密钥 {}", id);
+        Ok(())
+    }
+
+    fn list(&self) -> Vec<ClientKey> {
+        let mut keys: Vec<ClientKey> = self
+            .keys
+            .lock()
+            .unwrap()
+            .values()
+            .map(|stored| stored.key.clone())
+            .collect();
+        keys.sort_by(|a, b| a.id.cmp(&b.id));
+        keys
+    }
+}
+
+/// 接口所需的权限
+fn required_scope(path: &str) -> &'static str {
+    if path.starts_with("/chat/") {
+        "chat"
+    } else if path == "/translate" {
+        "translate"
+    } else {
+        "read"
+    }
+}
+
+/// 是否启用了客户端鉴权（客户端密钥或 JWT），启用时客户端凭据只用于代理鉴权，不转发给上游
+pub fn client_auth_enabled(state: &AppState) -> bool {
+    state.client_keys.is_enabled() || state.jwt_auth.is_enabled()
+}
+
+/// 客户端接口鉴权
+///
+/// 启用 JWT 时形如 JWT 的 Bearer 凭据按 OIDC 校验，其余凭据按客户端密钥校验密钥与接口权限，
+/// 未启用客户端密钥时返回 401；通过后把客户端身份写入请求扩展。
+pub async fn require_client_auth(
+    State(state): State<AppState>,
+    mut request: Request,
+    next: Next,
+) -> Result<Response, (StatusCode, String)> {
+    if !client_auth_enabled(&state) {
+        return Ok(next.run(request).await);
+    }
+    let authorization = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok());
+    let token = authorization
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .filter(|token| state.jwt_auth.is_enabled() && jwt::looks_like_jwt(token));
+    let identity = if let Some(token) = token {
+        state
+            .jwt_auth
+            .authenticate(&state.http_client, token)
+            .await?
+    } else if state.client_keys.is_enabled() {
+        state
+            .client_keys
+            .authorize(authorization, required_scope(request.uri().path()))?
+    } else {
+        return Err((StatusCode::UNAUTHORIZED, "缺少有效的 JWT".to_string()));
+    };
+    request.extensions_mut().insert(identity);
+    Ok(next.run(request).await)
+}
+
+/// 列出客户端密钥
+#[utoipa::path(
+    get,
+    path = "/admin/keys",
+    tag = "admin",
+    security(("admin_key" = [])),
+    responses(
+        (status = 200, description = "客户端密钥，不含密钥本身", body = Vec<ClientKey>),
+        (status = 401, description = "管理接口密钥无效", body = String),
+    ),
+)]
+pub async fn list_keys(State(state): State<AppState>) -> Json<Vec<ClientKey>> {
+    Json(state.client_keys.list())
+}
+
+/// 创建客户端密钥，响应中的 `key` 只返回这一次
+#[utoipa::path(
+    post,
+    path = "/admin/keys",
+    tag = "admin",
+    security(("admin_key" = [])),
+    request_body = CreateKeyRequest,
+    responses(
+        (status = 201, description = "新建的密钥", body = CreatedKey),
+        (status = 400, description = "权限无效", body = String),
+        (status = 401, description = "管理接口密钥无效", body = String),
+        (status = 404, description = "未配置 CLIENT_KEYS_FILE", body = String),
+    ),
+)]
+pub async fn create_key(
+    State(state): State<AppState>,
+    Json(request): Json<CreateKeyRequest>,
+) -> Result<(StatusCode, Json<CreatedKey>), (StatusCode, String)> {
+    if !state.client_keys.is_enabled() {
+        return Err((StatusCode::NOT_FOUND, "未配置 CLIENT_KEYS_FILE".to_string()));
+    }
+    let created = state.client_keys.create(request)?;
+    Ok((StatusCode::CREATED, Json(created)))
+}
+
+/// 吊销客户端密钥
+#[utoipa::path(
+    delete,
+    path = "/admin/keys/{id}",
+    tag = "admin",
+    security(("admin_key" = [])),
+    params(("id" = String, Path, description = "密钥 ID")),
+    responses(
+        (status = 204, description = "已吊销"),
+        (status = 401, description = "管理接口密钥无效", body = String),
+        (status = 404, description = "密钥不存在", body = String),
+    ),
+)]
+pub async fn revoke_key(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    state.client_keys.revoke(&id)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store() -> (ClientKeys, PathBuf) {
+        let path = std::env::temp_dir().join(format!("client-keys-{}.json", uuid::Uuid::now_v7()));
+        (ClientKeys::load(path.clone()).unwrap(), path)
+    }
+
+    fn create(keys: &ClientKeys, scopes: Option<&[&str]>, ttl_secs: Option<u64>) -> CreatedKey {
+        keys.create(CreateKeyRequest {
+            name: "app".to_string(),
+            scopes: scopes.map(|scopes| scopes.iter().map(|scope| scope.to_string()).collect()),
+            ttl_secs,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn rejects_missing_and_unknown_keys() {
+        let (keys, path) = store();
+        create(&keys, None, None);
+        let status = |authorization| keys.authorize(authorization, "chat").unwrap_err().0;
+        assert_eq!(status(None), StatusCode::UNAUTHORIZED);
+        assert_eq!(status(Some("Bearer fm-unknown")), StatusCode::UNAUTHORIZED);
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn checks_scopes_and_expiry() {
+        let (keys, path) = store();
+        let created = create(&keys, Some(&["translate"]), None);
+        let authorization = format!("Bearer {}", created.key);
+        let identity = keys.authorize(Some(&authorization), "translate").unwrap();
+        assert_eq!(identity.scope, format!("key:{}", created.info.id));
+        assert_eq!(
+            keys.authorize(Some(&authorization), "chat").unwrap_err().0,
+            StatusCode::FORBIDDEN
+        );
+
+        let expired = create(&keys, None, Some(0));
+        let authorization = format!("Bearer {}", expired.key);
+        assert_eq!(
+            keys.authorize(Some(&authorization), "chat").unwrap_err().0,
+            StatusCode::UNAUTHORIZED
+        );
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn rejects_unknown_scopes() {
+        let (keys, path) = store();
+        let result = keys.create(CreateKeyRequest {
+            name: "app".to_string(),
+            scopes: Some(vec!["admin".to_string()]),
+            ttl_secs: None,
+        });
+        assert_eq!(result.err().unwrap().0, StatusCode::BAD_REQUEST);
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn persists_only_hashes_and_revokes() {
+        let (keys, path) = store();
+        let created = create(&keys, None, None);
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert!(!content.contains(&created.key));
+        assert!(content.contains(&hash_key(&created.key)));
+
+        // 重新加载后密钥仍然有效，吊销后失效
+        let reloaded = ClientKeys::load(path.clone()).unwrap();
+        let authorization = format!("Bearer {}", created.key);
+        assert!(reloaded.authorize(Some(&authorization), "chat").is_ok());
+        reloaded.revoke(&created.info.id).unwrap();
+        assert!(reloaded.authorize(Some(&authorization), "chat").is_err());
+        assert!(ClientKeys::load(path.clone()).unwrap().list().is_empty());
+        assert_eq!(
+            reloaded.revoke(&created.info.id).unwrap_err().0,
+            StatusCode::NOT_FOUND
+        );
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn maps_routes_to_scopes() {
+        assert_eq!(required_scope("/chat/completions"), "chat");
+        assert_eq!(required_scope("/chat/completions/abc/cancel"), "chat");
+        assert_eq!(required_scope("/translate"), "translate");
+        assert_eq!(required_scope("/models"), "read");
+        assert_eq!(required_scope("/cost/estimate"), "read");
+    }
+}
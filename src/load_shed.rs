@@ -0,0 +1,178 @@
+use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{Request, State},
+    http::{HeaderValue, StatusCode, header::RETRY_AFTER},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use crate::AppState;
+
+/// 采样间隔
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// 负载降到阈值的该比例以下才恢复接收请求，避免在阈值附近反复切换
+const HYSTERESIS_RATIO: f64 = 0.9;
+
+/// 拒绝请求时建议客户端等待的秒数
+const RETRY_AFTER_SECS: &str = "5";
+
+/// 自适应降载
+///
+/// 后台定期采样进程内存（RSS）与 CPU 使用率，任一超过阈值时进入降载状态，
+/// 新的对话与翻译请求直接返回 503；负载回落到阈值的 90% 以下后恢复。
+pub struct LoadShedder {
+    memory_limit_bytes: Option<u64>,
+    cpu_limit_ratio: Option<f64>,
+    shedding: AtomicBool,
+}
+
+impl LoadShedder {
+    /// 读取 `LOAD_SHED_MEMORY_MB` 与 `LOAD_SHED_CPU_PERCENT`，均未配置时不启用
+    pub fn from_env() -> anyhow::Result<Self> {
+        let memory_limit_bytes = match std::env::var("LOAD_SHED_MEMORY_MB") {
+            Ok(value) => Some(value.parse::<u64>()? * 1024 * 1024),
+            Err(_) => None,
+        };
+        let cpu_limit_ratio = match std::env::var("LOAD_SHED_CPU_PERCENT") {
+            Ok(value) => Some(value.parse::<f64>()? / 100.0),
+            Err(_) => None,
+        };
+        Ok(Self {
+            memory_limit_bytes,
+            cpu_limit_ratio,
+            shedding: AtomicBool::new(false),
+        })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.memory_limit_bytes.is_some() || self.cpu_limit_ratio.is_some()
+    }
+
+    pub fn is_shedding(&self) -> bool {
+        self.shedding.load(Ordering::Relaxed)
+    }
+
+    /// 启动后台采样任务
+    pub fn spawn_sampler(self: std::sync::Arc<Self>) {
+        if !self.is_enabled() {
+            return;
+        }
+
+        tokio::spawn(async move {
+            let cpus = std::thread::available_parallelism().map_or(1, |n| n.get()) as f64;
+            let ticks_per_sec = clock_ticks_per_sec();
+            let mut last = (Instant::now(), read_cpu_ticks());
+            let mut interval = tokio::time::interval(SAMPLE_INTERVAL);
+            loop {
+                interval.tick().await;
+
+                let memory = read_resident_bytes();
+                let now = (Instant::now(), read_cpu_ticks());
+                let cpu = match (last.1, now.1, ticks_per_sec) {
+                    (Some(before), Some(after), Some(ticks_per_sec)) => {
+                        let elapsed = now.0.duration_since(last.0).as_secs_f64();
+                        Some((after - before) as f64 / ticks_per_sec / elapsed / cpus)
+                    }
+                    _ => None,
+                };
+                last = now;
+
+                if let Some(memory) = memory {
+                    metrics::gauge!("process_resident_memory_bytes").set(memory as f64);
+                }
+                if let Some(cpu) = cpu {
+                    metrics::gauge!("process_cpu_usage_ratio").set(cpu);
+                }
+                self.update(memory, cpu);
+            }
+        });
+    }
+
+    /// 根据最新采样切换降载状态
+    fn update(&self, memory: Option<u64>, cpu: Option<f64>) {
+        let ratio = |value: f64, limit: f64| value / limit;
+        let pressure = [
+            memory
+                .zip(self.memory_limit_bytes)
+                .map(|(memory, limit)| ratio(memory as f64, limit as f64)),
+            cpu.zip(self.cpu_limit_ratio)
+                .map(|(cpu, limit)| ratio(cpu, limit)),
+        ]
+        .into_iter()
+        .flatten()
+        .fold(0.0, f64::max);
+
+        let shedding = self.is_shedding();
+        if !shedding && pressure >= 1.0 {
+            tracing::warn!("进程负载过高（阈值的 {:.0}%），开始降载", pressure * 100.0);
+            self.shedding.store(true, Ordering::Relaxed);
+        } else if shedding && pressure < HYSTERESIS_RATIO {
+            tracing::info!(
+                "进程负载已回落（阈值的 {:.0}%），停止降载",
+                pressure * 100.0
+            );
+            self.shedding.store(false, Ordering::Relaxed);
+        }
+        metrics::gauge!("load_shedding_active").set(if self.is_shedding() { 1.0 } else { 0.0 });
+    }
+}
+
+/// 读取进程常驻内存（字节），取自 /proc/self/status 的 `VmRSS`，仅支持 Linux
+fn read_resident_bytes() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let kib: u64 = status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmRSS:"))?
+        .trim()
+        .strip_suffix("kB")?
+        .trim()
+        .parse()
+        .ok()?;
+    Some(kib * 1024)
+}
+
+/// /proc 中 CPU 时间的单位（USER_HZ），由 sysconf 读取
+#[cfg(unix)]
+fn clock_ticks_per_sec() -> Option<f64> {
+    // SAFETY: sysconf 只查询系统配置，不涉及内存访问
+    let ticks = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+    (ticks > 0).then_some(ticks as f64)
+}
+
+#[cfg(not(unix))]
+fn clock_ticks_per_sec() -> Option<f64> {
+    None
+}
+
+/// 读取进程累计使用的 CPU 时间（用户态 + 内核态，单位 USER_HZ），仅支持 Linux
+fn read_cpu_ticks() -> Option<u64> {
+    let stat = std::fs::read_to_string("/proc/self/stat").ok()?;
+    // 进程名可能包含空格，从最后一个 ')' 之后开始按字段解析
+    let fields: Vec<&str> = stat.rsplit_once(')')?.1.split_whitespace().collect();
+    let utime: u64 = fields.get(11)?.parse().ok()?;
+    let stime: u64 = fields.get(12)?.parse().ok()?;
+    Some(utime + stime)
+}
+
+/// 降载中间件：降载期间拒绝请求并返回 503 与 `Retry-After`
+pub async fn shed_load(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    if !state.load_shedder.is_shedding() {
+        return next.run(request).await;
+    }
+
+    metrics::counter!("load_shed_requests_total").increment(1);
+    let mut response = (
+        StatusCode::SERVICE_UNAVAILABLE,
+        "server overloaded, retry later",
+    )
+        .into_response();
+    response
+        .headers_mut()
+        .insert(RETRY_AFTER, HeaderValue::from_static(RETRY_AFTER_SECS));
+    response
+}
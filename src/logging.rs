@@ -0,0 +1,67 @@
+use axum::http::Request;
+use tracing::Span;
+use tracing_subscriber::{
+    EnvFilter, Registry, fmt::time::LocalTime, layer::SubscriberExt, reload,
+    util::SubscriberInitExt,
+};
+
+/// 请求 ID 头，由 `SetRequestIdLayer` 生成或沿用客户端传入的值
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// 未设置 `RUST_LOG` 时使用的默认过滤指令
+const DEFAULT_FILTER: &str = "debug";
+
+/// 日志过滤器的热更新句柄
+pub type LogFilterHandle = reload::Handle<EnvFilter, Registry>;
+
+/// 初始化日志输出，返回可在运行时替换过滤指令的句柄
+///
+/// 过滤指令读取 `RUST_LOG`（`EnvFilter` 语法，如 `info,free_model::handlers=trace`），默认 `debug`。
+/// `LOG_FORMAT=json` 时输出单行 JSON（包含当前 span 及其父 span 的字段），便于 Loki/ELK 采集；
+/// 其余情况保持原有的 Pretty 格式。
+pub fn init() -> LogFilterHandle {
+    let json = std::env::var("LOG_FORMAT")
+        .map(|format| format.eq_ignore_ascii_case("json"))
+        .unwrap_or(false);
+
+    let filter =
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(DEFAULT_FILTER));
+    let (filter, handle) = reload::Layer::new(filter);
+
+    let json_layer = json.then(|| {
+        tracing_subscriber::fmt::layer()
+            .with_timer(LocalTime::rfc_3339())
+            .json()
+            .with_current_span(true)
+            .with_span_list(true)
+    });
+    let pretty_layer = (!json).then(|| {
+        tracing_subscriber::fmt::layer()
+            .with_timer(LocalTime::rfc_3339())
+            .pretty()
+    });
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(json_layer)
+        .with(pretty_layer)
+        .init();
+
+    handle
+}
+
+/// 为每个 HTTP 请求创建 span，记录请求 ID 以便串联同一请求的所有日志
+pub fn make_request_span<B>(request: &Request<B>) -> Span {
+    let request_id = request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+
+    tracing::info_span!(
+        "request",
+        request_id = %request_id,
+        method = %request.method(),
+        uri = %request.uri(),
+    )
+}
@@ -1,16 +1,108 @@
-use axum::{Router, routing::post};
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use axum::{
+    Router,
+    http::HeaderName,
+    middleware,
+    routing::{delete, get, post, put},
+};
+use clap::Parser;
 use reqwest::Client;
-use tower_http::{cors::CorsLayer, trace::TraceLayer};
-use tracing::Level;
-use tracing_subscriber::fmt::time::LocalTime;
+use tower_http::{
+    compression::{
+        CompressionLayer,
+        predicate::{DefaultPredicate, NotForContentType, Predicate},
+    },
+    request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer},
+    trace::TraceLayer,
+};
 
+mod abuse;
+mod cancel;
+mod chat;
+mod chat_stream;
+mod cli;
+mod concurrency;
+mod context;
+mod cors;
+mod diagnostics;
+mod dns;
+mod experiments;
+mod fanout;
 mod handlers;
+mod health;
+mod http_client;
+mod idempotency;
+mod ip_acl;
+mod jwt;
+mod keys;
+mod load_shed;
+mod logging;
+mod metrics;
+mod models;
+mod normalize;
+mod openapi;
+mod overrides;
+mod pipeline;
+mod plugins;
+mod pricing;
+mod queue;
+mod quota;
+mod redaction;
+mod resume;
+mod retry;
+mod scheduler;
+mod scripts;
+mod sse;
+mod tee;
+mod tls;
+mod tool_emulation;
+mod upstream;
+
+/// SSE 保活注释的默认间隔秒数
+const DEFAULT_SSE_KEEPALIVE_SECS: u64 = 15;
 
 /// 应用状态
 #[derive(Clone)]
 pub struct AppState {
     pub http_client: Client,
     pub api_key: String,
+    pub upstream_overrides: Arc<overrides::UpstreamOverrides>,
+    pub ip_acl: Arc<ip_acl::IpAcl>,
+    pub client_keys: Arc<keys::ClientKeys>,
+    pub jwt_auth: Arc<jwt::JwtAuth>,
+    pub abuse_detector: Arc<abuse::AbuseDetector>,
+    /// 管理接口密钥，未配置时不开放管理接口
+    pub admin_api_key: Option<String>,
+    pub log_filter: logging::LogFilterHandle,
+    pub concurrency_limits: Arc<concurrency::ConcurrencyLimits>,
+    /// 记录响应内容到日志时保留的最大字节数，未配置时不记录
+    pub response_log_max_bytes: Option<usize>,
+    pub context: Arc<context::ContextConfig>,
+    pub cancel_registry: Arc<cancel::CancelRegistry>,
+    /// 流式对话续传，未配置 `STREAM_RESUME_GRACE_SECS` 时为 `None`
+    pub resume_store: Option<Arc<resume::ResumeStore>>,
+    pub upstream_retry: Arc<retry::UpstreamRetry>,
+    /// 上游流式响应中断后的最大续写次数，为 0 时不续写
+    pub stream_error_retries: u32,
+    /// 等待上游首个数据块期间发送 SSE 保活注释的间隔，为 `None` 时不发送
+    pub sse_keepalive: Option<Duration>,
+    pub idempotency: Arc<idempotency::IdempotencyStore>,
+    pub redaction_mode: redaction::RedactionMode,
+    pub plugins: Arc<plugins::Plugins>,
+    pub route_scripts: Arc<scripts::RouteScripts>,
+    pub metrics: metrics_exporter_prometheus::PrometheusHandle,
+    pub load_shedder: Arc<load_shed::LoadShedder>,
+    /// 上游并发的公平调度器，未配置 `UPSTREAM_MAX_CONCURRENCY` 时为 `None`
+    pub scheduler: Option<Arc<scheduler::FairScheduler>>,
+    pub experiments: Arc<experiments::Experiments>,
+    pub fanout_models: Arc<fanout::FanoutModels>,
+    pub tool_emulation: Arc<tool_emulation::ToolEmulation>,
+    pub price_table: Arc<pricing::PriceTable>,
+    pub token_quota: Arc<quota::TokenQuota>,
+    pub provider_health: Arc<health::ProviderHealth>,
+    pub model_catalog: Arc<models::ModelCatalog>,
+    pub pipeline: Arc<pipeline::RequestPipeline>,
 }
 
 #[tokio::main]
@@ -18,12 +110,29 @@ async fn main() {
     // 加载 .env 文件
     dotenvy::dotenv().ok();
 
+    let cli = cli::Cli::parse();
+    match cli.command.unwrap_or(cli::Command::Serve) {
+        cli::Command::Serve => serve().await,
+        cli::Command::CheckConfig => {
+            if !cli::check_config() {
+                std::process::exit(1);
+            }
+        }
+        cli::Command::TestUpstream => {
+            if !cli::test_upstream().await {
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+/// 启动 HTTP 服务
+async fn serve() {
     // 初始化日志
-    tracing_subscriber::fmt()
-        .pretty()
-        .with_timer(LocalTime::rfc_3339())
-        .with_max_level(Level::DEBUG)
-        .init();
+    let log_filter = logging::init();
+
+    // 初始化指标
+    let metrics = metrics::init();
 
     // 从环境变量读取 API 密钥，如果不存在则退出
     let api_key = std::env::var("DEEPSEEK_API_KEY")
@@ -31,25 +140,203 @@ async fn main() {
 
     // 创建应用状态
     let state = AppState {
-        http_client: Client::new(),
+        http_client: http_client::HttpClientConfig::from_env()
+            .and_then(http_client::HttpClientConfig::build)
+            .expect("上游连接配置无效"),
         api_key,
+        upstream_overrides: Arc::new(
+            overrides::UpstreamOverrides::from_env().expect("上游覆盖配置无效"),
+        ),
+        ip_acl: Arc::new(ip_acl::IpAcl::from_env().expect("网络访问控制配置无效")),
+        client_keys: Arc::new(keys::ClientKeys::from_env().expect("CLIENT_KEYS_FILE 配置无效")),
+        jwt_auth: Arc::new(jwt::JwtAuth::from_env().expect("OIDC 配置无效")),
+        abuse_detector: Arc::new(abuse::AbuseDetector::from_env().expect("滥用检测配置无效")),
+        admin_api_key: std::env::var("ADMIN_API_KEY").ok(),
+        log_filter,
+        concurrency_limits: Arc::new(
+            concurrency::ConcurrencyLimits::from_env().expect("CONCURRENCY_LIMITS 配置无效"),
+        ),
+        response_log_max_bytes: std::env::var("RESPONSE_LOG_MAX_BYTES")
+            .ok()
+            .map(|value| value.parse().expect("RESPONSE_LOG_MAX_BYTES 必须是整数")),
+        context: Arc::new(context::ContextConfig::from_env().expect("上下文压缩配置无效")),
+        cancel_registry: Arc::default(),
+        resume_store: resume::ResumeStore::from_env().expect("流式续传配置无效"),
+        upstream_retry: Arc::new(retry::UpstreamRetry::from_env().expect("上游重试配置无效")),
+        stream_error_retries: std::env::var("STREAM_ERROR_RETRIES")
+            .ok()
+            .map_or(0, |value| {
+                value.parse().expect("STREAM_ERROR_RETRIES 必须是整数")
+            }),
+        sse_keepalive: Some(match std::env::var("SSE_KEEPALIVE_SECS") {
+            Ok(value) => value.parse().expect("SSE_KEEPALIVE_SECS 必须是整数"),
+            Err(_) => DEFAULT_SSE_KEEPALIVE_SECS,
+        })
+        .filter(|secs| *secs > 0)
+        .map(Duration::from_secs),
+        idempotency: Arc::new(idempotency::IdempotencyStore::from_env().expect("幂等键配置无效")),
+        redaction_mode: redaction::RedactionMode::from_env().expect("PII_REDACTION 配置无效"),
+        plugins: Arc::new(plugins::Plugins::from_env().expect("WASM_PLUGINS 配置无效")),
+        route_scripts: Arc::new(scripts::RouteScripts::from_env().expect("ROUTE_SCRIPTS 配置无效")),
+        metrics,
+        load_shedder: Arc::new(load_shed::LoadShedder::from_env().expect("降载阈值配置无效")),
+        scheduler: scheduler::FairScheduler::from_env().expect("上游调度配置无效"),
+        experiments: Arc::new(experiments::Experiments::from_env().expect("EXPERIMENTS 配置无效")),
+        fanout_models: Arc::new(fanout::FanoutModels::from_env()),
+        tool_emulation: Arc::new(tool_emulation::ToolEmulation::from_env()),
+        price_table: Arc::new(pricing::PriceTable::from_env().expect("PRICE_TABLE 配置无效")),
+        token_quota: Arc::new(quota::TokenQuota::from_env().expect("token 配额配置无效")),
+        provider_health: Arc::new(
+            health::ProviderHealth::from_env().expect("HEALTH_PROBE_INTERVAL_SECS 配置无效"),
+        ),
+        model_catalog: Arc::new(models::ModelCatalog::from_env().expect("模型目录配置无效")),
+        pipeline: Arc::new(pipeline::RequestPipeline::from_env().expect("REQUEST_STAGES 配置无效")),
     };
+    tracing::info!("请求处理阶段: {}", state.pipeline.names().join(" -> "));
+    state.load_shedder.clone().spawn_sampler();
+    state.idempotency.clone().spawn_sweeper();
+    if let Some(resume_store) = &state.resume_store {
+        resume_store.clone().spawn_sweeper();
+    }
+    state
+        .provider_health
+        .clone()
+        .spawn_prober(state.http_client.clone(), state.api_key.clone());
+    state.model_catalog.clone().spawn_prober(state.clone());
+    http_client::PoolWarmup::from_env()
+        .expect("连接预热配置无效")
+        .spawn(state.http_client.clone(), state.api_key.clone());
+
+    // 响应压缩：默认跳过 SSE、图片与 gRPC，额外跳过音频流，仅压缩 JSON 等文本响应
+    let compression = CompressionLayer::new()
+        .gzip(true)
+        .br(true)
+        .compress_when(DefaultPredicate::new().and(NotForContentType::const_new("audio/")));
+
+    // 跨域：允许的来源、预检缓存时间与局域网访问
+    let cors = cors::CorsConfig::from_env().expect("CORS 配置无效").layer();
 
-    // 创建路由
+    // 队列消息中不可信的客户端来源请求头
+    let client_headers = state.ip_acl.client_headers();
+
+    // 请求 ID：沿用客户端传入的 x-request-id，否则生成 UUID，并回写到响应头
+    let request_id_header = HeaderName::from_static(logging::REQUEST_ID_HEADER);
+
+    // 管理接口
+    let admin = Router::new()
+        .route(
+            "/log-filter",
+            get(handlers::admin::get_log_filter).put(handlers::admin::put_log_filter),
+        )
+        .route("/prices", get(pricing::get_prices))
+        .route("/risks", get(abuse::get_risks))
+        .route("/quotas", get(quota::get_quotas))
+        .route(
+            "/quotas/{client}",
+            put(quota::put_quota).delete(quota::delete_quota),
+        )
+        .route("/quotas/{client}/reset", post(quota::reset_quota))
+        .route("/keys", get(keys::list_keys).post(keys::create_key))
+        .route("/keys/{id}", delete(keys::revoke_key))
+        .route(
+            "/prices/{model}",
+            put(pricing::put_price).delete(pricing::delete_price),
+        )
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            handlers::admin::require_admin,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            ip_acl::check_admin_ip,
+        ));
+
+    // 创建路由，会产生新上游调用的路由在降载期间被拒绝
     let app = Router::new()
         .route(
             "/chat/completions",
             post(handlers::chat_completions::handle_chat_completions),
         )
+        .route("/translate", post(handlers::translate::handle_translate))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            scheduler::schedule_fairly,
+        ))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            load_shed::shed_load,
+        ))
+        .route(
+            "/chat/completions/{completion_id}/cancel",
+            post(handlers::chat_completions::handle_cancel),
+        )
+        .route(
+            "/chat/completions/{completion_id}/resume",
+            get(resume::handle_resume),
+        )
+        .route("/models", get(models::handle_models))
+        .route("/status/providers", get(health::handle_provider_status))
+        .route("/cost/estimate", post(pricing::handle_cost_estimate))
+        // 以上客户端接口在启用客户端密钥或 JWT 时需要鉴权
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            keys::require_client_auth,
+        ))
+        .route("/metrics", get(metrics::handle_metrics))
+        .route("/openapi.json", get(openapi::handle_openapi))
+        .route("/docs", get(openapi::handle_docs))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            concurrency::limit_concurrency,
+        ))
+        .nest("/admin", admin)
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            ip_acl::check_ip,
+        ))
         .with_state(state)
-        .layer(CorsLayer::permissive())
-        .layer(TraceLayer::new_for_http());
+        .layer(compression)
+        .layer(cors)
+        .layer(PropagateRequestIdLayer::new(request_id_header.clone()))
+        .layer(TraceLayer::new_for_http().make_span_with(logging::make_request_span))
+        .layer(SetRequestIdLayer::new(request_id_header, MakeRequestUuid));
+
+    // 配置了 NATS 时同时从消息队列接收生成请求
+    if let Some(queue_config) = queue::QueueConfig::from_env().expect("NATS 队列配置无效") {
+        queue::spawn_consumer(queue_config, app.clone(), client_headers)
+            .await
+            .expect("连接 NATS 失败");
+    }
+
+    // 配置了证书时直接终止 TLS（HTTP/1.1 与 HTTP/2 通过 ALPN 协商）
+    if let Some(tls_paths) = tls::TlsPaths::from_env() {
+        let config = tls_paths
+            .load()
+            .await
+            .expect("加载 TLS 证书失败，请检查 TLS_CERT_PATH 与 TLS_KEY_PATH");
+        tls_paths.spawn_reload_on_sighup(config.clone());
+
+        let addr = "0.0.0.0:3000".parse().unwrap();
+
+        println!("🚀 服务器启动在 https://localhost:3000");
+
+        axum_server::bind_rustls(addr, config)
+            .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+            .await
+            .unwrap();
+        return;
+    }
 
     // 绑定地址
     let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
 
     println!("🚀 服务器启动在 http://localhost:3000");
 
-    // 启动服务器
-    axum::serve(listener, app).await.unwrap();
+    // 启动服务器（明文连接同样支持 HTTP/2 prior knowledge）
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await
+    .unwrap();
 }
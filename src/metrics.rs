@@ -0,0 +1,48 @@
+use axum::extract::State;
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
+
+use crate::AppState;
+
+/// 流式对话从发出上游请求到收到第一个 token 的耗时（秒），标签 `model`
+pub const CHAT_TIME_TO_FIRST_TOKEN: &str = "chat_time_to_first_token_seconds";
+
+/// 首 token 耗时的分桶，推理模型的思考时间可能长达数十秒
+const TIME_TO_FIRST_TOKEN_BUCKETS: &[f64] = &[
+    0.1, 0.25, 0.5, 0.75, 1.0, 1.5, 2.0, 3.0, 5.0, 10.0, 20.0, 30.0, 60.0,
+];
+
+/// 上游域名解析耗时（秒），标签 `result`
+pub const DNS_RESOLUTION_SECONDS: &str = "dns_resolution_seconds";
+
+/// 域名解析耗时的分桶
+const DNS_RESOLUTION_BUCKETS: &[f64] = &[
+    0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0,
+];
+
+/// 安装 Prometheus 指标记录器
+pub fn init() -> PrometheusHandle {
+    PrometheusBuilder::new()
+        .set_buckets_for_metric(
+            Matcher::Full(CHAT_TIME_TO_FIRST_TOKEN.to_string()),
+            TIME_TO_FIRST_TOKEN_BUCKETS,
+        )
+        .expect("指标分桶配置无效")
+        .set_buckets_for_metric(
+            Matcher::Full(DNS_RESOLUTION_SECONDS.to_string()),
+            DNS_RESOLUTION_BUCKETS,
+        )
+        .expect("指标分桶配置无效")
+        .install_recorder()
+        .expect("安装 Prometheus 指标记录器失败")
+}
+
+/// 以 Prometheus 文本格式输出当前指标
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    tag = "status",
+    responses((status = 200, description = "Prometheus 文本格式", body = String, content_type = "text/plain")),
+)]
+pub async fn handle_metrics(State(state): State<AppState>) -> String {
+    state.metrics.render()
+}
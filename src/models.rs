@@ -0,0 +1,412 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use axum::{Json, body::Bytes, extract::State, http::StatusCode};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value, json};
+use tokio::sync::Mutex;
+use utoipa::ToSchema;
+
+use crate::{
+    AppState, chat::ChatCompletionRequest,
+    handlers::chat_completions::UPSTREAM_CHAT_COMPLETIONS_URL, health::UPSTREAM_MODELS_URL,
+    pricing::ModelPrice,
+};
+
+/// 模型列表的默认缓存时间
+const DEFAULT_CACHE_TTL_SECS: u64 = 300;
+
+/// 本地别名在列表中的 `owned_by`
+const ALIAS_OWNER: &str = "free-model";
+
+/// 探测视觉能力使用的 1x1 PNG
+const PROBE_IMAGE: &str = "data:image/png;base64,iVBORw0KGgoAAAANSUhEUgAAAAEAAAABCAYAAAAfFcSJAAAADUlEQVR42mP8z8BQDwAEhQGAhKmMIQAAAABJRU5ErkJggg==";
+
+/// 模型能力，未知的项为 `None`
+#[derive(Clone, Default, Serialize, Deserialize, ToSchema)]
+pub struct Capabilities {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vision: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tools: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub json_mode: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub streaming: Option<bool>,
+}
+
+impl Capabilities {
+    /// 用另一组能力中已知的项覆盖当前值
+    fn merge(&mut self, other: &Capabilities) {
+        self.vision = other.vision.or(self.vision);
+        self.tools = other.tools.or(self.tools);
+        self.json_mode = other.json_mode.or(self.json_mode);
+        self.streaming = other.streaming.or(self.streaming);
+    }
+}
+
+/// 模型列表中附带的能力与上下文窗口
+#[derive(Clone, Default, Serialize, Deserialize, ToSchema)]
+pub struct ModelCapabilities {
+    #[serde(flatten)]
+    capabilities: Capabilities,
+    /// 上下文窗口 token 数
+    max_context: usize,
+}
+
+/// 模型列表中的一项，上游返回的其余字段原样保留
+#[derive(Clone, Serialize, Deserialize, ToSchema)]
+pub struct ModelEntry {
+    pub id: String,
+    #[serde(default)]
+    pub object: String,
+    #[serde(default)]
+    pub owned_by: String,
+    /// 别名对应的实际模型，只有本地别名有此字段
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alias_of: Option<String>,
+    /// 由代理补充，上游返回的同名字段会被覆盖
+    #[serde(default)]
+    pub capabilities: ModelCapabilities,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pricing: Option<ModelPrice>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// `GET /models` 的响应，格式与 OpenAI 一致
+#[derive(Serialize, ToSchema)]
+pub struct ModelList {
+    /// 固定为 `list`
+    pub object: String,
+    pub data: Vec<ModelEntry>,
+}
+
+/// 内置的 DeepSeek 模型能力
+fn builtin_capabilities(model: &str) -> Capabilities {
+    match model {
+        "deepseek-chat" => Capabilities {
+            vision: Some(false),
+            tools: Some(true),
+            json_mode: Some(true),
+            streaming: Some(true),
+        },
+        "deepseek-reasoner" => Capabilities {
+            vision: Some(false),
+            streaming: Some(true),
+            ..Default::default()
+        },
+        _ => Capabilities::default(),
+    }
+}
+
+/// 模型目录
+///
+/// `GET /models` 返回上游模型列表，按 `MODEL_ALLOWLIST`（逗号分隔）过滤，并追加 `MODEL_ALIASES`
+/// （`别名=模型`，逗号分隔）中的别名；上游列表缓存 `MODELS_CACHE_TTL_SECS` 秒（默认 300），
+/// 上游不可用时返回过期的缓存。对话请求中的别名在转发前替换为对应的模型；配置白名单时，
+/// 对话与翻译请求的模型既不是别名也不在白名单中时返回 404。
+///
+/// 每个模型附带能力标记与价格：能力依次取内置值、探测结果（`MODEL_CAPABILITY_PROBE=true` 时启动后
+/// 对每个模型发送极小的请求，按是否被上游拒绝判断）与 `MODEL_CAPABILITIES`（JSON 对象，模型名到能力）中的配置，
+/// 后者优先；上下文窗口取自 `MODEL_CONTEXT_WINDOWS`，价格取自价格表。
+pub struct ModelCatalog {
+    allowlist: Vec<String>,
+    aliases: HashMap<String, String>,
+    configured: HashMap<String, Capabilities>,
+    probe: bool,
+    probed: RwLock<HashMap<String, Capabilities>>,
+    ttl: Duration,
+    /// 上游模型列表与获取时间，持锁请求上游，避免缓存过期时并发重复请求
+    cache: Mutex<Option<(Instant, Vec<ModelEntry>)>>,
+}
+
+impl ModelCatalog {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let allowlist = std::env::var("MODEL_ALLOWLIST")
+            .map(|config| {
+                config
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|s| !s.is_empty())
+                    .map(str::to_string)
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let mut aliases = HashMap::new();
+        if let Ok(config) = std::env::var("MODEL_ALIASES") {
+            for entry in config.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+                let (alias, model) = entry
+                    .split_once('=')
+                    .ok_or_else(|| anyhow::anyhow!("MODEL_ALIASES 格式错误: {}", entry))?;
+                aliases.insert(alias.trim().to_string(), model.trim().to_string());
+            }
+        }
+
+        let ttl = match std::env::var("MODELS_CACHE_TTL_SECS") {
+            Ok(value) => value.parse()?,
+            Err(_) => DEFAULT_CACHE_TTL_SECS,
+        };
+
+        let configured = match std::env::var("MODEL_CAPABILITIES") {
+            Ok(config) => serde_json::from_str(&config).context("MODEL_CAPABILITIES 格式错误")?,
+            Err(_) => HashMap::new(),
+        };
+        let probe = std::env::var("MODEL_CAPABILITY_PROBE")
+            .map(|value| value == "true")
+            .unwrap_or(false);
+
+        Ok(Self {
+            allowlist,
+            aliases,
+            configured,
+            probe,
+            probed: RwLock::default(),
+            ttl: Duration::from_secs(ttl),
+            cache: Mutex::default(),
+        })
+    }
+
+    /// 是否需要检查请求的模型，即配置了别名或白名单
+    pub fn checks_requests(&self) -> bool {
+        !self.aliases.is_empty() || !self.allowlist.is_empty()
+    }
+
+    fn allowed(&self, model: &str) -> bool {
+        self.allowlist.is_empty() || self.allowlist.iter().any(|m| m == model)
+    }
+
+    /// 把请求的模型解析为实际模型：别名替换为对应的模型，不在白名单中的模型返回 404
+    pub fn resolve_model(&self, model: &str) -> Result<String, (StatusCode, String)> {
+        if let Some(target) = self.aliases.get(model) {
+            return Ok(target.clone());
+        }
+        if !self.allowed(model) {
+            return Err((StatusCode::NOT_FOUND, format!("模型 {} 不存在", model)));
+        }
+        Ok(model.to_string())
+    }
+
+    /// 按 [`resolve_model`](Self::resolve_model) 改写请求体中的模型
+    ///
+    /// 配置白名单时无法解析的请求体返回 400，否则原样转发，由上游校验并报错。
+    pub fn resolve_request(&self, body: Bytes) -> Result<Bytes, (StatusCode, String)> {
+        let Some(mut request) = ChatCompletionRequest::parse(&body) else {
+            if self.allowlist.is_empty() {
+                return Ok(body);
+            }
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "请求体不是有效的对话请求".to_string(),
+            ));
+        };
+        let model = self.resolve_model(&request.model)?;
+        if model == request.model {
+            return Ok(body);
+        }
+        request.model = model;
+        request.to_bytes()
+    }
+
+    /// 合并后的模型能力
+    fn capabilities(&self, model: &str) -> Capabilities {
+        let mut capabilities = builtin_capabilities(model);
+        if let Some(probed) = self.probed.read().unwrap().get(model) {
+            capabilities.merge(probed);
+        }
+        if let Some(configured) = self.configured.get(model) {
+            capabilities.merge(configured);
+        }
+        capabilities
+    }
+
+    /// 为模型条目补充能力、上下文窗口与价格
+    fn describe(&self, state: &AppState, entry: &mut ModelEntry, model: &str) {
+        entry.capabilities = ModelCapabilities {
+            capabilities: self.capabilities(model),
+            max_context: state.context.context_window(model),
+        };
+        entry.pricing = state.price_table.get(model);
+    }
+
+    /// 启用探测时在后台探测上游每个模型的能力
+    pub fn spawn_prober(self: Arc<Self>, state: AppState) {
+        if !self.probe {
+            return;
+        }
+        tokio::spawn(async move {
+            let models = match self.upstream_models(&state).await {
+                Ok(models) => models,
+                Err((_, e)) => {
+                    tracing::warn!("获取上游模型列表失败，跳过能力探测: {}", e);
+                    return;
+                }
+            };
+            for model in models.iter().map(|model| model.id.as_str()) {
+                let capabilities = probe_model(&state, model).await;
+                tracing::info!(
+                    model,
+                    vision = ?capabilities.vision,
+                    tools = ?capabilities.tools,
+                    json_mode = ?capabilities.json_mode,
+                    streaming = ?capabilities.streaming,
+                    "模型能力探测完成"
+                );
+                self.probed
+                    .write()
+                    .unwrap()
+                    .insert(model.to_string(), capabilities);
+            }
+        });
+    }
+
+    /// 取上游模型列表，缓存未过期时直接返回
+    async fn upstream_models(
+        &self,
+        state: &AppState,
+    ) -> Result<Vec<ModelEntry>, (StatusCode, String)> {
+        let mut cache = self.cache.lock().await;
+        if let Some((fetched_at, models)) = cache.as_ref()
+            && fetched_at.elapsed() < self.ttl
+        {
+            return Ok(models.clone());
+        }
+
+        match fetch_models(state).await {
+            Ok(models) => {
+                *cache = Some((Instant::now(), models.clone()));
+                Ok(models)
+            }
+            Err(e) => match cache.as_ref() {
+                Some((_, models)) => {
+                    tracing::warn!("获取上游模型列表失败，使用过期缓存: {}", e);
+                    Ok(models.clone())
+                }
+                None => Err((StatusCode::BAD_GATEWAY, e)),
+            },
+        }
+    }
+}
+
+async fn fetch_models(state: &AppState) -> Result<Vec<ModelEntry>, String> {
+    let response = state
+        .http_client
+        .get(UPSTREAM_MODELS_URL)
+        .bearer_auth(&state.api_key)
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    if !response.status().is_success() {
+        return Err(format!("上游返回 {}", response.status()));
+    }
+    #[derive(Deserialize)]
+    struct UpstreamModels {
+        #[serde(default)]
+        data: Vec<ModelEntry>,
+    }
+    let body: UpstreamModels = response.json().await.map_err(|e| e.to_string())?;
+    Ok(body.data)
+}
+
+/// 逐项探测模型能力：上游接受请求即支持，返回 400 即不支持，其余结果视为未知
+async fn probe_model(state: &AppState, model: &str) -> Capabilities {
+    let message = |content: Value| json!([{ "role": "user", "content": content }]);
+    let probes = [
+        json!({
+            "messages": message(json!([
+                { "type": "text", "text": "hi" },
+                { "type": "image_url", "image_url": { "url": PROBE_IMAGE } },
+            ])),
+        }),
+        json!({
+            "messages": message(json!("hi")),
+            "tools": [{
+                "type": "function",
+                "function": { "name": "ping", "parameters": { "type": "object", "properties": {} } },
+            }],
+        }),
+        json!({
+            "messages": message(json!("Reply with an empty json object")),
+            "response_format": { "type": "json_object" },
+        }),
+        json!({ "messages": message(json!("hi")), "stream": true }),
+    ];
+
+    let mut results = Vec::with_capacity(probes.len());
+    for mut probe in probes {
+        probe["model"] = json!(model);
+        probe["max_tokens"] = json!(1);
+        let response = state
+            .http_client
+            .post(UPSTREAM_CHAT_COMPLETIONS_URL)
+            .bearer_auth(&state.api_key)
+            .json(&probe)
+            .send()
+            .await;
+        results.push(match response {
+            Ok(response) if response.status().is_success() => Some(true),
+            Ok(response) if response.status() == StatusCode::BAD_REQUEST => Some(false),
+            _ => None,
+        });
+    }
+    Capabilities {
+        vision: results[0],
+        tools: results[1],
+        json_mode: results[2],
+        streaming: results[3],
+    }
+}
+
+/// 列出可用模型，格式与 OpenAI `GET /models` 一致
+#[utoipa::path(
+    get,
+    path = "/models",
+    tag = "chat",
+    responses(
+        (status = 200, description = "模型列表", body = ModelList),
+        (status = 502, description = "上游不可用且没有缓存", body = String),
+    ),
+)]
+pub async fn handle_models(
+    State(state): State<AppState>,
+) -> Result<Json<ModelList>, (StatusCode, String)> {
+    let catalog = &state.model_catalog;
+    let mut data: Vec<ModelEntry> = catalog
+        .upstream_models(&state)
+        .await?
+        .into_iter()
+        .filter(|model| catalog.allowed(&model.id))
+        .collect();
+    for entry in &mut data {
+        let model = entry.id.clone();
+        catalog.describe(&state, entry, &model);
+    }
+
+    let mut aliases: Vec<(&String, &String)> = catalog.aliases.iter().collect();
+    aliases.sort();
+    for (alias, model) in aliases {
+        if catalog.allowed(alias) || catalog.allowed(model) {
+            let mut entry = ModelEntry {
+                id: alias.clone(),
+                object: "model".to_string(),
+                owned_by: ALIAS_OWNER.to_string(),
+                alias_of: Some(model.clone()),
+                capabilities: ModelCapabilities::default(),
+                pricing: None,
+                extra: Map::new(),
+            };
+            catalog.describe(&state, &mut entry, model);
+            data.push(entry);
+        }
+    }
+
+    Ok(Json(ModelList {
+        object: "list".to_string(),
+        data,
+    }))
+}
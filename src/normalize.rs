@@ -0,0 +1,423 @@
+use axum::body::Bytes;
+use serde_json::{Map, Value, json};
+
+use crate::chat::{ChatCompletionResponse, Choice};
+
+/// 其他上游表示思考内容的字段名，统一改为 `reasoning_content`
+const REASONING_ALIASES: [&str; 2] = ["reasoning", "thinking"];
+
+/// 上游错误没有给出类型时使用的 `error.type`
+const UPSTREAM_ERROR_TYPE: &str = "upstream_error";
+
+/// 把非流式响应中各 choice 的字段转换为统一格式，没有需要转换的内容时原样返回
+pub fn normalize_response(body: Bytes) -> Bytes {
+    let Some(mut response) = ChatCompletionResponse::parse(&body) else {
+        return body;
+    };
+    if !normalize_chunk(&mut response) {
+        return body;
+    }
+    response.to_json().map_or(body, Bytes::from)
+}
+
+/// 把响应或流式数据块中各 choice 的字段转换为统一格式，返回是否有改动
+///
+/// 默认上游（DeepSeek）的响应已是 OpenAI 格式；通过 `X-Upstream-Base-Url` 或路由脚本改用其他上游时，
+/// 各家在细节上的差异在这里抹平，客户端始终看到同一种格式。
+pub fn normalize_chunk(chunk: &mut ChatCompletionResponse) -> bool {
+    let mut changed = false;
+    for choice in chunk.choices_mut() {
+        changed |= normalize_choice(choice);
+    }
+    changed
+}
+
+fn normalize_choice(choice: &mut Choice) -> bool {
+    let mut changed = choice
+        .extra
+        .get_mut("logprobs")
+        .is_some_and(normalize_logprobs);
+    if let Some(reason) = choice.finish_reason.as_deref().and_then(finish_reason) {
+        choice.finish_reason = Some(reason.to_string());
+        changed = true;
+    }
+    if let Some(delta) = choice.delta.as_mut()
+        && delta.reasoning_content.is_none()
+        && let Some(reasoning) = take_reasoning(&mut delta.extra)
+    {
+        delta.reasoning_content = Some(reasoning);
+        changed = true;
+    }
+    if let Some(message) = choice.message.as_mut()
+        && !message.extra.contains_key("reasoning_content")
+        && let Some(reasoning) = take_reasoning(&mut message.extra)
+    {
+        message
+            .extra
+            .insert("reasoning_content".to_string(), Value::String(reasoning));
+        changed = true;
+    }
+    changed
+}
+
+/// 其他上游的结束原因对应的 OpenAI 取值，已是 OpenAI 取值或无法识别时返回 `None`
+fn finish_reason(reason: &str) -> Option<&'static str> {
+    let normalized = match reason.to_ascii_lowercase().as_str() {
+        "stop" | "end_turn" | "stop_sequence" | "eos" | "eos_token" => "stop",
+        "length" | "max_tokens" | "model_length" => "length",
+        "tool_calls" | "tool_use" | "tool_call" => "tool_calls",
+        "content_filter" | "safety" | "content_filtered" => "content_filter",
+        _ => return None,
+    };
+    (normalized != reason).then_some(normalized)
+}
+
+/// 取出以其他字段名表示的思考内容
+fn take_reasoning(extra: &mut Map<String, Value>) -> Option<String> {
+    let alias = REASONING_ALIASES
+        .into_iter()
+        .find(|alias| extra.get(*alias).is_some_and(Value::is_string))?;
+    match extra.remove(alias) {
+        Some(Value::String(reasoning)) => Some(reasoning),
+        _ => None,
+    }
+}
+
+/// 识别流中的错误事件并转换为统一的 `{"message", "type", "code"}`，不是错误事件时返回 `None`
+///
+/// 支持 `event: error` 事件，以及数据为 `{"error": ...}`（字符串或对象）或 `{"object": "error", ...}` 的事件。
+pub fn stream_error(event: Option<&str>, data: &str) -> Option<Value> {
+    let value: Value =
+        serde_json::from_str(data).unwrap_or_else(|_| Value::String(data.to_string()));
+    let error = match value {
+        Value::Object(mut fields)
+            if fields.contains_key("error") && !fields.contains_key("choices") =>
+        {
+            fields.remove("error").unwrap_or_default()
+        }
+        Value::Object(fields) if fields.get("object").and_then(Value::as_str) == Some("error") => {
+            Value::Object(fields)
+        }
+        value if event == Some("error") => value,
+        _ => return None,
+    };
+    Some(error_object(error))
+}
+
+fn error_object(error: Value) -> Value {
+    let (message, kind, code) = match error {
+        Value::Object(fields) => {
+            let message = match fields.get("message").and_then(Value::as_str) {
+                Some(message) => message.to_string(),
+                None => Value::Object(fields.clone()).to_string(),
+            };
+            let kind = fields
+                .get("type")
+                .and_then(Value::as_str)
+                .filter(|kind| *kind != "error")
+                .unwrap_or(UPSTREAM_ERROR_TYPE)
+                .to_string();
+            let code = fields.get("code").cloned().unwrap_or(Value::Null);
+            (message, kind, code)
+        }
+        Value::String(message) => (message, UPSTREAM_ERROR_TYPE.to_string(), Value::Null),
+        other => (
+            other.to_string(),
+            UPSTREAM_ERROR_TYPE.to_string(),
+            Value::Null,
+        ),
+    };
+    json!({ "message": message, "type": kind, "code": code })
+}
+
+/// 把 `logprobs` 统一为 OpenAI Chat 格式：`{"content": [{"token", "logprob", "bytes", "top_logprobs": [...]}]}`
+///
+/// 兼容两种常见的其他格式：直接返回条目数组，以及 Completions 接口的
+/// `{"tokens", "token_logprobs", "top_logprobs": [{token: logprob}]}`；条目中以对象表示的 `top_logprobs` 转为数组。
+fn normalize_logprobs(logprobs: &mut Value) -> bool {
+    match logprobs {
+        Value::Array(entries) => {
+            let mut entries = std::mem::take(entries);
+            entries.iter_mut().for_each(|entry| {
+                normalize_entry(entry);
+            });
+            *logprobs = json!({ "content": entries });
+            true
+        }
+        Value::Object(fields) if fields.contains_key("tokens") => {
+            let tokens = fields.get("tokens").and_then(Value::as_array);
+            let token_logprobs = fields.get("token_logprobs").and_then(Value::as_array);
+            let top_logprobs = fields.get("top_logprobs").and_then(Value::as_array);
+            let content: Vec<Value> = tokens
+                .into_iter()
+                .flatten()
+                .enumerate()
+                .map(|(index, token)| {
+                    let token = token.as_str().unwrap_or_default();
+                    let logprob = token_logprobs
+                        .and_then(|logprobs| logprobs.get(index))
+                        .cloned()
+                        .unwrap_or(Value::Null);
+                    let top = match top_logprobs.and_then(|top| top.get(index)) {
+                        Some(Value::Object(top)) => top_list(top),
+                        _ => Vec::new(),
+                    };
+                    entry(token, logprob, top)
+                })
+                .collect();
+            *logprobs = json!({ "content": content });
+            true
+        }
+        Value::Object(fields) => {
+            let mut changed = false;
+            for key in ["content", "refusal"] {
+                if let Some(Value::Array(entries)) = fields.get_mut(key) {
+                    for entry in entries {
+                        changed |= normalize_entry(entry);
+                    }
+                }
+            }
+            changed
+        }
+        _ => false,
+    }
+}
+
+/// 把条目中以对象（`{token: logprob}`）表示的 `top_logprobs` 转为数组，返回是否有改动
+fn normalize_entry(entry: &mut Value) -> bool {
+    let Some(Value::Object(top)) = entry.get("top_logprobs") else {
+        return false;
+    };
+    let top = top_list(top);
+    entry["top_logprobs"] = Value::Array(top);
+    true
+}
+
+/// `{token: logprob}` 按概率从高到低转为条目数组
+fn top_list(top: &Map<String, Value>) -> Vec<Value> {
+    let mut top: Vec<(&String, &Value)> = top.iter().collect();
+    top.sort_by(|a, b| {
+        let logprob = |value: &Value| value.as_f64().unwrap_or(f64::NEG_INFINITY);
+        logprob(b.1).total_cmp(&logprob(a.1))
+    });
+    top.into_iter()
+        .map(|(token, logprob)| entry(token, logprob.clone(), Vec::new()))
+        .collect()
+}
+
+/// 一个 OpenAI 格式的 token 条目，`bytes` 为 token 的 UTF-8 字节
+fn entry(token: &str, logprob: Value, top_logprobs: Vec<Value>) -> Value {
+    let mut entry = json!({
+        "token": token,
+        "logprob": logprob,
+        "bytes": token.as_bytes(),
+    });
+    if !top_logprobs.is_empty() {
+        entry["top_logprobs"] = Value::Array(top_logprobs);
+    }
+    entry
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn logprobs(response: &ChatCompletionResponse) -> &Value {
+        &response.choices()[0].extra["logprobs"]
+    }
+
+    #[test]
+    fn passes_openai_logprobs_through() {
+        let body = json!({
+            "id": "c1",
+            "object": "chat.completion.chunk",
+            "choices": [{
+                "index": 0,
+                "delta": { "content": "Hi" },
+                "logprobs": {
+                    "content": [{
+                        "token": "Hi",
+                        "logprob": -0.1,
+                        "bytes": [72, 105],
+                        "top_logprobs": [{ "token": "Hi", "logprob": -0.1, "bytes": [72, 105] }],
+                    }],
+                },
+                "finish_reason": "stop",
+            }],
+        });
+        let mut chunk = ChatCompletionResponse::parse(body.to_string().as_bytes()).unwrap();
+        assert!(!normalize_chunk(&mut chunk));
+
+        // 还原占位符等改写会重新序列化数据块，logprobs 与结束原因保持不变
+        let rewritten: Value = serde_json::from_str(&chunk.to_json().unwrap()).unwrap();
+        assert_eq!(rewritten, body);
+    }
+
+    #[test]
+    fn converts_completions_style_logprobs() {
+        let body = json!({
+            "choices": [{
+                "index": 0,
+                "message": { "role": "assistant", "content": "Hi!" },
+                "logprobs": {
+                    "tokens": ["Hi", "!"],
+                    "token_logprobs": [-0.1, -0.5],
+                    "top_logprobs": [{ "Hello": -2.0, "Hi": -0.1 }, { "!": -0.5 }],
+                    "text_offset": [0, 2],
+                },
+                "finish_reason": "stop",
+            }],
+        });
+        let normalized = normalize_response(Bytes::from(body.to_string()));
+        let response = ChatCompletionResponse::parse(&normalized).unwrap();
+        assert_eq!(
+            logprobs(&response),
+            &json!({
+                "content": [
+                    {
+                        "token": "Hi",
+                        "logprob": -0.1,
+                        "bytes": [72, 105],
+                        "top_logprobs": [
+                            { "token": "Hi", "logprob": -0.1, "bytes": [72, 105] },
+                            { "token": "Hello", "logprob": -2.0, "bytes": [72, 101, 108, 108, 111] },
+                        ],
+                    },
+                    {
+                        "token": "!",
+                        "logprob": -0.5,
+                        "bytes": [33],
+                        "top_logprobs": [{ "token": "!", "logprob": -0.5, "bytes": [33] }],
+                    },
+                ],
+            })
+        );
+    }
+
+    #[test]
+    fn wraps_entry_arrays_and_top_logprob_maps() {
+        let body = json!({
+            "choices": [{
+                "index": 0,
+                "delta": { "content": "a" },
+                "logprobs": [{ "token": "a", "logprob": -1.0, "top_logprobs": { "a": -1.0, "b": -1.5 } }],
+            }],
+        });
+        let mut chunk = ChatCompletionResponse::parse(body.to_string().as_bytes()).unwrap();
+        assert!(normalize_chunk(&mut chunk));
+        assert_eq!(
+            logprobs(&chunk),
+            &json!({
+                "content": [{
+                    "token": "a",
+                    "logprob": -1.0,
+                    "top_logprobs": [
+                        { "token": "a", "logprob": -1.0, "bytes": [97] },
+                        { "token": "b", "logprob": -1.5, "bytes": [98] },
+                    ],
+                }],
+            })
+        );
+    }
+
+    #[test]
+    fn maps_reasoning_fields_and_finish_reasons() {
+        let body = json!({
+            "choices": [
+                { "index": 0, "delta": { "reasoning": "hmm" }, "finish_reason": null },
+                { "index": 1, "delta": { "thinking": "so" }, "finish_reason": "max_tokens" },
+                { "index": 2, "delta": {}, "finish_reason": "end_turn" },
+                { "index": 3, "delta": {}, "finish_reason": "STOP" },
+                { "index": 4, "delta": {}, "finish_reason": "tool_use" },
+            ],
+        });
+        let mut chunk = ChatCompletionResponse::parse(body.to_string().as_bytes()).unwrap();
+        assert!(normalize_chunk(&mut chunk));
+        let choices = chunk.choices();
+        let reasoning = |index: usize| {
+            choices[index]
+                .delta
+                .as_ref()
+                .unwrap()
+                .reasoning_content
+                .as_deref()
+        };
+        assert_eq!(reasoning(0), Some("hmm"));
+        assert_eq!(reasoning(1), Some("so"));
+        assert!(choices[1].delta.as_ref().unwrap().extra.is_empty());
+        let reasons: Vec<_> = choices
+            .iter()
+            .map(|choice| choice.finish_reason.as_deref())
+            .collect();
+        assert_eq!(
+            reasons,
+            [
+                None,
+                Some("length"),
+                Some("stop"),
+                Some("stop"),
+                Some("tool_calls")
+            ]
+        );
+
+        let body = json!({
+            "choices": [{
+                "index": 0,
+                "message": { "role": "assistant", "content": "Hi", "reasoning": "hmm" },
+                "finish_reason": "stop",
+            }],
+        });
+        let normalized = normalize_response(Bytes::from(body.to_string()));
+        let response = ChatCompletionResponse::parse(&normalized).unwrap();
+        let message = response.choices()[0].message.as_ref().unwrap();
+        assert_eq!(message.extra["reasoning_content"], "hmm");
+        assert!(!message.extra.contains_key("reasoning"));
+    }
+
+    #[test]
+    fn converts_stream_error_formats() {
+        assert_eq!(
+            stream_error(None, r#"{"error":"rate limited"}"#),
+            Some(json!({ "message": "rate limited", "type": "upstream_error", "code": null }))
+        );
+        assert_eq!(
+            stream_error(
+                None,
+                r#"{"error":{"message":"overloaded","type":"server_error","code":529}}"#
+            ),
+            Some(json!({ "message": "overloaded", "type": "server_error", "code": 529 }))
+        );
+        assert_eq!(
+            stream_error(
+                None,
+                r#"{"object":"error","message":"bad input","type":"BadRequestError","code":400}"#
+            ),
+            Some(json!({ "message": "bad input", "type": "BadRequestError", "code": 400 }))
+        );
+        assert_eq!(
+            stream_error(
+                Some("error"),
+                r#"{"type":"error","error":{"type":"overloaded_error","message":"busy"}}"#
+            ),
+            Some(json!({ "message": "busy", "type": "overloaded_error", "code": null }))
+        );
+        assert_eq!(
+            stream_error(Some("error"), "upstream exploded"),
+            Some(json!({ "message": "upstream exploded", "type": "upstream_error", "code": null }))
+        );
+    }
+
+    #[test]
+    fn ignores_regular_events() {
+        assert_eq!(stream_error(None, "[DONE]"), None);
+        assert_eq!(
+            stream_error(
+                None,
+                r#"{"choices":[{"index":0,"delta":{"content":"error"}}]}"#
+            ),
+            None
+        );
+        // 带 `choices` 的数据块即使有 `error` 字段也不视为错误事件
+        assert_eq!(stream_error(None, r#"{"choices":[],"error":null}"#), None);
+    }
+}
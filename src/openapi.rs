@@ -0,0 +1,84 @@
+use axum::{Json, response::Html};
+use utoipa::{
+    Modify, OpenApi,
+    openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme},
+};
+
+use crate::{abuse, handlers, health, keys, metrics, models, pricing, quota, resume};
+
+/// Swagger UI 页面，静态资源取自 CDN
+const SWAGGER_UI_HTML: &str = r##"<!doctype html>
+<html lang="zh-CN">
+<head>
+  <meta charset="utf-8">
+  <title>free-model API</title>
+  <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@5/swagger-ui.css">
+</head>
+<body>
+  <div id="swagger-ui"></div>
+  <script src="https://unpkg.com/swagger-ui-dist@5/swagger-ui-bundle.js"></script>
+  <script>SwaggerUIBundle({ url: "openapi.json", dom_id: "#swagger-ui" });</script>
+</body>
+</html>
+"##;
+
+/// 代理接口的 OpenAPI 3.1 描述，由各接口的 `#[utoipa::path]` 与请求、响应类型的 `ToSchema` 生成
+///
+/// 对话接口原样转发 DeepSeek 的请求与响应，类型中只声明代理自身读取或添加的字段，其余字段允许透传。
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "free-model", description = "DeepSeek API 代理"),
+    paths(
+        handlers::chat_completions::handle_chat_completions,
+        handlers::chat_completions::handle_cancel,
+        resume::handle_resume,
+        handlers::translate::handle_translate,
+        models::handle_models,
+        pricing::handle_cost_estimate,
+        health::handle_provider_status,
+        metrics::handle_metrics,
+        handlers::admin::get_log_filter,
+        handlers::admin::put_log_filter,
+        pricing::get_prices,
+        pricing::put_price,
+        pricing::delete_price,
+        abuse::get_risks,
+        quota::get_quotas,
+        quota::put_quota,
+        quota::delete_quota,
+        quota::reset_quota,
+        keys::list_keys,
+        keys::create_key,
+        keys::revoke_key,
+    ),
+    modifiers(&AdminKey),
+)]
+struct ApiDoc;
+
+/// 管理接口的鉴权方式
+struct AdminKey;
+
+impl Modify for AdminKey {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "admin_key",
+            SecurityScheme::Http(
+                HttpBuilder::new()
+                    .scheme(HttpAuthScheme::Bearer)
+                    .description(Some("ADMIN_API_KEY"))
+                    .build(),
+            ),
+        );
+    }
+}
+
+/// 输出 OpenAPI 描述
+pub async fn handle_openapi() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
+}
+
+/// Swagger UI
+pub async fn handle_docs() -> Html<&'static str> {
+    Html(SWAGGER_UI_HTML)
+}
@@ -0,0 +1,151 @@
+use std::collections::HashMap;
+
+use axum::http::{HeaderMap, StatusCode, header::AUTHORIZATION};
+
+/// 指定上游服务商
+const PROVIDER_HEADER: &str = "x-provider";
+
+/// 指定上游 OpenAI 兼容接口的基础地址
+const BASE_URL_HEADER: &str = "x-upstream-base-url";
+
+/// 按 ID 指定 `UPSTREAM_KEYS` 中的上游密钥
+const KEY_ID_HEADER: &str = "x-upstream-key-id";
+
+/// 目前唯一的上游服务商
+const PROVIDER: &str = "deepseek";
+
+/// 单个请求生效的上游覆盖
+pub struct Override {
+    /// 替换默认地址的对话接口地址
+    pub url: Option<String>,
+    /// 发往上游的密钥
+    pub api_key: String,
+}
+
+/// 按请求覆盖上游
+///
+/// 读取 `PRIVILEGED_API_KEYS`（逗号分隔的客户端密钥）与 `UPSTREAM_KEYS`（`id=key`，逗号分隔）。
+/// 持特权密钥的客户端视为代理自身的调用方，其密钥不会转发给上游，改用服务端密钥或
+/// `X-Upstream-Key-Id` 指定的密钥，并可通过 `X-Upstream-Base-Url` 指定上游地址（此时必须同时指定密钥，
+/// 服务端密钥不会发往其他地址）；其余客户端携带这些请求头时返回 403。
+#[derive(Default)]
+pub struct UpstreamOverrides {
+    privileged_keys: Vec<String>,
+    keys: HashMap<String, String>,
+}
+
+impl UpstreamOverrides {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let privileged_keys = std::env::var("PRIVILEGED_API_KEYS")
+            .map(|config| {
+                config
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|s| !s.is_empty())
+                    .map(str::to_string)
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let mut keys = HashMap::new();
+        if let Ok(config) = std::env::var("UPSTREAM_KEYS") {
+            for entry in config.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+                let (id, key) = entry
+                    .split_once('=')
+                    .ok_or_else(|| anyhow::anyhow!("UPSTREAM_KEYS 格式错误: {}", entry))?;
+                keys.insert(id.trim().to_string(), key.trim().to_string());
+            }
+        }
+
+        Ok(Self {
+            privileged_keys,
+            keys,
+        })
+    }
+
+    /// 解析请求的上游覆盖，非特权客户端返回 `None`
+    pub fn resolve(
+        &self,
+        headers: &HeaderMap,
+        default_key: &str,
+    ) -> Result<Option<Override>, (StatusCode, String)> {
+        let header = |name| headers.get(name).and_then(|value| value.to_str().ok());
+        let privileged = header(AUTHORIZATION.as_str())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .is_some_and(|key| self.privileged_keys.iter().any(|k| k == key));
+
+        let requested = [PROVIDER_HEADER, BASE_URL_HEADER, KEY_ID_HEADER]
+            .iter()
+            .any(|name| headers.contains_key(*name));
+        if !privileged {
+            return if requested {
+                Err((StatusCode::FORBIDDEN, "无权覆盖上游配置".to_string()))
+            } else {
+                Ok(None)
+            };
+        }
+
+        if let Some(provider) = header(PROVIDER_HEADER)
+            && provider != PROVIDER
+        {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!("不支持的上游服务商: {}", provider),
+            ));
+        }
+
+        self.target(header(BASE_URL_HEADER), header(KEY_ID_HEADER), default_key)
+            .map(Some)
+    }
+
+    /// 按基础地址与 `UPSTREAM_KEYS` 中的密钥 ID 确定上游，供覆盖请求头与路由脚本共用
+    pub fn target(
+        &self,
+        base_url: Option<&str>,
+        key_id: Option<&str>,
+        default_key: &str,
+    ) -> Result<Override, (StatusCode, String)> {
+        let url = match base_url {
+            Some(base_url) => {
+                let parsed = url::Url::parse(base_url)
+                    .map_err(|e| (StatusCode::BAD_REQUEST, format!("上游地址无效: {}", e)))?;
+                if !matches!(parsed.scheme(), "http" | "https") {
+                    return Err((
+                        StatusCode::BAD_REQUEST,
+                        "上游地址仅支持 http 与 https".to_string(),
+                    ));
+                }
+                Some(format!(
+                    "{}/chat/completions",
+                    base_url.trim_end_matches('/')
+                ))
+            }
+            None => None,
+        };
+
+        // 服务端密钥只发往默认上游，覆盖地址时必须显式指定密钥
+        let api_key = match key_id {
+            Some(id) => self
+                .keys
+                .get(id)
+                .ok_or_else(|| (StatusCode::BAD_REQUEST, format!("未知的上游密钥: {}", id)))?
+                .clone(),
+            None if url.is_some() => {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    format!("覆盖上游地址时需通过 {} 指定密钥", KEY_ID_HEADER),
+                ));
+            }
+            None => default_key.to_string(),
+        };
+
+        Ok(Override { url, api_key })
+    }
+
+    /// 从转发给上游的请求头中移除覆盖用的控制头
+    pub fn strip_headers(headers: &mut HeaderMap) {
+        for name in [PROVIDER_HEADER, BASE_URL_HEADER, KEY_ID_HEADER] {
+            headers.remove(name);
+        }
+    }
+}
@@ -0,0 +1,485 @@
+use anyhow::Context;
+use axum::{
+    body::Bytes,
+    http::{HeaderMap, HeaderName, HeaderValue, StatusCode},
+};
+use futures::{FutureExt, future::BoxFuture};
+
+use crate::{
+    AppState,
+    abuse::AbuseAction,
+    context::{CompressionStrategy, compress_request},
+    experiments::{Assignment, bucket_key},
+    handlers::chat_completions::{UPSTREAM_CHAT_COMPLETIONS_URL, client_id},
+    redaction::{RedactionMode, TokenMap, redact_body},
+};
+
+/// 默认的阶段顺序
+const DEFAULT_STAGES: &[&str] = &[
+    "abuse",
+    "plugins",
+    "aliases",
+    "scripts",
+    "redaction",
+    "compression",
+    "experiments",
+    "tool_emulation",
+];
+
+/// 根据环境变量的值判断功能是否启用
+type FeatureCheck = fn(&str) -> bool;
+
+/// 阶段对应功能的环境变量与判断其是否启用的方法，功能启用而阶段未列入 `REQUEST_STAGES` 时拒绝启动
+const STAGE_FEATURES: &[(&str, &str, FeatureCheck)] = &[
+    ("abuse", "ABUSE_DETECTION", |value| {
+        value
+            .parse::<AbuseAction>()
+            .is_ok_and(|action| action != AbuseAction::Off)
+    }),
+    ("plugins", "WASM_PLUGINS", is_configured),
+    ("aliases", "MODEL_ALIASES", is_configured),
+    ("aliases", "MODEL_ALLOWLIST", is_configured),
+    ("scripts", "ROUTE_SCRIPTS", is_configured),
+    ("redaction", "PII_REDACTION", |value| {
+        value
+            .parse::<RedactionMode>()
+            .is_ok_and(|mode| mode != RedactionMode::Off)
+    }),
+    ("compression", "CONTEXT_COMPRESSION", |value| {
+        value
+            .parse::<CompressionStrategy>()
+            .is_ok_and(|strategy| strategy != CompressionStrategy::None)
+    }),
+    ("experiments", "EXPERIMENTS", is_configured),
+    ("tool_emulation", "TOOL_EMULATION_MODELS", is_configured),
+];
+
+fn is_configured(value: &str) -> bool {
+    !value.trim().is_empty()
+}
+
+/// 请求处理阶段之间共享的请求信息与处理结果
+pub struct StageContext<'a> {
+    pub state: &'a AppState,
+    /// 路由脚本使用的路由，`chat` 或 `translate`
+    pub route: &'static str,
+    /// 客户端请求头
+    pub headers: &'a HeaderMap,
+    /// 客户端凭据，即客户端请求的 Authorization 头
+    pub scope: &'a str,
+    /// 上游对话接口地址，覆盖上游或路由脚本选择上游时为选择后的地址
+    pub upstream_url: String,
+    /// 发往上游的 Authorization 头
+    pub authorization: HeaderValue,
+    /// 路由脚本改写的上游请求头，值为 `None` 时移除
+    pub header_rewrites: Vec<(HeaderName, Option<HeaderValue>)>,
+    pub strategy: CompressionStrategy,
+    /// 滥用检测命中的信号
+    pub abuse_signals: Vec<&'static str>,
+    /// 可逆脱敏的占位符映射
+    pub token_map: TokenMap,
+    /// 命中的实验分组
+    pub assignment: Option<Assignment>,
+    /// 是否模拟工具调用
+    pub emulate: bool,
+}
+
+/// 对话请求体的处理阶段
+///
+/// 阶段按顺序接收上一阶段输出的请求体并返回处理后的请求体，处理结果写入 [`StageContext`]。
+/// 新阶段实现此 trait 后通过 [`PipelineBuilder::stage`] 加入流水线。
+pub trait RequestStage: Send + Sync {
+    /// 阶段名称，即 `REQUEST_STAGES` 中的配置项
+    fn name(&self) -> &'static str;
+
+    /// 当前请求是否需要执行本阶段，所有阶段都不需要执行时请求体不缓冲、直接流式转发
+    fn is_enabled(&self, ctx: &StageContext) -> bool;
+
+    /// 处理请求体，返回错误时请求以该错误结束
+    fn apply<'a>(
+        &'a self,
+        ctx: &'a mut StageContext<'_>,
+        body: Bytes,
+    ) -> BoxFuture<'a, Result<Bytes, (StatusCode, String)>>;
+}
+
+/// 对话请求体的处理流水线
+///
+/// `REQUEST_STAGES` 为逗号分隔的阶段名称，按配置顺序执行；未配置时按默认顺序执行全部阶段：
+/// `abuse,plugins,aliases,scripts,redaction,compression,experiments,tool_emulation`。
+/// 已启用功能的阶段未列出时拒绝启动，避免如配置了 `PII_REDACTION` 却未脱敏。
+/// 调整顺序会改变语义，如 `redaction` 放在 `compression` 之后时生成摘要的请求会包含未脱敏的内容。
+pub struct RequestPipeline {
+    stages: Vec<Box<dyn RequestStage>>,
+}
+
+impl RequestPipeline {
+    pub fn builder() -> PipelineBuilder {
+        PipelineBuilder { stages: Vec::new() }
+    }
+
+    pub fn from_env() -> anyhow::Result<Self> {
+        let config = std::env::var("REQUEST_STAGES").unwrap_or_else(|_| DEFAULT_STAGES.join(","));
+        Self::parse(&config, |var| std::env::var(var).ok())
+    }
+
+    /// 按配置的阶段名称构建流水线，`env` 读取各功能的环境变量
+    fn parse(config: &str, env: impl Fn(&str) -> Option<String>) -> anyhow::Result<Self> {
+        let mut builder = Self::builder();
+        for name in config.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            if builder.stages.iter().any(|stage| stage.name() == name) {
+                anyhow::bail!("阶段 {} 重复配置", name);
+            }
+            let stage = builtin_stage(name).with_context(|| {
+                format!("未知的阶段 {}，可选 {}", name, DEFAULT_STAGES.join(","))
+            })?;
+            builder = builder.stage(stage);
+        }
+        for (name, var, enabled) in STAGE_FEATURES {
+            let is_enabled = env(var).is_some_and(|value| enabled(&value));
+            if is_enabled && !builder.stages.iter().any(|stage| stage.name() == *name) {
+                anyhow::bail!("{} 已启用但 REQUEST_STAGES 中缺少 {}", var, name);
+            }
+        }
+        Ok(builder.build())
+    }
+
+    /// 阶段名称，按执行顺序
+    pub fn names(&self) -> Vec<&'static str> {
+        self.stages.iter().map(|stage| stage.name()).collect()
+    }
+
+    /// 是否有阶段需要处理当前请求
+    pub fn needs_body(&self, ctx: &StageContext) -> bool {
+        self.stages.iter().any(|stage| stage.is_enabled(ctx))
+    }
+
+    /// 按顺序执行需要处理当前请求的阶段
+    pub async fn run(
+        &self,
+        ctx: &mut StageContext<'_>,
+        mut body: Bytes,
+    ) -> Result<Bytes, (StatusCode, String)> {
+        for stage in &self.stages {
+            if stage.is_enabled(ctx) {
+                body = stage.apply(ctx, body).await?;
+            }
+        }
+        Ok(body)
+    }
+}
+
+/// 流水线构建器，阶段按加入顺序执行
+pub struct PipelineBuilder {
+    stages: Vec<Box<dyn RequestStage>>,
+}
+
+impl PipelineBuilder {
+    pub fn stage(mut self, stage: Box<dyn RequestStage>) -> Self {
+        self.stages.push(stage);
+        self
+    }
+
+    pub fn build(self) -> RequestPipeline {
+        RequestPipeline {
+            stages: self.stages,
+        }
+    }
+}
+
+fn builtin_stage(name: &str) -> Option<Box<dyn RequestStage>> {
+    let stage: Box<dyn RequestStage> = match name {
+        "abuse" => Box::new(AbuseStage),
+        "plugins" => Box::new(PluginStage),
+        "aliases" => Box::new(AliasStage),
+        "scripts" => Box::new(ScriptStage),
+        "redaction" => Box::new(RedactionStage),
+        "compression" => Box::new(CompressionStage),
+        "experiments" => Box::new(ExperimentStage),
+        "tool_emulation" => Box::new(ToolEmulationStage),
+        _ => return None,
+    };
+    Some(stage)
+}
+
+/// 滥用检测，针对客户端的原始请求
+struct AbuseStage;
+
+impl RequestStage for AbuseStage {
+    fn name(&self) -> &'static str {
+        "abuse"
+    }
+
+    fn is_enabled(&self, ctx: &StageContext) -> bool {
+        ctx.state.abuse_detector.is_enabled()
+    }
+
+    fn apply<'a>(
+        &'a self,
+        ctx: &'a mut StageContext<'_>,
+        body: Bytes,
+    ) -> BoxFuture<'a, Result<Bytes, (StatusCode, String)>> {
+        async move {
+            ctx.abuse_signals = ctx
+                .state
+                .abuse_detector
+                .inspect(&client_id(ctx.scope), &body)?;
+            Ok(body)
+        }
+        .boxed()
+    }
+}
+
+/// WASM 插件，放在内置脱敏之前时自定义脱敏规则与内置脱敏互不影响
+struct PluginStage;
+
+impl RequestStage for PluginStage {
+    fn name(&self) -> &'static str {
+        "plugins"
+    }
+
+    fn is_enabled(&self, ctx: &StageContext) -> bool {
+        ctx.state.plugins.transforms_request()
+    }
+
+    fn apply<'a>(
+        &'a self,
+        ctx: &'a mut StageContext<'_>,
+        body: Bytes,
+    ) -> BoxFuture<'a, Result<Bytes, (StatusCode, String)>> {
+        ctx.state.plugins.transform_request(body).boxed()
+    }
+}
+
+/// 模型别名替换为实际模型并按白名单拒绝其他模型，之后的阶段按实际模型判断上下文窗口、实验与工具调用模拟
+struct AliasStage;
+
+impl RequestStage for AliasStage {
+    fn name(&self) -> &'static str {
+        "aliases"
+    }
+
+    fn is_enabled(&self, ctx: &StageContext) -> bool {
+        ctx.state.model_catalog.checks_requests()
+    }
+
+    fn apply<'a>(
+        &'a self,
+        ctx: &'a mut StageContext<'_>,
+        body: Bytes,
+    ) -> BoxFuture<'a, Result<Bytes, (StatusCode, String)>> {
+        async move { ctx.state.model_catalog.resolve_request(body) }.boxed()
+    }
+}
+
+/// 路由脚本，按实际模型拒绝请求、改写请求头或选择上游，放在上下文压缩之前使摘要请求发往选择后的上游
+struct ScriptStage;
+
+impl RequestStage for ScriptStage {
+    fn name(&self) -> &'static str {
+        "scripts"
+    }
+
+    fn is_enabled(&self, ctx: &StageContext) -> bool {
+        ctx.state.route_scripts.has(ctx.route)
+    }
+
+    fn apply<'a>(
+        &'a self,
+        ctx: &'a mut StageContext<'_>,
+        body: Bytes,
+    ) -> BoxFuture<'a, Result<Bytes, (StatusCode, String)>> {
+        async move {
+            let request: serde_json::Value = serde_json::from_slice(&body).map_err(|e| {
+                (
+                    StatusCode::BAD_REQUEST,
+                    format!("请求体不是有效的 JSON: {}", e),
+                )
+            })?;
+            let state = ctx.state;
+            let Some(decision) = state.route_scripts.evaluate(
+                ctx.route,
+                ctx.headers,
+                &request,
+                &state.upstream_overrides,
+                &state.api_key,
+            )?
+            else {
+                return Ok(body);
+            };
+            if let Some(upstream) = decision.upstream {
+                ctx.upstream_url = upstream
+                    .url
+                    .unwrap_or_else(|| UPSTREAM_CHAT_COMPLETIONS_URL.to_string());
+                ctx.authorization = HeaderValue::from_str(&format!("Bearer {}", upstream.api_key))
+                    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+            }
+            ctx.header_rewrites = decision.headers;
+            Ok(body)
+        }
+        .boxed()
+    }
+}
+
+/// 脱敏，放在上下文压缩之前确保生成摘要时发往上游的内容同样不含敏感信息
+struct RedactionStage;
+
+impl RequestStage for RedactionStage {
+    fn name(&self) -> &'static str {
+        "redaction"
+    }
+
+    fn is_enabled(&self, ctx: &StageContext) -> bool {
+        ctx.state.redaction_mode != RedactionMode::Off
+    }
+
+    fn apply<'a>(
+        &'a self,
+        ctx: &'a mut StageContext<'_>,
+        body: Bytes,
+    ) -> BoxFuture<'a, Result<Bytes, (StatusCode, String)>> {
+        async move {
+            let (body, token_map) = redact_body(body)?;
+            ctx.token_map = token_map;
+            Ok(body)
+        }
+        .boxed()
+    }
+}
+
+/// 上下文压缩
+struct CompressionStage;
+
+impl RequestStage for CompressionStage {
+    fn name(&self) -> &'static str {
+        "compression"
+    }
+
+    fn is_enabled(&self, ctx: &StageContext) -> bool {
+        ctx.strategy != CompressionStrategy::None
+    }
+
+    fn apply<'a>(
+        &'a self,
+        ctx: &'a mut StageContext<'_>,
+        body: Bytes,
+    ) -> BoxFuture<'a, Result<Bytes, (StatusCode, String)>> {
+        compress_request(
+            ctx.state,
+            &ctx.upstream_url,
+            &ctx.authorization,
+            ctx.strategy,
+            body,
+        )
+        .boxed()
+    }
+}
+
+/// 实验分流，可能改写模型
+struct ExperimentStage;
+
+impl RequestStage for ExperimentStage {
+    fn name(&self) -> &'static str {
+        "experiments"
+    }
+
+    fn is_enabled(&self, ctx: &StageContext) -> bool {
+        !ctx.state.experiments.is_empty()
+    }
+
+    fn apply<'a>(
+        &'a self,
+        ctx: &'a mut StageContext<'_>,
+        body: Bytes,
+    ) -> BoxFuture<'a, Result<Bytes, (StatusCode, String)>> {
+        async move {
+            let client = bucket_key(ctx.headers, ctx.scope);
+            let (body, assignment) = ctx.state.experiments.assign(&client, body)?;
+            ctx.assignment = assignment;
+            Ok(body)
+        }
+        .boxed()
+    }
+}
+
+/// 工具调用模拟，放在实验分流之后按最终模型判断
+struct ToolEmulationStage;
+
+impl RequestStage for ToolEmulationStage {
+    fn name(&self) -> &'static str {
+        "tool_emulation"
+    }
+
+    fn is_enabled(&self, ctx: &StageContext) -> bool {
+        !ctx.state.tool_emulation.is_empty()
+    }
+
+    fn apply<'a>(
+        &'a self,
+        ctx: &'a mut StageContext<'_>,
+        body: Bytes,
+    ) -> BoxFuture<'a, Result<Bytes, (StatusCode, String)>> {
+        async move {
+            let (body, emulate) = ctx.state.tool_emulation.rewrite_request(body)?;
+            ctx.emulate = emulate;
+            Ok(body)
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(config: &str, env: &[(&str, &str)]) -> anyhow::Result<RequestPipeline> {
+        RequestPipeline::parse(config, |var| {
+            env.iter()
+                .find(|(name, _)| *name == var)
+                .map(|(_, value)| value.to_string())
+        })
+    }
+
+    #[test]
+    fn default_order() {
+        let pipeline = parse(&DEFAULT_STAGES.join(","), &[]).unwrap();
+        assert_eq!(pipeline.names(), DEFAULT_STAGES);
+    }
+
+    #[test]
+    fn keeps_configured_order() {
+        let pipeline = parse(" redaction , abuse,,aliases", &[]).unwrap();
+        assert_eq!(pipeline.names(), ["redaction", "abuse", "aliases"]);
+    }
+
+    #[test]
+    fn rejects_unknown_and_duplicate_stages() {
+        assert!(parse("abuse,auth", &[]).is_err());
+        assert!(parse("abuse,redaction,abuse", &[]).is_err());
+    }
+
+    #[test]
+    fn rejects_missing_stage_of_enabled_feature() {
+        let env = [("PII_REDACTION", "mask")];
+        let error = parse("abuse,aliases", &env).err().unwrap();
+        assert!(error.to_string().contains("PII_REDACTION"));
+        assert!(parse("abuse,aliases,redaction", &env).is_ok());
+        // 关闭的功能不要求对应阶段
+        assert!(parse("abuse", &[("PII_REDACTION", "off")]).is_ok());
+    }
+
+    #[test]
+    fn translate_scripts_require_scripts_stage() {
+        assert!(parse("abuse", &[("ROUTE_SCRIPTS", "translate=t.rhai")]).is_err());
+        assert!(parse("scripts", &[("ROUTE_SCRIPTS", "translate=t.rhai")]).is_ok());
+    }
+
+    #[test]
+    fn builder_keeps_insertion_order() {
+        let pipeline = RequestPipeline::builder()
+            .stage(Box::new(ToolEmulationStage))
+            .stage(Box::new(AbuseStage))
+            .build();
+        assert_eq!(pipeline.names(), ["tool_emulation", "abuse"]);
+    }
+}
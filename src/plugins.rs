@@ -0,0 +1,151 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use axum::{body::Bytes, http::StatusCode};
+use wasmtime::{Config, Engine, Instance, Module, Store, StoreLimits, StoreLimitsBuilder};
+
+/// 单次调用可执行的指令数上限（fuel），防止插件死循环
+const FUEL_PER_CALL: u64 = 500_000_000;
+
+/// 单个插件实例可使用的最大线性内存
+const MAX_MEMORY_BYTES: usize = 64 * 1024 * 1024;
+
+/// 改写请求体的导出函数
+const TRANSFORM_REQUEST: &str = "transform_request";
+
+/// 改写非流式响应体的导出函数
+const TRANSFORM_RESPONSE: &str = "transform_response";
+
+struct Plugin {
+    name: String,
+    module: Module,
+    transforms_request: bool,
+    transforms_response: bool,
+}
+
+/// WASM 插件
+///
+/// 读取 `WASM_PLUGINS`（逗号分隔的 `.wasm`/`.wat` 文件路径），按顺序对对话请求体与非流式响应体做改写，
+/// 可用于自定义脱敏或请求改写。插件不导入任何宿主函数，无法访问文件与网络，每次调用使用新实例并限制指令数与内存。
+///
+/// 插件需导出 `memory` 与 `alloc(len: i32) -> i32`，以及 `transform_request` / `transform_response`
+/// 中的至少一个，签名为 `(ptr: i32, len: i32) -> i64`，返回值高 32 位为输出地址、低 32 位为输出长度。
+pub struct Plugins {
+    engine: Engine,
+    plugins: Vec<Plugin>,
+}
+
+impl Plugins {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let engine = Engine::new(Config::new().consume_fuel(true))?;
+        let mut plugins = Vec::new();
+        if let Ok(paths) = std::env::var("WASM_PLUGINS") {
+            for path in paths.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+                let module = Module::from_file(&engine, path)
+                    .with_context(|| format!("加载插件 {} 失败", path))?;
+                let exports = |name| module.exports().any(|export| export.name() == name);
+                let transforms_request = exports(TRANSFORM_REQUEST);
+                let transforms_response = exports(TRANSFORM_RESPONSE);
+                if !transforms_request && !transforms_response {
+                    anyhow::bail!(
+                        "插件 {} 未导出 {} 或 {}",
+                        path,
+                        TRANSFORM_REQUEST,
+                        TRANSFORM_RESPONSE
+                    );
+                }
+                tracing::info!("已加载 WASM 插件 {}", path);
+                plugins.push(Plugin {
+                    name: path.to_string(),
+                    module,
+                    transforms_request,
+                    transforms_response,
+                });
+            }
+        }
+        Ok(Self { engine, plugins })
+    }
+
+    pub fn transforms_request(&self) -> bool {
+        self.plugins.iter().any(|plugin| plugin.transforms_request)
+    }
+
+    pub fn transforms_response(&self) -> bool {
+        self.plugins.iter().any(|plugin| plugin.transforms_response)
+    }
+
+    /// 依次经过所有插件改写请求体
+    pub async fn transform_request(
+        self: &Arc<Self>,
+        body: Bytes,
+    ) -> Result<Bytes, (StatusCode, String)> {
+        self.run(TRANSFORM_REQUEST, body).await
+    }
+
+    /// 依次经过所有插件改写非流式响应体
+    pub async fn transform_response(
+        self: &Arc<Self>,
+        body: Bytes,
+    ) -> Result<Bytes, (StatusCode, String)> {
+        self.run(TRANSFORM_RESPONSE, body).await
+    }
+
+    async fn run(
+        self: &Arc<Self>,
+        export: &'static str,
+        body: Bytes,
+    ) -> Result<Bytes, (StatusCode, String)> {
+        let plugins = self.clone();
+        // 插件同步执行，放到阻塞线程池避免占用异步运行时
+        tokio::task::spawn_blocking(move || {
+            let mut body = body;
+            for plugin in &plugins.plugins {
+                let enabled = match export {
+                    TRANSFORM_REQUEST => plugin.transforms_request,
+                    _ => plugin.transforms_response,
+                };
+                if enabled {
+                    body = plugins
+                        .call(plugin, export, &body)
+                        .map(Bytes::from)
+                        .map_err(|e| {
+                            tracing::error!("插件 {} 执行 {} 失败: {:#}", plugin.name, export, e);
+                            (
+                                StatusCode::INTERNAL_SERVER_ERROR,
+                                format!("插件 {} 执行失败", plugin.name),
+                            )
+                        })?;
+                }
+            }
+            Ok(body)
+        })
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+    }
+
+    fn call(&self, plugin: &Plugin, export: &str, input: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let limits = StoreLimitsBuilder::new()
+            .memory_size(MAX_MEMORY_BYTES)
+            .build();
+        let mut store = Store::new(&self.engine, limits);
+        store.limiter(|limits: &mut StoreLimits| limits);
+        store.set_fuel(FUEL_PER_CALL)?;
+
+        let instance = Instance::new(&mut store, &plugin.module, &[])?;
+        let memory = instance
+            .get_memory(&mut store, "memory")
+            .context("插件未导出 memory")?;
+        let alloc = instance.get_typed_func::<i32, i32>(&mut store, "alloc")?;
+        let transform = instance.get_typed_func::<(i32, i32), i64>(&mut store, export)?;
+
+        let len = i32::try_from(input.len()).context("输入过大")?;
+        let ptr = alloc.call(&mut store, len)?;
+        memory.write(&mut store, ptr as u32 as usize, input)?;
+        let packed = transform.call(&mut store, (ptr, len))? as u64;
+
+        let (ptr, len) = ((packed >> 32) as usize, (packed & 0xffff_ffff) as usize);
+        let mut output = vec![0; len];
+        memory.read(&store, ptr, &mut output)?;
+        Ok(output)
+    }
+}
@@ -0,0 +1,311 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, RwLock},
+};
+
+use axum::{
+    Json,
+    body::Bytes,
+    extract::{Path, State},
+    http::StatusCode,
+};
+use futures::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use utoipa::ToSchema;
+
+use crate::{
+    AppState,
+    chat::{ChatCompletionRequest, ChatCompletionResponse, Usage},
+    context::estimate_message_tokens,
+    sse::SseParser,
+};
+
+/// 按模型累计的对话费用（计价单位的百万分之一），标签 `model`
+const CHAT_COST_MICROS_TOTAL: &str = "chat_cost_micros_total";
+
+/// 计算实际费用时缓存的非流式响应体上限，超出后不再计费
+const MAX_USAGE_BODY_BYTES: usize = 4 * 1024 * 1024;
+
+/// 单个模型的价格，单位为每百万 token
+#[derive(Clone, Serialize, Deserialize, ToSchema)]
+pub struct ModelPrice {
+    pub input: f64,
+    pub output: f64,
+    /// 命中上下文缓存的输入价格，未配置时按 `input` 计算
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_hit_input: Option<f64>,
+}
+
+/// 价格表
+///
+/// 启动时读取 `PRICE_TABLE`（JSON 对象，模型名到价格），运行期间可通过管理接口修改。
+/// 对话响应结束后按 `usage` 计算实际费用，记录到日志与 `chat_cost_micros_total` 指标。
+#[derive(Default)]
+pub struct PriceTable(RwLock<HashMap<String, ModelPrice>>);
+
+/// 响应体的旁路读取状态，随响应流一同释放时取出带 `usage` 的响应交给观察者
+///
+/// 带 `Content-Length` 的响应写完即停止读取，不会读到流的结尾，因此在释放时而不是流结束时处理。
+struct UsageRecorder<F: FnOnce(&ChatCompletionResponse)> {
+    observe: Option<F>,
+    is_event_stream: bool,
+    parser: SseParser,
+    /// SSE 响应中最后一个带 `usage` 的数据块
+    usage: Option<ChatCompletionResponse>,
+    /// 非流式响应体
+    body: Vec<u8>,
+    truncated: bool,
+}
+
+impl<F: FnOnce(&ChatCompletionResponse)> UsageRecorder<F> {
+    fn push(&mut self, bytes: &Bytes) {
+        if self.is_event_stream {
+            for event in self.parser.push(bytes) {
+                if let Some(chunk) = event
+                    .data
+                    .and_then(|data| ChatCompletionResponse::parse(data.as_bytes()))
+                    .filter(|chunk| chunk.usage.is_some())
+                {
+                    self.usage = Some(chunk);
+                }
+            }
+        } else if self.body.len() + bytes.len() <= MAX_USAGE_BODY_BYTES {
+            self.body.extend_from_slice(bytes);
+        } else {
+            self.truncated = true;
+        }
+    }
+}
+
+impl<F: FnOnce(&ChatCompletionResponse)> Drop for UsageRecorder<F> {
+    fn drop(&mut self) {
+        let response = if self.is_event_stream {
+            self.usage.take()
+        } else if !self.truncated {
+            ChatCompletionResponse::parse(&self.body)
+        } else {
+            None
+        };
+        if let (Some(response), Some(observe)) = (response, self.observe.take()) {
+            observe(&response);
+        }
+    }
+}
+
+/// 旁路读取对话响应体，响应释放时把带 `usage` 的响应交给 `observe`，不影响转发
+///
+/// 流式响应取最后一个带 `usage` 的数据块；非流式响应体超过 4 MiB 时不处理。
+pub fn observe_usage<S, E, F>(
+    stream: S,
+    is_event_stream: bool,
+    observe: F,
+) -> impl Stream<Item = Result<Bytes, E>>
+where
+    S: Stream<Item = Result<Bytes, E>>,
+    F: FnOnce(&ChatCompletionResponse),
+{
+    let mut recorder = UsageRecorder {
+        observe: Some(observe),
+        is_event_stream,
+        parser: SseParser::default(),
+        usage: None,
+        body: Vec::new(),
+        truncated: false,
+    };
+    stream.inspect(move |chunk| {
+        if let Ok(bytes) = chunk {
+            recorder.push(bytes);
+        }
+    })
+}
+
+/// 费用估算结果
+#[derive(Serialize, ToSchema)]
+pub struct CostEstimate {
+    model: String,
+    input_tokens: usize,
+    input_cost: f64,
+    /// 请求未设置 `max_tokens` 时为 `None`
+    max_output_tokens: Option<u64>,
+    max_output_cost: Option<f64>,
+    max_total_cost: Option<f64>,
+}
+
+impl PriceTable {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let prices = match std::env::var("PRICE_TABLE") {
+            Ok(config) => serde_json::from_str(&config)?,
+            Err(_) => HashMap::new(),
+        };
+        Ok(Self(RwLock::new(prices)))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.read().unwrap().is_empty()
+    }
+
+    pub fn get(&self, model: &str) -> Option<ModelPrice> {
+        self.0.read().unwrap().get(model).cloned()
+    }
+
+    /// 按 `usage` 计算一次请求的实际费用
+    fn cost(&self, model: &str, usage: &Usage) -> Option<f64> {
+        let price = self.get(model)?;
+        let tokens = |count: Option<u64>| count.unwrap_or(0) as f64;
+        let prompt = tokens(usage.prompt_tokens);
+        let cache_hit = tokens(usage.prompt_cache_hit_tokens).min(prompt);
+        let cache_hit_price = price.cache_hit_input.unwrap_or(price.input);
+        let cost = (prompt - cache_hit) * price.input
+            + cache_hit * cache_hit_price
+            + tokens(usage.completion_tokens) * price.output;
+        Some(cost / 1_000_000.0)
+    }
+
+    /// 旁路读取响应体，结束后从 `usage` 计算实际费用，不影响转发
+    pub fn record_cost<S, E>(
+        self: Arc<Self>,
+        stream: S,
+        is_event_stream: bool,
+    ) -> impl Stream<Item = Result<Bytes, E>>
+    where
+        S: Stream<Item = Result<Bytes, E>>,
+    {
+        observe_usage(stream, is_event_stream, move |response| {
+            self.observe(response)
+        })
+    }
+
+    fn observe(&self, response: &ChatCompletionResponse) {
+        let model = response.model.as_deref().unwrap_or_default();
+        let Some(usage) = &response.usage else {
+            return;
+        };
+        let Some(cost) = self.cost(model, usage) else {
+            return;
+        };
+        tracing::info!(model, cost, usage = ?usage, "对话请求费用");
+        metrics::counter!(CHAT_COST_MICROS_TOTAL, "model" => model.to_string())
+            .increment((cost * 1_000_000.0).round() as u64);
+    }
+}
+
+/// 估算对话请求的费用：输入按消息内容估算 token 数，输出按 `max_tokens` 计算上限
+#[utoipa::path(
+    post,
+    path = "/cost/estimate",
+    tag = "chat",
+    request_body = ChatCompletionRequest,
+    responses(
+        (status = 200, description = "费用估算", body = CostEstimate),
+        (status = 400, description = "请求体无效", body = String),
+        (status = 404, description = "未配置模型价格", body = String),
+    ),
+)]
+pub async fn handle_cost_estimate(
+    State(state): State<AppState>,
+    Json(payload): Json<Value>,
+) -> Result<Json<CostEstimate>, (StatusCode, String)> {
+    let request: ChatCompletionRequest =
+        serde_json::from_value(payload).map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+    let model = request.model.as_str();
+    let price = state.price_table.get(model).ok_or((
+        StatusCode::NOT_FOUND,
+        format!("未配置模型 {} 的价格", model),
+    ))?;
+
+    let input_tokens: usize = request.messages.iter().map(estimate_message_tokens).sum();
+    let input_cost = input_tokens as f64 * price.input / 1_000_000.0;
+    let max_output_tokens = request.max_output_tokens();
+    let max_output_cost =
+        max_output_tokens.map(|tokens| tokens as f64 * price.output / 1_000_000.0);
+
+    Ok(Json(CostEstimate {
+        model: model.to_string(),
+        input_tokens,
+        input_cost,
+        max_output_tokens,
+        max_output_cost,
+        max_total_cost: max_output_cost.map(|cost| input_cost + cost),
+    }))
+}
+
+/// 查看价格表
+#[utoipa::path(
+    get,
+    path = "/admin/prices",
+    tag = "admin",
+    security(("admin_key" = [])),
+    responses(
+        (status = 200, description = "模型名到价格", body = BTreeMap<String, ModelPrice>),
+        (status = 401, description = "管理接口密钥无效", body = String),
+    ),
+)]
+pub async fn get_prices(State(state): State<AppState>) -> Json<BTreeMap<String, ModelPrice>> {
+    let prices = state.price_table.0.read().unwrap();
+    Json(
+        prices
+            .iter()
+            .map(|(model, price)| (model.clone(), price.clone()))
+            .collect(),
+    )
+}
+
+/// 设置单个模型的价格
+#[utoipa::path(
+    put,
+    path = "/admin/prices/{model}",
+    tag = "admin",
+    security(("admin_key" = [])),
+    params(("model" = String, Path, description = "模型名")),
+    request_body = ModelPrice,
+    responses(
+        (status = 200, description = "设置后的价格", body = ModelPrice),
+        (status = 401, description = "管理接口密钥无效", body = String),
+    ),
+)]
+pub async fn put_price(
+    State(state): State<AppState>,
+    Path(model): Path<String>,
+    Json(price): Json<ModelPrice>,
+) -> Json<ModelPrice> {
+    tracing::info!(
+        input = price.input,
+        output = price.output,
+        "模型 {} 的价格已更新",
+        model
+    );
+    state
+        .price_table
+        .0
+        .write()
+        .unwrap()
+        .insert(model, price.clone());
+    Json(price)
+}
+
+/// 删除单个模型的价格
+#[utoipa::path(
+    delete,
+    path = "/admin/prices/{model}",
+    tag = "admin",
+    security(("admin_key" = [])),
+    params(("model" = String, Path, description = "模型名")),
+    responses(
+        (status = 204, description = "已删除"),
+        (status = 401, description = "管理接口密钥无效", body = String),
+        (status = 404, description = "未配置模型价格", body = String),
+    ),
+)]
+pub async fn delete_price(
+    State(state): State<AppState>,
+    Path(model): Path<String>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    match state.price_table.0.write().unwrap().remove(&model) {
+        Some(_) => Ok(StatusCode::NO_CONTENT),
+        None => Err((
+            StatusCode::NOT_FOUND,
+            format!("未配置模型 {} 的价格", model),
+        )),
+    }
+}
@@ -0,0 +1,215 @@
+use std::{collections::HashMap, sync::Arc};
+
+use axum::{
+    Router,
+    body::{Body, to_bytes},
+    http::{HeaderName, Method, Request, header::CONTENT_TYPE},
+};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::Semaphore;
+use tower::ServiceExt;
+
+/// 结果响应体允许读取的最大字节数
+const MAX_RESULT_BYTES: usize = 32 * 1024 * 1024;
+
+/// 队列消息可以调用的路由，管理接口等其余路由不对队列开放
+const ALLOWED_PATHS: &[&str] = &["/chat/completions", "/translate"];
+
+/// 队列中的生成请求
+#[derive(Deserialize)]
+struct QueueRequest {
+    /// 调用方自定义的请求标识，原样写回结果
+    #[serde(default)]
+    id: Option<String>,
+    /// 处理该请求的路由，如 `/chat/completions`、`/translate`
+    path: String,
+    /// 附加请求头，如 `Authorization`
+    #[serde(default)]
+    headers: HashMap<String, String>,
+    /// 请求体
+    body: Value,
+}
+
+/// 发布到结果主题的处理结果
+#[derive(Serialize)]
+pub struct QueueResult {
+    id: Option<String>,
+    pub status: u16,
+    /// JSON 响应解析为对象，其余（如 SSE 文本、错误信息）保留为字符串
+    body: Value,
+}
+
+/// NATS 队列配置
+pub struct QueueConfig {
+    url: String,
+    request_subject: String,
+    result_subject: String,
+    queue_group: String,
+    max_in_flight: usize,
+}
+
+impl QueueConfig {
+    /// 读取 `NATS_URL`，未配置时不启用队列消费
+    pub fn from_env() -> anyhow::Result<Option<Self>> {
+        let Ok(url) = std::env::var("NATS_URL") else {
+            return Ok(None);
+        };
+        let var =
+            |name: &str, default: &str| std::env::var(name).unwrap_or_else(|_| default.to_string());
+        Ok(Some(Self {
+            url,
+            request_subject: var("NATS_REQUEST_SUBJECT", "free-model.requests"),
+            result_subject: var("NATS_RESULT_SUBJECT", "free-model.results"),
+            queue_group: var("NATS_QUEUE_GROUP", "free-model"),
+            max_in_flight: var("NATS_MAX_IN_FLIGHT", "16").parse()?,
+        }))
+    }
+}
+
+/// 启动队列消费者
+///
+/// 以队列组订阅请求主题（多个实例之间负载均衡），每条消息按 `path` 交给与 HTTP 相同的路由处理，
+/// 因此降载、调度、脱敏等逻辑同样生效；结果发布到消息的 reply 主题，没有时发布到结果主题。
+/// `path` 只能是对话与翻译接口。队列消息没有对端地址，配置了 IP 访问规则时会被网络访问控制拒绝；
+/// 消息中描述客户端来源的请求头（`client_headers`，如 `X-Forwarded-For`、国家代码头）在进入路由前移除。
+pub async fn spawn_consumer(
+    config: QueueConfig,
+    app: Router,
+    client_headers: Vec<HeaderName>,
+) -> anyhow::Result<()> {
+    let client_headers = Arc::new(client_headers);
+    let client = async_nats::connect(&config.url).await?;
+    let mut subscriber = client
+        .queue_subscribe(config.request_subject.clone(), config.queue_group.clone())
+        .await?;
+    let in_flight = Arc::new(Semaphore::new(config.max_in_flight));
+
+    tracing::info!("已订阅 NATS 主题 {}", config.request_subject);
+    tokio::spawn(async move {
+        while let Some(message) = subscriber.next().await {
+            let Ok(permit) = in_flight.clone().acquire_owned().await else {
+                break;
+            };
+            let client = client.clone();
+            let app = app.clone();
+            let client_headers = client_headers.clone();
+            let result_subject = config.result_subject.clone();
+            tokio::spawn(async move {
+                let result = process(app, &message.payload, &client_headers).await;
+                let subject = message
+                    .reply
+                    .map(|reply| reply.to_string())
+                    .unwrap_or(result_subject);
+                match serde_json::to_vec(&result) {
+                    Ok(payload) => {
+                        if let Err(e) = client.publish(subject, payload.into()).await {
+                            tracing::error!("发布队列处理结果失败: {}", e);
+                        }
+                    }
+                    Err(e) => tracing::error!("序列化队列处理结果失败: {}", e),
+                }
+                drop(permit);
+            });
+        }
+        tracing::warn!("NATS 订阅已结束");
+    });
+    Ok(())
+}
+
+/// 将一条队列消息交给路由处理，移除 `client_headers` 中的请求头
+pub async fn process(app: Router, payload: &[u8], client_headers: &[HeaderName]) -> QueueResult {
+    let request: QueueRequest = match serde_json::from_slice(payload) {
+        Ok(request) => request,
+        Err(e) => {
+            return QueueResult {
+                id: None,
+                status: 400,
+                body: Value::String(format!("无效的队列消息: {}", e)),
+            };
+        }
+    };
+
+    if !ALLOWED_PATHS.contains(&request.path.as_str()) {
+        return QueueResult {
+            id: request.id,
+            status: 403,
+            body: Value::String(format!("队列消息不能调用 {}", request.path)),
+        };
+    }
+
+    let mut builder = Request::builder()
+        .method(Method::POST)
+        .uri(&request.path)
+        .header(CONTENT_TYPE, "application/json");
+    for (name, value) in &request.headers {
+        if HeaderName::try_from(name.as_str()).is_ok_and(|name| client_headers.contains(&name)) {
+            continue;
+        }
+        builder = builder.header(name, value);
+    }
+    let http_request = match builder.body(Body::from(request.body.to_string())) {
+        Ok(http_request) => http_request,
+        Err(e) => {
+            return QueueResult {
+                id: request.id,
+                status: 400,
+                body: Value::String(e.to_string()),
+            };
+        }
+    };
+
+    let response = match app.oneshot(http_request).await {
+        Ok(response) => response,
+        Err(infallible) => match infallible {},
+    };
+    let status = response.status().as_u16();
+    let body = match to_bytes(response.into_body(), MAX_RESULT_BYTES).await {
+        Ok(bytes) => serde_json::from_slice(&bytes)
+            .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(&bytes).into_owned())),
+        Err(e) => Value::String(e.to_string()),
+    };
+
+    QueueResult {
+        id: request.id,
+        status,
+        body,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        http::{HeaderMap, header::HOST},
+        routing::post,
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    async fn strips_client_headers_and_rejects_other_routes() {
+        let app = Router::new().route(
+            "/chat/completions",
+            post(|headers: HeaderMap| async move {
+                let mut names: Vec<&str> = headers.keys().map(|name| name.as_str()).collect();
+                names.sort();
+                names.join(",")
+            }),
+        );
+        let client_headers = [HeaderName::from_static("x-forwarded-for"), HOST];
+        let payload = serde_json::json!({
+            "id": "1",
+            "path": "/chat/completions",
+            "headers": { "X-Forwarded-For": "10.0.0.1", "Host": "internal", "Authorization": "Bearer k" },
+            "body": {},
+        });
+        let result = process(app.clone(), payload.to_string().as_bytes(), &client_headers).await;
+        assert_eq!(result.status, 200);
+        assert_eq!(result.body, "authorization,content-type");
+
+        let payload = serde_json::json!({ "path": "/admin/keys", "body": {} });
+        let result = process(app, payload.to_string().as_bytes(), &client_headers).await;
+        assert_eq!(result.status, 403);
+    }
+}
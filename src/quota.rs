@@ -0,0 +1,615 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    Json,
+    body::Bytes,
+    extract::{Path, State},
+    http::{HeaderValue, StatusCode},
+};
+use futures::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use utoipa::ToSchema;
+
+use crate::{
+    AppState,
+    chat::{ChatCompletionRequest, ChatCompletionResponse},
+    handlers::chat_completions::client_id,
+    keys::ClientIdentity,
+    pricing::observe_usage,
+    sse::SseParser,
+};
+
+/// 接近配额时的警告响应头
+pub const QUOTA_WARNING_HEADER: &str = "x-quota-warning";
+
+/// 因配额用尽被拒绝的请求数，标签 `period`（`daily`/`monthly`）
+const QUOTA_EXCEEDED_TOTAL: &str = "quota_exceeded_total";
+
+/// 默认的软限制百分比
+const DEFAULT_SOFT_LIMIT_PERCENT: u64 = 80;
+
+/// 最多跟踪的客户端数，超出时移除不是当月的记录
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+/// 当前 UTC 日期，自 Unix 纪元起的天数
+fn today() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs() / 86_400)
+}
+
+/// 自 Unix 纪元起的天数所在的 UTC 月份，以 `年 * 12 + 月 - 1` 表示
+fn month_of(day: u64) -> u64 {
+    // 按公历 400 年周期换算，见 Howard Hinnant 的 civil_from_days
+    let z = day + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+    year * 12 + month - 1
+}
+
+/// 配额对应的客户端：有租户时按租户合计，否则按客户端密钥、JWT 主体或凭据摘要区分
+pub fn quota_client(identity: Option<&ClientIdentity>, scope: &str) -> String {
+    match identity {
+        Some(ClientIdentity {
+            tenant: Some(tenant),
+            ..
+        }) => format!("tenant:{}", tenant),
+        Some(identity) => identity.scope.clone(),
+        None => client_id(scope),
+    }
+}
+
+/// token 配额，为 0 时不限制
+#[derive(Clone, Copy, Default, Serialize, Deserialize, ToSchema)]
+pub struct QuotaLimits {
+    /// 每个 UTC 日的 token 数
+    #[serde(default)]
+    pub daily: u64,
+    /// 每个 UTC 月的 token 数
+    #[serde(default)]
+    pub monthly: u64,
+}
+
+/// 客户端的用量
+#[derive(Clone, Copy, Default)]
+struct QuotaUsage {
+    day: u64,
+    daily: u64,
+    month: u64,
+    monthly: u64,
+}
+
+impl QuotaUsage {
+    /// 跨日或跨月时清零对应的用量
+    fn roll(&mut self, day: u64) {
+        if self.day != day {
+            self.day = day;
+            self.daily = 0;
+        }
+        let month = month_of(day);
+        if self.month != month {
+            self.month = month;
+            self.monthly = 0;
+        }
+    }
+}
+
+/// 客户端的配额与当期用量
+#[derive(Serialize, ToSchema)]
+pub struct QuotaStatus {
+    pub client: String,
+    pub limits: QuotaLimits,
+    /// 是否为管理接口设置的配额
+    pub custom: bool,
+    pub daily_used: u64,
+    pub monthly_used: u64,
+}
+
+/// 按客户端的每日与每月 token 配额
+///
+/// `DAILY_TOKEN_QUOTA` 与 `MONTHLY_TOKEN_QUOTA` 为默认配额，管理接口可为单个客户端调整。
+/// 用量达到 `QUOTA_SOFT_LIMIT_PERCENT` 后响应带 `x-quota-warning` 头，用尽后对话与翻译请求返回 429。
+/// 用量在响应结束后按 `usage` 计入，因此进行中的请求可能使用量略超配额。
+/// 用量与调整后的配额只保存在内存中，重启后恢复为默认配额。
+pub struct TokenQuota {
+    defaults: QuotaLimits,
+    soft_limit_percent: u64,
+    /// 管理接口设置的配额
+    overrides: Mutex<HashMap<String, QuotaLimits>>,
+    usage: Mutex<HashMap<String, QuotaUsage>>,
+}
+
+impl TokenQuota {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let parse = |name: &str, default: u64| -> anyhow::Result<u64> {
+            match std::env::var(name) {
+                Ok(value) => Ok(value.trim().parse()?),
+                Err(_) => Ok(default),
+            }
+        };
+        let soft_limit_percent = parse("QUOTA_SOFT_LIMIT_PERCENT", DEFAULT_SOFT_LIMIT_PERCENT)?;
+        if !(1..=100).contains(&soft_limit_percent) {
+            anyhow::bail!("QUOTA_SOFT_LIMIT_PERCENT 必须在 1 到 100 之间");
+        }
+        Ok(Self::new(
+            QuotaLimits {
+                daily: parse("DAILY_TOKEN_QUOTA", 0)?,
+                monthly: parse("MONTHLY_TOKEN_QUOTA", 0)?,
+            },
+            soft_limit_percent,
+        ))
+    }
+
+    fn new(defaults: QuotaLimits, soft_limit_percent: u64) -> Self {
+        Self {
+            defaults,
+            soft_limit_percent,
+            overrides: Mutex::default(),
+            usage: Mutex::default(),
+        }
+    }
+
+    /// 配置了默认配额或为任一客户端设置了配额时启用
+    pub fn is_enabled(&self) -> bool {
+        self.defaults.daily > 0
+            || self.defaults.monthly > 0
+            || !self.overrides.lock().unwrap().is_empty()
+    }
+
+    fn limits(&self, client: &str) -> QuotaLimits {
+        self.overrides
+            .lock()
+            .unwrap()
+            .get(client)
+            .copied()
+            .unwrap_or(self.defaults)
+    }
+
+    /// 客户端配额用尽时返回 429，达到软限制时返回警告头的内容
+    pub fn check(&self, client: &str) -> Result<Option<HeaderValue>, (StatusCode, String)> {
+        self.check_on(client, today())
+    }
+
+    fn check_on(
+        &self,
+        client: &str,
+        day: u64,
+    ) -> Result<Option<HeaderValue>, (StatusCode, String)> {
+        let limits = self.limits(client);
+        let mut usage = self
+            .usage
+            .lock()
+            .unwrap()
+            .get(client)
+            .copied()
+            .unwrap_or_default();
+        usage.roll(day);
+
+        let periods = [
+            ("daily", "今日", usage.daily, limits.daily),
+            ("monthly", "本月", usage.monthly, limits.monthly),
+        ];
+        let mut warnings = Vec::new();
+        for (period, label, used, limit) in periods {
+            if limit == 0 {
+                continue;
+            }
+            if used >= limit {
+                metrics::counter!(QUOTA_EXCEEDED_TOTAL, "period" => period).increment(1);
+                return Err((
+                    StatusCode::TOO_MANY_REQUESTS,
+                    format!("{} token 配额 {} 已用完", label, limit),
+                ));
+            }
+            if used * 100 >= limit * self.soft_limit_percent {
+                warnings.push(format!("{} {}/{}", period, used, limit));
+            }
+        }
+        Ok((!warnings.is_empty())
+            .then(|| HeaderValue::from_str(&warnings.join(", ")).ok())
+            .flatten())
+    }
+
+    fn charge(&self, client: &str, tokens: u64) {
+        self.charge_on(client, tokens, today());
+    }
+
+    fn charge_on(&self, client: &str, tokens: u64, day: u64) {
+        let mut usage = self.usage.lock().unwrap();
+        if !usage.contains_key(client) && usage.len() >= MAX_TRACKED_CLIENTS {
+            let month = month_of(day);
+            usage.retain(|_, entry| entry.month == month);
+        }
+        let entry = usage.entry(client.to_string()).or_default();
+        entry.roll(day);
+        entry.daily += tokens;
+        entry.monthly += tokens;
+    }
+
+    /// 旁路读取响应体，结束后把 `usage` 中的 token 数计入客户端用量
+    pub fn record_usage<S, E>(
+        self: Arc<Self>,
+        client: String,
+        stream: S,
+        is_event_stream: bool,
+    ) -> impl Stream<Item = Result<Bytes, E>>
+    where
+        S: Stream<Item = Result<Bytes, E>>,
+    {
+        observe_usage(stream, is_event_stream, move |response| {
+            if let Some(usage) = &response.usage {
+                let tokens =
+                    usage.prompt_tokens.unwrap_or(0) + usage.completion_tokens.unwrap_or(0);
+                self.charge(&client, tokens);
+            }
+        })
+    }
+
+    fn status(&self, client: &str) -> QuotaStatus {
+        let custom = self.overrides.lock().unwrap().get(client).copied();
+        let mut usage = self
+            .usage
+            .lock()
+            .unwrap()
+            .get(client)
+            .copied()
+            .unwrap_or_default();
+        usage.roll(today());
+        QuotaStatus {
+            client: client.to_string(),
+            limits: custom.unwrap_or(self.defaults),
+            custom: custom.is_some(),
+            daily_used: usage.daily,
+            monthly_used: usage.monthly,
+        }
+    }
+
+    fn snapshot(&self) -> Vec<QuotaStatus> {
+        let mut clients: Vec<String> = self.usage.lock().unwrap().keys().cloned().collect();
+        clients.extend(self.overrides.lock().unwrap().keys().cloned());
+        clients.sort();
+        clients.dedup();
+        clients.iter().map(|client| self.status(client)).collect()
+    }
+}
+
+/// 流式请求强制开启 `stream_options.include_usage`，以便按最后的用量数据块计入配额
+///
+/// 返回改写后的请求体，以及客户端原本是否未要求用量数据块（此时转发响应前需要移除）。
+pub fn request_usage(body: Bytes) -> Result<(Bytes, bool), (StatusCode, String)> {
+    let Some(mut request) = ChatCompletionRequest::parse(&body) else {
+        return Ok((body, false));
+    };
+    if request.stream != Some(true) {
+        return Ok((body, false));
+    }
+    let requested = request
+        .extra
+        .get("stream_options")
+        .and_then(|options| options.get("include_usage"))
+        .and_then(Value::as_bool)
+        .unwrap_or(false);
+    if requested {
+        return Ok((body, false));
+    }
+    match request.extra.get_mut("stream_options") {
+        Some(Value::Object(options)) => {
+            options.insert("include_usage".to_string(), Value::Bool(true));
+        }
+        _ => {
+            request.extra.insert(
+                "stream_options".to_string(),
+                json!({ "include_usage": true }),
+            );
+        }
+    }
+    Ok((request.to_bytes()?, true))
+}
+
+/// 是否为 `include_usage` 追加的用量数据块（`choices` 为空且带 `usage`）
+fn is_usage_chunk(data: &str) -> bool {
+    let Ok(value) = serde_json::from_str::<Value>(data) else {
+        return false;
+    };
+    value
+        .get("choices")
+        .and_then(Value::as_array)
+        .is_some_and(Vec::is_empty)
+        && ChatCompletionResponse::parse(data.as_bytes()).is_some_and(|chunk| chunk.usage.is_some())
+}
+
+/// 移除 SSE 响应中代理强制开启的用量数据块，其余事件按原样转发
+pub fn strip_usage_chunks<S, E>(stream: S) -> impl Stream<Item = Result<Bytes, E>>
+where
+    S: Stream<Item = Result<Bytes, E>>,
+{
+    let mut parser = SseParser::default();
+    let mut finished = false;
+    stream
+        .map(Some)
+        .chain(futures::stream::once(futures::future::ready(None)))
+        .filter_map(move |chunk| {
+            let output = match chunk {
+                Some(Ok(bytes)) => {
+                    let mut output = Vec::with_capacity(bytes.len());
+                    for event in parser.push(&bytes) {
+                        if !event.data.as_deref().is_some_and(is_usage_chunk) {
+                            output.extend_from_slice(&event.raw);
+                        }
+                    }
+                    Some(Ok(Bytes::from(output)))
+                }
+                Some(Err(e)) => Some(Err(e)),
+                None if !finished => {
+                    finished = true;
+                    parser.finish().map(Ok)
+                }
+                None => None,
+            };
+            futures::future::ready(
+                output.filter(|chunk| !matches!(chunk, Ok(bytes) if bytes.is_empty())),
+            )
+        })
+}
+
+/// 查看各客户端的配额与当期用量
+#[utoipa::path(
+    get,
+    path = "/admin/quotas",
+    tag = "admin",
+    security(("admin_key" = [])),
+    responses(
+        (status = 200, description = "有用量或单独设置了配额的客户端", body = Vec<QuotaStatus>),
+        (status = 401, description = "管理接口密钥无效", body = String),
+    ),
+)]
+pub async fn get_quotas(State(state): State<AppState>) -> Json<Vec<QuotaStatus>> {
+    Json(state.token_quota.snapshot())
+}
+
+/// 设置单个客户端的配额
+#[utoipa::path(
+    put,
+    path = "/admin/quotas/{client}",
+    tag = "admin",
+    security(("admin_key" = [])),
+    params(("client" = String, Path, description = "配额客户端，如 `tenant:<租户>`、`key:<密钥 ID>`、`jwt:<sub>`")),
+    request_body = QuotaLimits,
+    responses(
+        (status = 200, description = "设置后的配额与用量", body = QuotaStatus),
+        (status = 401, description = "管理接口密钥无效", body = String),
+    ),
+)]
+pub async fn put_quota(
+    State(state): State<AppState>,
+    Path(client): Path<String>,
+    Json(limits): Json<QuotaLimits>,
+) -> Json<QuotaStatus> {
+    tracing::info!(
+        daily = limits.daily,
+        monthly = limits.monthly,
+        "客户端 {} 的配额已更新",
+        client
+    );
+    state
+        .token_quota
+        .overrides
+        .lock()
+        .unwrap()
+        .insert(client.clone(), limits);
+    Json(state.token_quota.status(&client))
+}
+
+/// 删除单个客户端的配额，恢复为默认配额
+#[utoipa::path(
+    delete,
+    path = "/admin/quotas/{client}",
+    tag = "admin",
+    security(("admin_key" = [])),
+    params(("client" = String, Path, description = "配额客户端")),
+    responses(
+        (status = 204, description = "已恢复为默认配额"),
+        (status = 401, description = "管理接口密钥无效", body = String),
+        (status = 404, description = "未单独设置配额", body = String),
+    ),
+)]
+pub async fn delete_quota(
+    State(state): State<AppState>,
+    Path(client): Path<String>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    match state.token_quota.overrides.lock().unwrap().remove(&client) {
+        Some(_) => Ok(StatusCode::NO_CONTENT),
+        None => Err((StatusCode::NOT_FOUND, "未单独设置配额".to_string())),
+    }
+}
+
+/// 清零单个客户端的当日与当月用量
+#[utoipa::path(
+    post,
+    path = "/admin/quotas/{client}/reset",
+    tag = "admin",
+    security(("admin_key" = [])),
+    params(("client" = String, Path, description = "配额客户端")),
+    responses(
+        (status = 200, description = "清零后的配额与用量", body = QuotaStatus),
+        (status = 401, description = "管理接口密钥无效", body = String),
+    ),
+)]
+pub async fn reset_quota(
+    State(state): State<AppState>,
+    Path(client): Path<String>,
+) -> Json<QuotaStatus> {
+    tracing::info!("客户端 {} 的用量已清零", client);
+    state.token_quota.usage.lock().unwrap().remove(&client);
+    Json(state.token_quota.status(&client))
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+
+    use super::*;
+
+    /// 2026-01-30
+    const JAN_30: u64 = 20_483;
+
+    fn quota(daily: u64, monthly: u64) -> TokenQuota {
+        TokenQuota::new(QuotaLimits { daily, monthly }, 80)
+    }
+
+    #[test]
+    fn converts_days_to_months() {
+        assert_eq!(month_of(0), 1970 * 12);
+        assert_eq!(month_of(JAN_30), 2026 * 12);
+        assert_eq!(month_of(JAN_30 + 2), 2026 * 12 + 1);
+        // 2024-02-29 与 2024-03-01
+        assert_eq!(month_of(19_782), 2024 * 12 + 1);
+        assert_eq!(month_of(19_783), 2024 * 12 + 2);
+    }
+
+    #[test]
+    fn warns_at_soft_limit_and_rejects_at_hard_limit() {
+        let quota = quota(100, 0);
+        quota.charge_on("a", 79, JAN_30);
+        assert_eq!(quota.check_on("a", JAN_30), Ok(None));
+        quota.charge_on("a", 1, JAN_30);
+        let warning = quota.check_on("a", JAN_30).unwrap().unwrap();
+        assert_eq!(warning, "daily 80/100");
+        quota.charge_on("a", 20, JAN_30);
+        let (status, _) = quota.check_on("a", JAN_30).unwrap_err();
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        // 其他客户端不受影响
+        assert_eq!(quota.check_on("b", JAN_30), Ok(None));
+    }
+
+    #[test]
+    fn rolls_over_days_and_months() {
+        let quota = quota(100, 150);
+        quota.charge_on("a", 100, JAN_30);
+        assert!(quota.check_on("a", JAN_30).is_err());
+        // 次日每日用量清零，每月用量保留
+        quota.charge_on("a", 50, JAN_30 + 1);
+        let (_, message) = quota.check_on("a", JAN_30 + 1).unwrap_err();
+        assert!(message.contains("本月"));
+        // 跨月后全部清零
+        assert_eq!(quota.check_on("a", JAN_30 + 2), Ok(None));
+        quota.charge_on("a", 10, JAN_30 + 2);
+        let usage = quota.usage.lock().unwrap()["a"];
+        assert_eq!((usage.daily, usage.monthly), (10, 10));
+    }
+
+    #[test]
+    fn overrides_take_precedence_over_defaults() {
+        let quota = quota(0, 0);
+        assert!(!quota.is_enabled());
+        quota.overrides.lock().unwrap().insert(
+            "a".to_string(),
+            QuotaLimits {
+                daily: 10,
+                monthly: 0,
+            },
+        );
+        assert!(quota.is_enabled());
+        quota.charge_on("a", 10, JAN_30);
+        quota.charge_on("b", 10, JAN_30);
+        assert!(quota.check_on("a", JAN_30).is_err());
+        assert_eq!(quota.check_on("b", JAN_30), Ok(None));
+    }
+
+    #[test]
+    fn evicts_entries_from_previous_months_when_full() {
+        let quota = quota(100, 0);
+        for i in 0..MAX_TRACKED_CLIENTS {
+            quota.charge_on(&format!("old-{}", i), 1, JAN_30);
+        }
+        quota.charge_on("current", 1, JAN_30 + 2);
+        quota.charge_on("new", 1, JAN_30 + 2);
+        let usage = quota.usage.lock().unwrap();
+        assert_eq!(usage.len(), 2);
+        assert!(usage.contains_key("current") && usage.contains_key("new"));
+    }
+
+    #[test]
+    fn charges_usage_from_stream() {
+        let quota = Arc::new(quota(100, 0));
+        let chunks: Vec<Result<Bytes, ()>> = vec![
+            Ok(Bytes::from(
+                "data: {\"choices\":[{\"delta\":{\"content\":\"hi\"}}]}\n\n",
+            )),
+            Ok(Bytes::from(
+                "data: {\"choices\":[],\"usage\":{\"prompt_tokens\":7,\"completion_tokens\":3}}\n\ndata: [DONE]\n\n",
+            )),
+        ];
+        let stream =
+            quota
+                .clone()
+                .record_usage("a".to_string(), futures::stream::iter(chunks), true);
+        block_on(stream.collect::<Vec<_>>());
+        assert_eq!(quota.usage.lock().unwrap()["a"].daily, 10);
+    }
+
+    #[test]
+    fn forces_include_usage_on_streaming_requests() {
+        let body = Bytes::from(r#"{"model":"m","messages":[],"stream":true}"#);
+        let (body, strip) = request_usage(body).unwrap();
+        assert!(strip);
+        let value: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(value["stream_options"]["include_usage"], true);
+
+        let body = Bytes::from(
+            r#"{"model":"m","messages":[],"stream":true,"stream_options":{"include_usage":true}}"#,
+        );
+        assert!(!request_usage(body).unwrap().1);
+
+        let body = Bytes::from(r#"{"model":"m","messages":[]}"#);
+        let (unchanged, strip) = request_usage(body.clone()).unwrap();
+        assert!(!strip);
+        assert_eq!(unchanged, body);
+    }
+
+    #[test]
+    fn strips_only_usage_chunks() {
+        let chunks: Vec<Result<Bytes, ()>> = vec![
+            Ok(Bytes::from(
+                "data: {\"choices\":[{\"delta\":{\"content\":\"hi\"}}]}\n\ndata: {\"choi",
+            )),
+            Ok(Bytes::from(
+                "ces\":[],\"usage\":{\"prompt_tokens\":7}}\n\ndata: [DONE]\n\n",
+            )),
+        ];
+        let output: Vec<Bytes> = block_on(
+            strip_usage_chunks(futures::stream::iter(chunks))
+                .map(Result::unwrap)
+                .collect(),
+        );
+        assert_eq!(
+            output.concat(),
+            b"data: {\"choices\":[{\"delta\":{\"content\":\"hi\"}}]}\n\ndata: [DONE]\n\n"
+        );
+    }
+
+    #[test]
+    fn groups_tenants_and_identities() {
+        let identity = ClientIdentity {
+            scope: "jwt:alice".to_string(),
+            tenant: Some("acme".to_string()),
+        };
+        assert_eq!(quota_client(Some(&identity), "x"), "tenant:acme");
+        let identity = ClientIdentity {
+            scope: "key:1".to_string(),
+            tenant: None,
+        };
+        assert_eq!(quota_client(Some(&identity), "x"), "key:1");
+        assert_eq!(quota_client(None, "Bearer k"), client_id("Bearer k"));
+    }
+}
@@ -0,0 +1,458 @@
+use std::{collections::HashMap, str::FromStr};
+
+use crate::chat::{
+    ChatCompletionRequest, ChatCompletionResponse, Choice, Content, Delta, FunctionCall, ToolCall,
+};
+use axum::{body::Bytes, http::StatusCode};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde_json::Value;
+
+/// 占位符前缀，格式为 `[PII_类型_序号]`
+const PLACEHOLDER_PREFIX: &str = "[PII_";
+
+/// 占位符的最大长度，流式还原时超过该长度仍未闭合的 `[` 不再视为占位符
+const MAX_PLACEHOLDER_LEN: usize = 32;
+
+/// 中国大陆 18 位身份证号
+static ID_CARD: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"[1-9][0-9]{5}(?:18|19|20)[0-9]{2}(?:0[1-9]|1[0-2])(?:0[1-9]|[12][0-9]|3[01])[0-9]{3}[0-9Xx]")
+        .unwrap()
+});
+
+/// 手机号，可带 +86 前缀
+static PHONE: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?:\+?86[- ]?)?1[3-9][0-9]{9}").unwrap());
+
+static EMAIL: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"[A-Za-z0-9._%+-]+@[A-Za-z0-9.-]+\.[A-Za-z]{2,}").unwrap());
+
+/// 脱敏模式
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum RedactionMode {
+    /// 不脱敏
+    Off,
+    /// 替换为占位符，响应中保持占位符
+    Mask,
+    /// 替换为占位符，并在返回客户端前将响应中的占位符还原为原文
+    Reversible,
+}
+
+impl FromStr for RedactionMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "off" => Ok(Self::Off),
+            "mask" => Ok(Self::Mask),
+            "reversible" => Ok(Self::Reversible),
+            other => Err(anyhow::anyhow!("未知的脱敏模式: {}", other)),
+        }
+    }
+}
+
+impl RedactionMode {
+    /// 读取 `PII_REDACTION`，默认 `off`
+    pub fn from_env() -> anyhow::Result<Self> {
+        match std::env::var("PII_REDACTION") {
+            Ok(value) => value.parse(),
+            Err(_) => Ok(Self::Off),
+        }
+    }
+}
+
+/// 占位符与原文的对应关系，同一原文在一次请求中始终使用同一个占位符
+#[derive(Default)]
+pub struct TokenMap {
+    originals: HashMap<String, String>,
+    placeholders: HashMap<String, String>,
+}
+
+impl TokenMap {
+    pub fn is_empty(&self) -> bool {
+        self.originals.is_empty()
+    }
+
+    fn placeholder_for(&mut self, kind: &str, original: &str) -> String {
+        if let Some(placeholder) = self.placeholders.get(original) {
+            return placeholder.clone();
+        }
+        let placeholder = format!(
+            "{}{}_{}]",
+            PLACEHOLDER_PREFIX,
+            kind,
+            self.originals.len() + 1
+        );
+        self.originals
+            .insert(placeholder.clone(), original.to_string());
+        self.placeholders
+            .insert(original.to_string(), placeholder.clone());
+        placeholder
+    }
+
+    /// 将文本中的占位符还原为原文
+    pub fn restore(&self, text: &str) -> String {
+        let mut restored = text.to_string();
+        for (placeholder, original) in &self.originals {
+            restored = restored.replace(placeholder, original);
+        }
+        restored
+    }
+
+    /// 替换文本中所有匹配的敏感信息
+    fn redact_text(&mut self, text: &str) -> String {
+        let mut text = text.to_string();
+        for (kind, pattern) in [("ID", &*ID_CARD), ("PHONE", &*PHONE), ("EMAIL", &*EMAIL)] {
+            let mut redacted = String::with_capacity(text.len());
+            let mut last = 0;
+            for found in pattern.find_iter(&text) {
+                // 数字类规则要求前后不是数字，避免截取更长数字串的一部分
+                let before = text[..found.start()].chars().next_back();
+                let after = text[found.end()..].chars().next();
+                if kind != "EMAIL"
+                    && (before.is_some_and(|c| c.is_ascii_digit())
+                        || after.is_some_and(|c| c.is_ascii_digit()))
+                {
+                    continue;
+                }
+                redacted.push_str(&text[last..found.start()]);
+                redacted.push_str(&self.placeholder_for(kind, found.as_str()));
+                last = found.end();
+            }
+            redacted.push_str(&text[last..]);
+            text = redacted;
+        }
+        text
+    }
+}
+
+/// 对请求中消息的文本内容、工具调用参数与其他字符串字段（如 `name`）脱敏，返回占位符映射
+///
+/// 工具定义、工具名与请求级的其他参数不含用户数据，不做处理。
+fn redact_request(request: &mut ChatCompletionRequest) -> TokenMap {
+    let mut map = TokenMap::default();
+    for message in &mut request.messages {
+        match &mut message.content {
+            Content::Text(text) => *text = map.redact_text(text),
+            Content::Parts(parts) => {
+                for text in parts.iter_mut().filter_map(|part| part.text.as_mut()) {
+                    *text = map.redact_text(text);
+                }
+            }
+            Content::Other(value) => redact_value(&mut map, value),
+            Content::Absent => {}
+        }
+        // 参数是 JSON 文本，占位符不含引号与反斜杠，替换后仍是有效的 JSON
+        for call in message.tool_calls.iter_mut().flatten() {
+            if let Some(arguments) = &mut call.function.arguments {
+                *arguments = map.redact_text(arguments);
+            }
+        }
+        message
+            .extra
+            .values_mut()
+            .for_each(|value| redact_value(&mut map, value));
+    }
+    map
+}
+
+/// 对 JSON 值中的所有字符串脱敏
+fn redact_value(map: &mut TokenMap, value: &mut Value) {
+    match value {
+        Value::String(text) => *text = map.redact_text(text),
+        Value::Array(items) => items.iter_mut().for_each(|item| redact_value(map, item)),
+        Value::Object(fields) => fields
+            .values_mut()
+            .for_each(|field| redact_value(map, field)),
+        _ => {}
+    }
+}
+
+/// 流式响应中需要还原占位符的字段
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum Field {
+    Content,
+    ReasoningContent,
+    /// 指定序号的工具调用的参数
+    Arguments(u64),
+}
+
+/// 流式响应的占位符还原器
+///
+/// 占位符可能被拆在相邻的两个数据块中，每个 choice 的正文、推理内容与工具调用参数末尾疑似占位符开头的部分
+/// 会暂存到下一个数据块再还原，该 choice 结束时一并输出。
+pub struct StreamRestorer {
+    map: TokenMap,
+    /// 按（choice 序号，字段）暂存的文本
+    pending: HashMap<(u64, Field), String>,
+}
+
+impl StreamRestorer {
+    pub fn new(map: TokenMap) -> Self {
+        Self {
+            map,
+            pending: HashMap::new(),
+        }
+    }
+
+    /// 还原一个数据块中各 choice 的占位符
+    pub fn restore(&mut self, chunk: &mut ChatCompletionResponse) {
+        for choice in chunk.choices_mut() {
+            self.restore_choice(choice);
+        }
+    }
+
+    fn restore_choice(&mut self, choice: &mut Choice) {
+        let finished = choice.is_finished();
+        let index = choice.index;
+        let has_pending = self.pending.keys().any(|(choice, _)| *choice == index);
+        // 结束的数据块可能不带 delta，仍需补发暂存的文本
+        let delta = match choice.delta.as_mut() {
+            Some(delta) => delta,
+            None if finished && has_pending => choice.delta.insert(Delta::default()),
+            None => return,
+        };
+        self.restore_field(index, Field::Content, &mut delta.content, finished);
+        self.restore_field(
+            index,
+            Field::ReasoningContent,
+            &mut delta.reasoning_content,
+            finished,
+        );
+        for call in delta.tool_calls.iter_mut().flatten() {
+            let Some(call_index) = call.index else {
+                continue;
+            };
+            let field = Field::Arguments(call_index);
+            self.restore_field(index, field, &mut call.function.arguments, finished);
+        }
+
+        if !finished {
+            return;
+        }
+        // 本数据块中没有出现的工具调用，以只带参数的增量补发暂存的部分
+        let mut rest: Vec<_> = self
+            .pending
+            .extract_if(|(choice, field), _| {
+                *choice == index && matches!(field, Field::Arguments(_))
+            })
+            .filter_map(|((_, field), text)| match field {
+                Field::Arguments(call_index) => Some((call_index, text)),
+                _ => None,
+            })
+            .collect();
+        rest.sort_by_key(|(call_index, _)| *call_index);
+        for (call_index, text) in rest {
+            delta.tool_calls.get_or_insert_default().push(ToolCall {
+                index: Some(call_index),
+                function: FunctionCall {
+                    arguments: Some(self.map.restore(&text)),
+                    ..FunctionCall::default()
+                },
+                ..ToolCall::default()
+            });
+        }
+    }
+
+    /// 拼接暂存的文本后还原，choice 未结束时末尾疑似占位符开头的部分继续暂存
+    fn restore_field(
+        &mut self,
+        index: u64,
+        field: Field,
+        value: &mut Option<String>,
+        finished: bool,
+    ) {
+        let pending = self.pending.remove(&(index, field)).unwrap_or_default();
+        let current = value.as_deref().unwrap_or_default();
+        if pending.is_empty() && current.is_empty() {
+            return;
+        }
+
+        let text = pending + current;
+        let split = if finished {
+            text.len()
+        } else {
+            hold_back_at(&text)
+        };
+        let (ready, rest) = text.split_at(split);
+        if !rest.is_empty() {
+            self.pending.insert((index, field), rest.to_string());
+        }
+        *value = Some(self.map.restore(ready));
+    }
+}
+
+/// 找到文本末尾可能是未完整占位符的起始位置，没有时返回文本长度
+fn hold_back_at(text: &str) -> usize {
+    let Some(start) = text.rfind('[') else {
+        return text.len();
+    };
+    let tail = &text[start..];
+    let is_partial = tail.len() < MAX_PLACEHOLDER_LEN
+        && !tail.contains(']')
+        && (PLACEHOLDER_PREFIX.starts_with(tail)
+            || (tail.starts_with(PLACEHOLDER_PREFIX)
+                && tail[PLACEHOLDER_PREFIX.len()..]
+                    .chars()
+                    .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_')));
+    if is_partial { start } else { text.len() }
+}
+
+/// 对请求体脱敏，没有敏感信息时原样返回
+///
+/// 请求体不是有效的对话请求时返回 400，避免未脱敏的内容发往上游。
+pub fn redact_body(body: Bytes) -> Result<(Bytes, TokenMap), (StatusCode, String)> {
+    let Some(mut request) = ChatCompletionRequest::parse(&body) else {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "请求体不是有效的对话请求".to_string(),
+        ));
+    };
+    let map = redact_request(&mut request);
+    if map.is_empty() {
+        return Ok((body, map));
+    }
+
+    tracing::info!("请求中 {} 处敏感信息已替换为占位符", map.originals.len());
+    Ok((request.to_bytes()?, map))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn chunk(choice: Value) -> ChatCompletionResponse {
+        serde_json::from_value(json!({ "choices": [choice] })).unwrap()
+    }
+
+    fn restorer() -> StreamRestorer {
+        let mut map = TokenMap::default();
+        map.redact_text("a@example.com 13812345678");
+        StreamRestorer::new(map)
+    }
+
+    #[test]
+    fn redacts_numbers_only_on_digit_boundaries() {
+        let mut map = TokenMap::default();
+        assert_eq!(
+            map.redact_text("电话13812345678，备用+86 13912345678"),
+            "电话[PII_PHONE_1]，备用[PII_PHONE_2]"
+        );
+        // 更长数字串中的片段不视为手机号
+        assert_eq!(map.redact_text("订单 913812345678"), "订单 913812345678");
+        assert_eq!(map.redact_text("订单 138123456789"), "订单 138123456789");
+        assert_eq!(
+            map.redact_text("身份证11010519491231002X"),
+            "身份证[PII_ID_3]"
+        );
+        // 同一原文使用同一个占位符
+        assert_eq!(map.redact_text("13812345678"), "[PII_PHONE_1]");
+        assert_eq!(map.restore("[PII_PHONE_1]"), "13812345678");
+    }
+
+    #[test]
+    fn redacts_tool_arguments_and_extra_fields() {
+        let body = json!({
+            "model": "deepseek-chat",
+            "messages": [
+                { "role": "user", "name": "a@example.com", "content": "hi" },
+                {
+                    "role": "assistant",
+                    "tool_calls": [{
+                        "id": "call_1",
+                        "type": "function",
+                        "function": { "name": "send", "arguments": "{\"to\":\"a@example.com\"}" },
+                    }],
+                },
+            ],
+        });
+        let (body, map) = redact_body(Bytes::from(body.to_string())).unwrap();
+        let request: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(request["messages"][0]["name"], "[PII_EMAIL_1]");
+        let arguments = &request["messages"][1]["tool_calls"][0]["function"]["arguments"];
+        assert_eq!(arguments, "{\"to\":\"[PII_EMAIL_1]\"}");
+        assert_eq!(map.originals.len(), 1);
+    }
+
+    #[test]
+    fn holds_back_partial_placeholders() {
+        assert_eq!(hold_back_at("你好"), "你好".len());
+        assert_eq!(hold_back_at("邮箱 [PII"), "邮箱 ".len());
+        assert_eq!(hold_back_at("邮箱 [PII_EMA"), "邮箱 ".len());
+        assert_eq!(hold_back_at("邮箱 [PII_EMAIL_1"), "邮箱 ".len());
+        // 已闭合或不像占位符的方括号照常转发
+        assert_eq!(hold_back_at("[PII_EMAIL_1]"), "[PII_EMAIL_1]".len());
+        assert_eq!(hold_back_at("数组 [1, 2"), "数组 [1, 2".len());
+    }
+
+    #[test]
+    fn restores_placeholders_split_across_chunks() {
+        let mut restorer = restorer();
+        let mut first = chunk(json!({ "index": 0, "delta": { "content": "发给 [PII_EM" } }));
+        restorer.restore(&mut first);
+        let content = |chunk: &ChatCompletionResponse| {
+            chunk.choices()[0].delta.as_ref().unwrap().content.clone()
+        };
+        assert_eq!(content(&first).as_deref(), Some("发给 "));
+
+        let mut second = chunk(json!({ "index": 0, "delta": { "content": "AIL_2] 了" } }));
+        restorer.restore(&mut second);
+        assert_eq!(content(&second).as_deref(), Some("a@example.com 了"));
+
+        // 未结束时暂存的部分在结束数据块（即使不带 delta）中补发
+        let mut third = chunk(json!({ "index": 0, "delta": { "content": "[PII" } }));
+        restorer.restore(&mut third);
+        let mut last = chunk(json!({ "index": 0, "finish_reason": "stop" }));
+        restorer.restore(&mut last);
+        assert_eq!(content(&last).as_deref(), Some("[PII"));
+    }
+
+    #[test]
+    fn restores_placeholders_in_tool_call_arguments() {
+        let mut restorer = restorer();
+        let mut first = chunk(json!({
+            "index": 0,
+            "delta": { "tool_calls": [{ "index": 0, "function": { "arguments": "{\"to\":\"[PII_PH" } }] },
+        }));
+        restorer.restore(&mut first);
+        let mut last = chunk(json!({
+            "index": 0,
+            "delta": { "tool_calls": [{ "index": 0, "function": { "arguments": "ONE_1]\"}" } }] },
+            "finish_reason": "tool_calls",
+        }));
+        restorer.restore(&mut last);
+        let arguments = |chunk: &ChatCompletionResponse| {
+            chunk.choices()[0]
+                .delta
+                .as_ref()
+                .unwrap()
+                .tool_calls
+                .as_ref()
+                .unwrap()[0]
+                .function
+                .arguments
+                .clone()
+        };
+        assert_eq!(arguments(&first).as_deref(), Some("{\"to\":\""));
+        assert_eq!(arguments(&last).as_deref(), Some("13812345678\"}"));
+
+        // 结束数据块中没有该工具调用时单独补发
+        let mut first = chunk(json!({
+            "index": 0,
+            "delta": { "tool_calls": [{ "index": 1, "function": { "arguments": "[PII_PHONE_1" } }] },
+        }));
+        restorer.restore(&mut first);
+        let mut last = chunk(json!({ "index": 0, "delta": {}, "finish_reason": "tool_calls" }));
+        restorer.restore(&mut last);
+        let calls = last.choices()[0]
+            .delta
+            .as_ref()
+            .unwrap()
+            .tool_calls
+            .clone()
+            .unwrap();
+        assert_eq!(calls[0].index, Some(1));
+        assert_eq!(calls[0].function.arguments.as_deref(), Some("[PII_PHONE_1"));
+    }
+}
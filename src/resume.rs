@@ -0,0 +1,466 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    Extension,
+    body::{Body, Bytes},
+    extract::{Path, State},
+    http::{
+        HeaderMap, HeaderValue, StatusCode,
+        header::{CACHE_CONTROL, CONTENT_TYPE},
+    },
+    response::Response,
+};
+use futures::{Stream, StreamExt};
+use tokio::sync::watch;
+
+use crate::{
+    AppState, handlers::chat_completions::client_scope, keys::ClientIdentity, sse::SseParser,
+};
+
+/// 客户端重连时携带的最后一个事件 ID
+const LAST_EVENT_ID_HEADER: &str = "last-event-id";
+
+/// 未配置时单个响应可缓存的最大字节数
+const DEFAULT_MAX_BYTES: usize = 8 * 1024 * 1024;
+
+/// 清理已过期缓存的间隔
+const SWEEP_INTERVAL: Duration = Duration::from_secs(30);
+
+enum Progress {
+    /// 仍在接收上游事件
+    Streaming,
+    /// 上游已结束，记录结束时间用于过期清理
+    Finished(Instant),
+    /// 上游出错或客户端超过宽限期未重连，附带原因
+    Aborted(String),
+}
+
+struct Shared {
+    /// 缓存的事件，第一个事件的编号为 `offset`
+    events: VecDeque<Bytes>,
+    /// 已丢弃的事件数
+    offset: usize,
+    /// 缓存的字节数
+    bytes: usize,
+    /// 缓存超过上限后不再可续传，只保留读取中的客户端尚未读取的事件
+    resumable: bool,
+    progress: Progress,
+    /// 正在读取的客户端及其下一个要读取的事件编号
+    readers: HashMap<u64, usize>,
+    next_reader: u64,
+    /// 最后一个客户端断开的时间
+    detached_at: Instant,
+}
+
+impl Shared {
+    /// 不可续传时丢弃所有读取中的客户端都已读取的事件
+    fn trim(&mut self) {
+        if self.resumable {
+            return;
+        }
+        let end = self.offset + self.events.len();
+        let min = self.readers.values().copied().min().unwrap_or(end);
+        while self.offset < min
+            && let Some(event) = self.events.pop_front()
+        {
+            self.bytes -= event.len();
+            self.offset += 1;
+        }
+    }
+}
+
+/// 一次可续传的流式响应
+struct Entry {
+    /// 发起请求的客户端凭据，续传时需要一致
+    scope: String,
+    shared: Mutex<Shared>,
+    version: watch::Sender<u64>,
+}
+
+impl Entry {
+    fn update(&self, f: impl FnOnce(&mut Shared)) {
+        f(&mut self.shared.lock().unwrap());
+        self.version.send_modify(|version| *version += 1);
+    }
+}
+
+/// 流式对话续传
+///
+/// 读取 `STREAM_RESUME_GRACE_SECS`，配置后 SSE 对话响应由后台任务接收并按事件编号缓存，
+/// 客户端断开后继续生成，宽限期内可携带 `Last-Event-ID` 请求
+/// `GET /chat/completions/{completion_id}/resume` 补发错过的事件并继续接收；
+/// 宽限期内无人重连则关闭上游连接。生成结束后缓存同样保留一个宽限期。
+/// 缓存超过 `STREAM_RESUME_MAX_BYTES` 的响应不再可续传，客户端断开即关闭上游连接。
+pub struct ResumeStore {
+    entries: Mutex<HashMap<String, Arc<Entry>>>,
+    grace: Duration,
+    max_bytes: usize,
+}
+
+impl ResumeStore {
+    pub fn from_env() -> anyhow::Result<Option<Arc<Self>>> {
+        let Ok(value) = std::env::var("STREAM_RESUME_GRACE_SECS") else {
+            return Ok(None);
+        };
+        let max_bytes = match std::env::var("STREAM_RESUME_MAX_BYTES") {
+            Ok(value) => value.parse()?,
+            Err(_) => DEFAULT_MAX_BYTES,
+        };
+        Ok(Some(Arc::new(Self {
+            entries: Mutex::default(),
+            grace: Duration::from_secs(value.parse()?),
+            max_bytes,
+        })))
+    }
+
+    /// 在后台接收 SSE 响应流并缓存，返回从第一个事件开始读取的响应流
+    ///
+    /// `completion_id` 为服务端生成的对话 ID，即响应头 `x-completion-id`。
+    pub fn track<S, E>(
+        self: &Arc<Self>,
+        completion_id: String,
+        scope: String,
+        stream: S,
+    ) -> impl Stream<Item = Result<Bytes, std::io::Error>> + use<S, E>
+    where
+        S: Stream<Item = Result<Bytes, E>> + Send + 'static,
+        E: std::fmt::Display,
+    {
+        let entry = Arc::new(Entry {
+            scope,
+            shared: Mutex::new(Shared {
+                events: VecDeque::new(),
+                offset: 0,
+                bytes: 0,
+                resumable: true,
+                progress: Progress::Streaming,
+                readers: HashMap::new(),
+                next_reader: 0,
+                detached_at: Instant::now(),
+            }),
+            version: watch::Sender::new(0),
+        });
+        self.entries
+            .lock()
+            .unwrap()
+            .insert(completion_id.clone(), entry.clone());
+        let reader = read_events(entry.clone(), 0);
+
+        let store = self.clone();
+        tokio::spawn(async move {
+            let mut parser = SseParser::default();
+            let mut stream = std::pin::pin!(stream);
+            let mut version = entry.version.subscribe();
+            loop {
+                // 没有客户端在读取时，超过宽限期即放弃生成，不可续传时立即放弃
+                let deadline = {
+                    let shared = entry.shared.lock().unwrap();
+                    (shared.readers.is_empty()).then(|| {
+                        if shared.resumable {
+                            shared.detached_at + store.grace
+                        } else {
+                            shared.detached_at
+                        }
+                    })
+                };
+                let deadline = async {
+                    match deadline {
+                        Some(deadline) => tokio::time::sleep_until(deadline.into()).await,
+                        None => std::future::pending().await,
+                    }
+                };
+
+                tokio::select! {
+                    _ = deadline => {
+                        tracing::info!("对话 {} 的客户端已断开且无法续传，停止上游生成", completion_id);
+                        entry.update(|shared| {
+                            shared.progress = Progress::Aborted("客户端已断开且无法续传".to_string())
+                        });
+                        store.remove(&completion_id, &entry);
+                        return;
+                    }
+                    // 客户端连接或断开后重新计算截止时间
+                    _ = version.changed() => {}
+                    chunk = stream.next() => match chunk {
+                        Some(Ok(bytes)) => {
+                            let events = parser.push(&bytes);
+                            if !events.is_empty() {
+                                let mut overflowed = false;
+                                entry.update(|shared| {
+                                    for event in events {
+                                        shared.bytes += event.raw.len();
+                                        shared.events.push_back(event.raw);
+                                    }
+                                    if shared.resumable && shared.bytes > store.max_bytes {
+                                        shared.resumable = false;
+                                        overflowed = true;
+                                    }
+                                    shared.trim();
+                                });
+                                if overflowed {
+                                    tracing::info!(
+                                        "对话 {} 的响应超过 {} 字节，不再可续传",
+                                        completion_id,
+                                        store.max_bytes
+                                    );
+                                    store.remove(&completion_id, &entry);
+                                }
+                            }
+                        }
+                        Some(Err(e)) => {
+                            tracing::warn!("可续传请求的上游响应中断: {}", e);
+                            entry.update(|shared| {
+                                shared.progress = Progress::Aborted(format!("上游响应中断: {}", e))
+                            });
+                            store.remove(&completion_id, &entry);
+                            return;
+                        }
+                        None => {
+                            entry.update(|shared| {
+                                if let Some(event) = parser.finish() {
+                                    shared.bytes += event.len();
+                                    shared.events.push_back(event);
+                                }
+                                shared.progress = Progress::Finished(Instant::now());
+                            });
+                            return;
+                        }
+                    },
+                }
+            }
+        });
+
+        reader
+    }
+
+    /// 启动后台清理任务，定期移除生成结束后超过宽限期的缓存
+    pub fn spawn_sweeper(self: Arc<Self>) {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+            loop {
+                interval.tick().await;
+                self.sweep();
+            }
+        });
+    }
+
+    fn sweep(&self) {
+        self.entries.lock().unwrap().retain(|_, entry| {
+            match entry.shared.lock().unwrap().progress {
+                Progress::Finished(at) => at.elapsed() < self.grace,
+                _ => true,
+            }
+        });
+    }
+
+    fn remove(&self, completion_id: &str, entry: &Arc<Entry>) {
+        let mut entries = self.entries.lock().unwrap();
+        if entries
+            .get(completion_id)
+            .is_some_and(|current| Arc::ptr_eq(current, entry))
+        {
+            entries.remove(completion_id);
+        }
+    }
+}
+
+/// 读取中的客户端，断开时记录时间以开始计算宽限期
+struct ReaderGuard {
+    entry: Arc<Entry>,
+    id: u64,
+}
+
+impl Drop for ReaderGuard {
+    fn drop(&mut self) {
+        self.entry.update(|shared| {
+            shared.readers.remove(&self.id);
+            if shared.readers.is_empty() {
+                shared.detached_at = Instant::now();
+            }
+            shared.trim();
+        });
+    }
+}
+
+/// 从第 `from` 个事件之后开始读取，每个事件前加上 `id` 字段，跟随新到达的事件直到结束
+fn read_events(
+    entry: Arc<Entry>,
+    from: usize,
+) -> impl Stream<Item = Result<Bytes, std::io::Error>> {
+    let mut id = 0;
+    entry.update(|shared| {
+        id = shared.next_reader;
+        shared.next_reader += 1;
+        shared.readers.insert(id, from);
+    });
+    let receiver = entry.version.subscribe();
+    // 索引为 `None` 表示已返回错误，读取结束
+    futures::stream::unfold(
+        (ReaderGuard { entry, id }, Some(from), receiver),
+        |(guard, index, mut receiver)| async move {
+            let index = index?;
+            loop {
+                receiver.borrow_and_update();
+                {
+                    let mut shared = guard.entry.shared.lock().unwrap();
+                    if index < shared.offset {
+                        drop(shared);
+                        let error = std::io::Error::other("事件已超出续传缓存");
+                        return Some((Err(error), (guard, None, receiver)));
+                    }
+                    if let Some(event) = shared.events.get(index - shared.offset) {
+                        let mut bytes = format!("id: {}\n", index + 1).into_bytes();
+                        bytes.extend_from_slice(event);
+                        shared.readers.insert(guard.id, index + 1);
+                        shared.trim();
+                        drop(shared);
+                        return Some((Ok(Bytes::from(bytes)), (guard, Some(index + 1), receiver)));
+                    }
+                    match &shared.progress {
+                        Progress::Streaming => {}
+                        Progress::Finished(_) => return None,
+                        Progress::Aborted(reason) => {
+                            let error = std::io::Error::other(reason.clone());
+                            drop(shared);
+                            return Some((Err(error), (guard, None, receiver)));
+                        }
+                    }
+                }
+                if receiver.changed().await.is_err() {
+                    return None;
+                }
+            }
+        },
+    )
+}
+
+/// 续传流式对话：补发 `Last-Event-ID` 之后的事件并继续接收，需使用与原请求相同的凭据
+#[utoipa::path(
+    get,
+    path = "/chat/completions/{completion_id}/resume",
+    tag = "chat",
+    params(
+        ("completion_id" = String, Path, description = "对话响应头 `x-completion-id` 中的 ID"),
+        ("Last-Event-ID" = Option<u64>, Header, description = "最后收到的事件 ID"),
+    ),
+    responses(
+        (status = 200, description = "SSE 事件流", body = String, content_type = "text/event-stream"),
+        (status = 400, description = "Last-Event-ID 无效", body = String),
+        (status = 404, description = "请求不存在、已过期、超出续传缓存或凭据不一致", body = String),
+    ),
+)]
+pub async fn handle_resume(
+    State(state): State<AppState>,
+    Path(completion_id): Path<String>,
+    identity: Option<Extension<ClientIdentity>>,
+    headers: HeaderMap,
+) -> Result<Response, (StatusCode, String)> {
+    let not_found = || (StatusCode::NOT_FOUND, "请求不存在或已过期".to_string());
+    let store = state.resume_store.as_ref().ok_or_else(not_found)?;
+    let entry = store
+        .entries
+        .lock()
+        .unwrap()
+        .get(&completion_id)
+        .cloned()
+        .ok_or_else(not_found)?;
+
+    if client_scope(&headers, identity.as_deref(), &state.api_key) != entry.scope {
+        return Err(not_found());
+    }
+
+    let from = match headers.get(LAST_EVENT_ID_HEADER) {
+        Some(value) => value
+            .to_str()
+            .ok()
+            .and_then(|value| value.trim().parse().ok())
+            .ok_or((StatusCode::BAD_REQUEST, "Last-Event-ID 无效".to_string()))?,
+        None => 0,
+    };
+
+    let mut response = Response::new(Body::from_stream(read_events(entry, from)));
+    let headers = response.headers_mut();
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static("text/event-stream"));
+    headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store(grace: Duration, max_bytes: usize) -> Arc<ResumeStore> {
+        Arc::new(ResumeStore {
+            entries: Mutex::default(),
+            grace,
+            max_bytes,
+        })
+    }
+
+    fn upstream(events: &[&'static str]) -> impl Stream<Item = Result<Bytes, String>> + use<> {
+        let chunks: Vec<_> = events.iter().map(|event| Ok(Bytes::from(*event))).collect();
+        futures::stream::iter(chunks)
+    }
+
+    async fn collect(stream: impl Stream<Item = Result<Bytes, std::io::Error>>) -> String {
+        let chunks: Vec<_> = stream.collect().await;
+        chunks
+            .into_iter()
+            .map(|chunk| String::from_utf8(chunk.unwrap().to_vec()).unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn replays_events_after_last_event_id() {
+        let store = store(Duration::from_secs(60), DEFAULT_MAX_BYTES);
+        let events = ["data: a\n\n", "data: b\n\n", "data: c\n\n"];
+        let reader = store.track("c1".to_string(), "scope".to_string(), upstream(&events));
+        assert_eq!(
+            collect(reader).await,
+            "id: 1\ndata: a\n\nid: 2\ndata: b\n\nid: 3\ndata: c\n\n"
+        );
+
+        // 生成结束后仍可从任意已读事件之后续传
+        let entry = store.entries.lock().unwrap().get("c1").cloned().unwrap();
+        assert_eq!(
+            collect(read_events(entry.clone(), 1)).await,
+            "id: 2\ndata: b\n\nid: 3\ndata: c\n\n"
+        );
+        assert_eq!(collect(read_events(entry, 3)).await, "");
+
+        store.sweep();
+        assert!(store.entries.lock().unwrap().contains_key("c1"));
+    }
+
+    #[tokio::test]
+    async fn sweeps_finished_entries_after_grace() {
+        let store = store(Duration::ZERO, DEFAULT_MAX_BYTES);
+        let reader = store.track(
+            "c1".to_string(),
+            "scope".to_string(),
+            upstream(&["data: a\n\n"]),
+        );
+        collect(reader).await;
+        assert!(store.entries.lock().unwrap().contains_key("c1"));
+        store.sweep();
+        assert!(store.entries.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn stops_resuming_after_byte_cap() {
+        let store = store(Duration::from_secs(60), 16);
+        let events = ["data: first\n\n", "data: second\n\n", "data: third\n\n"];
+        let reader = store.track("c1".to_string(), "scope".to_string(), upstream(&events));
+
+        // 已连接的客户端仍能读完，但超过上限后不再登记为可续传
+        assert_eq!(
+            collect(reader).await,
+            "id: 1\ndata: first\n\nid: 2\ndata: second\n\nid: 3\ndata: third\n\n"
+        );
+        assert!(store.entries.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,225 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use axum::{
+    body::{Body, Bytes},
+    http::{HeaderMap, Method, StatusCode},
+};
+use futures::{Stream, StreamExt, TryStreamExt, stream};
+use tokio::io::AsyncWriteExt;
+use tokio_util::io::ReaderStream;
+
+/// 上游请求的重试次数，标签 `reason`（`connect` / `status`）
+const UPSTREAM_RETRIES_TOTAL: &str = "upstream_retries_total";
+
+/// 可重放请求体的默认上限
+const DEFAULT_BUFFER_MAX_BYTES: u64 = 64 * 1024 * 1024;
+
+/// 请求体在内存中缓冲的默认上限，超出后写入临时文件
+const DEFAULT_MEMORY_MAX_BYTES: usize = 1024 * 1024;
+
+/// 首次重试前的等待时间，之后每次翻倍
+const RETRY_BACKOFF: Duration = Duration::from_millis(200);
+
+/// 视为上游暂时不可用、可以重试的状态码
+const RETRYABLE_STATUS: [StatusCode; 3] = [
+    StatusCode::BAD_GATEWAY,
+    StatusCode::SERVICE_UNAVAILABLE,
+    StatusCode::GATEWAY_TIMEOUT,
+];
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// 写入磁盘的请求体，释放时删除临时文件，读取流持有其引用直到读取结束
+pub struct SpillFile {
+    path: PathBuf,
+}
+
+impl SpillFile {
+    async fn create(dir: &Path) -> std::io::Result<(Self, tokio::fs::File)> {
+        let path = dir.join(format!("free-model-{}.body", uuid::Uuid::now_v7()));
+        let file = tokio::fs::File::create(&path).await?;
+        Ok((Self { path }, file))
+    }
+
+    async fn stream(
+        self: Arc<Self>,
+    ) -> std::io::Result<impl Stream<Item = std::io::Result<Bytes>> + Send + 'static> {
+        let file = tokio::fs::File::open(&self.path).await?;
+        Ok(ReaderStream::new(file).map(move |chunk| {
+            let _spill = &self;
+            chunk
+        }))
+    }
+}
+
+impl Drop for SpillFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// 发往上游的请求体
+pub enum UpstreamBody {
+    /// 已完整缓冲在内存中，可重复发送
+    Memory(Bytes),
+    /// 已完整写入临时文件，可重复发送
+    Disk(Arc<SpillFile>),
+    /// 超出缓冲上限或未启用重试，只能发送一次
+    Streaming(reqwest::Body),
+}
+
+impl UpstreamBody {
+    /// 为一次发送构造请求体，只能发送一次的请求体被取出
+    async fn take(&mut self) -> std::io::Result<reqwest::Body> {
+        match self {
+            Self::Memory(bytes) => Ok(reqwest::Body::from(bytes.clone())),
+            Self::Disk(spill) => Ok(reqwest::Body::wrap_stream(spill.clone().stream().await?)),
+            Self::Streaming(body) => Ok(std::mem::replace(body, reqwest::Body::from(Bytes::new()))),
+        }
+    }
+
+    fn is_replayable(&self) -> bool {
+        !matches!(self, Self::Streaming(_))
+    }
+}
+
+/// 上游请求重试
+///
+/// `UPSTREAM_RETRIES` 大于 0 时，连接失败或上游返回 502/503/504 的请求按指数退避重试。
+/// 重试需要重放请求体：不超过 `RETRY_BUFFER_MAX_BYTES`（默认 64 MiB）的请求体先完整缓冲，
+/// 超过 `RETRY_MEMORY_MAX_BYTES`（默认 1 MiB）的部分写入 `RETRY_SPILL_DIR`（默认系统临时目录）下的临时文件；
+/// 更大的请求体保持流式转发，不重试。
+pub struct UpstreamRetry {
+    retries: u32,
+    buffer_max: u64,
+    memory_max: usize,
+    spill_dir: PathBuf,
+}
+
+impl UpstreamRetry {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let retries = match std::env::var("UPSTREAM_RETRIES") {
+            Ok(value) => value.parse()?,
+            Err(_) => 0,
+        };
+        let buffer_max = match std::env::var("RETRY_BUFFER_MAX_BYTES") {
+            Ok(value) => value.parse()?,
+            Err(_) => DEFAULT_BUFFER_MAX_BYTES,
+        };
+        let memory_max = match std::env::var("RETRY_MEMORY_MAX_BYTES") {
+            Ok(value) => value.parse()?,
+            Err(_) => DEFAULT_MEMORY_MAX_BYTES,
+        };
+        let spill_dir = std::env::var("RETRY_SPILL_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(|_| std::env::temp_dir());
+        Ok(Self {
+            retries,
+            buffer_max,
+            memory_max,
+            spill_dir,
+        })
+    }
+
+    /// 按大小缓冲客户端请求体，未启用重试或请求体过大时直接流式转发
+    pub async fn buffer(
+        &self,
+        body: Body,
+        content_length: Option<u64>,
+    ) -> Result<UpstreamBody, (StatusCode, String)> {
+        let mut data = body.into_data_stream();
+        if self.retries == 0 || content_length.is_some_and(|length| length > self.buffer_max) {
+            return Ok(UpstreamBody::Streaming(reqwest::Body::wrap_stream(data)));
+        }
+
+        let io_error = |e: std::io::Error| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string());
+        let mut memory = Vec::new();
+        let mut spill: Option<(SpillFile, tokio::fs::File)> = None;
+        let mut total = 0u64;
+        while let Some(chunk) = data.next().await {
+            let chunk = chunk.map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+            total += chunk.len() as u64;
+
+            // 未声明长度的请求体超出上限时，已读部分与剩余部分拼接后流式转发
+            if total > self.buffer_max {
+                let buffered = match spill {
+                    Some((spill, mut file)) => {
+                        file.flush().await.map_err(io_error)?;
+                        Arc::new(spill)
+                            .stream()
+                            .await
+                            .map_err(io_error)?
+                            .map_err(BoxError::from)
+                            .boxed()
+                    }
+                    None => stream::iter([Ok(Bytes::from(memory))]).boxed(),
+                };
+                let rest = stream::iter([Ok(chunk)]).chain(data.map_err(BoxError::from));
+                return Ok(UpstreamBody::Streaming(reqwest::Body::wrap_stream(
+                    buffered.chain(rest),
+                )));
+            }
+
+            if spill.is_none() && memory.len() + chunk.len() > self.memory_max {
+                let (spill_file, mut file) =
+                    SpillFile::create(&self.spill_dir).await.map_err(io_error)?;
+                file.write_all(&memory).await.map_err(io_error)?;
+                memory = Vec::new();
+                spill = Some((spill_file, file));
+            }
+            match &mut spill {
+                Some((_, file)) => file.write_all(&chunk).await.map_err(io_error)?,
+                None => memory.extend_from_slice(&chunk),
+            }
+        }
+
+        match spill {
+            Some((spill, mut file)) => {
+                file.flush().await.map_err(io_error)?;
+                Ok(UpstreamBody::Disk(Arc::new(spill)))
+            }
+            None => Ok(UpstreamBody::Memory(Bytes::from(memory))),
+        }
+    }
+
+    /// 发送请求，请求体可重放时按配置重试
+    pub async fn send(
+        &self,
+        client: &reqwest::Client,
+        method: Method,
+        url: &str,
+        headers: HeaderMap,
+        mut body: UpstreamBody,
+    ) -> Result<reqwest::Response, String> {
+        let mut attempt = 0;
+        loop {
+            let result = client
+                .request(method.clone(), url)
+                .headers(headers.clone())
+                .body(body.take().await.map_err(|e| e.to_string())?)
+                .send()
+                .await;
+            let reason = match &result {
+                Err(e) if e.is_connect() => "connect",
+                Ok(response) if RETRYABLE_STATUS.contains(&response.status()) => "status",
+                _ => return result.map_err(|e| e.to_string()),
+            };
+            if attempt >= self.retries || !body.is_replayable() {
+                return result.map_err(|e| e.to_string());
+            }
+
+            attempt += 1;
+            metrics::counter!(UPSTREAM_RETRIES_TOTAL, "reason" => reason).increment(1);
+            match &result {
+                Ok(response) => tracing::warn!(attempt, "上游返回 {}，重试", response.status()),
+                Err(e) => tracing::warn!(attempt, "连接上游失败，重试: {}", e),
+            }
+            tokio::time::sleep(RETRY_BACKOFF.saturating_mul(2u32.saturating_pow(attempt - 1)))
+                .await;
+        }
+    }
+}
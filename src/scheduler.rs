@@ -0,0 +1,230 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use axum::{
+    body::Body,
+    extract::{Request, State},
+    http::{HeaderMap, HeaderValue, StatusCode, header::AUTHORIZATION, header::RETRY_AFTER},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use futures::StreamExt;
+use tokio::sync::oneshot;
+
+use crate::{AppState, keys::ClientIdentity};
+
+/// 未配置时排队等待上游名额的最长时间
+const DEFAULT_QUEUE_TIMEOUT_SECS: u64 = 30;
+
+/// 未携带 Authorization 的请求归入同一个客户端
+const ANONYMOUS_CLIENT: &str = "anonymous";
+
+struct Inner {
+    /// 空闲的上游名额
+    available: usize,
+    /// 各客户端排队中的请求
+    queues: HashMap<String, VecDeque<oneshot::Sender<()>>>,
+    /// 有请求排队的客户端，按轮转顺序排列
+    ring: VecDeque<String>,
+    /// 队首客户端在本轮已获得的名额数
+    granted_in_turn: usize,
+}
+
+/// 上游并发的公平调度器
+///
+/// 所有产生上游调用的请求共享 `UPSTREAM_MAX_CONCURRENCY` 个名额。名额用满后请求按客户端
+/// （Authorization 凭据）分别排队，释放的名额在有排队的客户端之间加权轮转分配：
+/// 权重为 `w` 的客户端每轮最多连续获得 `w` 个名额，避免单个客户端占满上游。
+pub struct FairScheduler {
+    inner: Mutex<Inner>,
+    weights: HashMap<String, usize>,
+    queue_timeout: Duration,
+}
+
+impl FairScheduler {
+    /// 读取 `UPSTREAM_MAX_CONCURRENCY`，未配置时返回 `None` 表示不调度；
+    /// `CLIENT_WEIGHTS` 格式为 `凭据=权重`，逗号分隔，未配置的客户端权重为 1；
+    /// `UPSTREAM_QUEUE_TIMEOUT_SECS` 为最长排队时间，默认 30 秒
+    pub fn from_env() -> anyhow::Result<Option<Arc<Self>>> {
+        let Ok(capacity) = std::env::var("UPSTREAM_MAX_CONCURRENCY") else {
+            return Ok(None);
+        };
+
+        let mut weights = HashMap::new();
+        if let Ok(config) = std::env::var("CLIENT_WEIGHTS") {
+            for entry in config.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+                let (client, weight) = entry
+                    .rsplit_once('=')
+                    .ok_or_else(|| anyhow::anyhow!("CLIENT_WEIGHTS 格式错误"))?;
+                let weight: usize = weight.trim().parse()?;
+                weights.insert(client.trim().to_string(), weight.max(1));
+            }
+        }
+
+        let queue_timeout = match std::env::var("UPSTREAM_QUEUE_TIMEOUT_SECS") {
+            Ok(value) => value.parse()?,
+            Err(_) => DEFAULT_QUEUE_TIMEOUT_SECS,
+        };
+
+        Ok(Some(Arc::new(Self {
+            inner: Mutex::new(Inner {
+                available: capacity.parse()?,
+                queues: HashMap::new(),
+                ring: VecDeque::new(),
+                granted_in_turn: 0,
+            }),
+            weights,
+            queue_timeout: Duration::from_secs(queue_timeout),
+        })))
+    }
+
+    /// 获取一个上游名额，排队超时返回 `None`
+    async fn acquire(self: &Arc<Self>, client: &str) -> Option<Permit> {
+        let receiver = {
+            let mut inner = self.inner.lock().unwrap();
+            if inner.available > 0 && inner.ring.is_empty() {
+                inner.available -= 1;
+                return Some(Permit(self.clone()));
+            }
+
+            let (sender, receiver) = oneshot::channel();
+            let queue = inner.queues.entry(client.to_string()).or_default();
+            queue.push_back(sender);
+            if queue.len() == 1 {
+                inner.ring.push_back(client.to_string());
+            }
+            receiver
+        };
+
+        let mut waiter = Waiter {
+            scheduler: self.clone(),
+            receiver,
+            granted: false,
+        };
+        let granted = tokio::time::timeout(self.queue_timeout, &mut waiter.receiver).await;
+        if matches!(granted, Ok(Ok(()))) {
+            waiter.granted = true;
+            return Some(Permit(self.clone()));
+        }
+        None
+    }
+
+    /// 依次获取多个上游名额，任一排队超时时归还已获取的名额并返回 `None`
+    pub async fn acquire_many(self: &Arc<Self>, client: &str, count: u64) -> Option<Vec<Permit>> {
+        let mut permits = Vec::new();
+        for _ in 0..count {
+            permits.push(self.acquire(client).await?);
+        }
+        Some(permits)
+    }
+
+    /// 归还名额：按加权轮转交给下一个排队的请求，没有排队时放回空闲池
+    fn release(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        while let Some(client) = inner.ring.front().cloned() {
+            let weight = self.weights.get(&client).copied().unwrap_or(1);
+            let queue = inner.queues.get_mut(&client).unwrap();
+            let sender = queue.pop_front();
+            let drained = queue.is_empty();
+
+            if drained {
+                inner.queues.remove(&client);
+                inner.ring.pop_front();
+                inner.granted_in_turn = 0;
+            }
+
+            // 排队的请求已超时或被取消时继续分配给下一个
+            let Some(sender) = sender else { continue };
+            if sender.send(()).is_err() {
+                continue;
+            }
+
+            if !drained {
+                inner.granted_in_turn += 1;
+                if inner.granted_in_turn >= weight {
+                    inner.ring.rotate_left(1);
+                    inner.granted_in_turn = 0;
+                }
+            }
+            return;
+        }
+        inner.available += 1;
+    }
+}
+
+/// 已获得的上游名额，丢弃时归还
+pub struct Permit(Arc<FairScheduler>);
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.0.release();
+    }
+}
+
+/// 排队中的请求；超时或被取消时若名额恰好已分配过来，需要归还，避免名额泄漏
+struct Waiter {
+    scheduler: Arc<FairScheduler>,
+    receiver: oneshot::Receiver<()>,
+    granted: bool,
+}
+
+impl Drop for Waiter {
+    fn drop(&mut self) {
+        if self.granted {
+            return;
+        }
+        self.receiver.close();
+        if self.receiver.try_recv().is_ok() {
+            self.scheduler.release();
+        }
+    }
+}
+
+/// 调度时归属的客户端
+///
+/// 通过 JWT 鉴权的客户端按身份调度，JWT 刷新后仍属于同一客户端；其余按 Authorization 凭据。
+pub fn client_of(headers: &HeaderMap, identity: Option<&ClientIdentity>) -> String {
+    match identity {
+        Some(identity) => identity.scope.clone(),
+        None => headers
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.strip_prefix("Bearer ").unwrap_or(value).to_string())
+            .unwrap_or_else(|| ANONYMOUS_CLIENT.to_string()),
+    }
+}
+
+/// 公平调度中间件：名额一直持有到响应体（含流式响应）传输结束
+pub async fn schedule_fairly(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(scheduler) = state.scheduler.clone() else {
+        return next.run(request).await;
+    };
+
+    let client = client_of(
+        request.headers(),
+        request.extensions().get::<ClientIdentity>(),
+    );
+    let Some(permit) = scheduler.acquire(&client).await else {
+        tracing::warn!("等待上游名额超时，拒绝请求");
+        let mut response =
+            (StatusCode::TOO_MANY_REQUESTS, "upstream busy, retry later").into_response();
+        response
+            .headers_mut()
+            .insert(RETRY_AFTER, HeaderValue::from_static("1"));
+        return response;
+    };
+
+    let (parts, body) = next.run(request).await.into_parts();
+    let stream = body.into_data_stream().map(move |chunk| {
+        let _permit = &permit;
+        chunk
+    });
+    Response::from_parts(parts, Body::from_stream(stream))
+}
@@ -0,0 +1,184 @@
+use std::collections::HashMap;
+
+use anyhow::Context;
+use axum::http::{HeaderMap, HeaderName, HeaderValue, StatusCode};
+use rhai::{AST, Dynamic, Engine, Map, Scope};
+
+use crate::overrides::{Override, UpstreamOverrides};
+
+/// 脚本的入口函数
+const ENTRY: &str = "on_request";
+
+/// 可配置脚本的路由
+const ROUTES: &[&str] = &["chat", "translate"];
+
+/// 单次调用可执行的操作数上限，防止脚本死循环
+const MAX_OPERATIONS: u64 = 1_000_000;
+
+/// 脚本对请求的处理结果
+#[derive(Default)]
+pub struct ScriptDecision {
+    /// 改写的上游请求头，值为 `None` 时移除
+    pub headers: Vec<(HeaderName, Option<HeaderValue>)>,
+    /// 脚本选择的上游
+    pub upstream: Option<Override>,
+}
+
+/// 将脚本改写的请求头应用到发往上游的请求头
+pub fn apply_headers(rewrites: &[(HeaderName, Option<HeaderValue>)], headers: &mut HeaderMap) {
+    for (name, value) in rewrites {
+        match value {
+            Some(value) => {
+                headers.insert(name.clone(), value.clone());
+            }
+            None => {
+                headers.remove(name);
+            }
+        }
+    }
+}
+
+/// Rhai 路由脚本
+///
+/// 读取 `ROUTE_SCRIPTS`（逗号分隔的 `路由=脚本路径`，路由为 `chat` 或 `translate`），
+/// 每个请求调用脚本的 `on_request(request, headers)`：`request` 为发往上游的对话请求体，
+/// `headers` 为客户端请求头（名称小写）。返回 `()` 表示不做处理，返回对象时可包含：
+///
+/// - `reject`：拒绝请求，值为返回给客户端的 403 错误信息
+/// - `headers`：改写发往上游的请求头，值为 `()` 时移除
+/// - `base_url` / `key_id`：选择上游，规则与 `X-Upstream-Base-Url` / `X-Upstream-Key-Id` 相同
+///
+/// 脚本无法访问文件与网络，每次调用限制操作数。
+pub struct RouteScripts {
+    engine: Engine,
+    scripts: HashMap<String, (String, AST)>,
+}
+
+impl RouteScripts {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+
+        let mut scripts = HashMap::new();
+        if let Ok(config) = std::env::var("ROUTE_SCRIPTS") {
+            for entry in config.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+                let (route, path) = entry
+                    .split_once('=')
+                    .ok_or_else(|| anyhow::anyhow!("ROUTE_SCRIPTS 格式错误: {}", entry))?;
+                let (route, path) = (route.trim(), path.trim());
+                if !ROUTES.contains(&route) {
+                    anyhow::bail!("未知的路由 {}，可选 {}", route, ROUTES.join(","));
+                }
+                let ast = engine
+                    .compile_file(path.into())
+                    .map_err(|e| anyhow::anyhow!("{}", e))
+                    .with_context(|| format!("加载脚本 {} 失败", path))?;
+                if !ast.iter_functions().any(|function| function.name == ENTRY) {
+                    anyhow::bail!("脚本 {} 未定义 {}", path, ENTRY);
+                }
+                tracing::info!("路由 {} 已加载脚本 {}", route, path);
+                scripts.insert(route.to_string(), (path.to_string(), ast));
+            }
+        }
+        Ok(Self { engine, scripts })
+    }
+
+    /// 路由是否配置了脚本
+    pub fn has(&self, route: &str) -> bool {
+        self.scripts.contains_key(route)
+    }
+
+    /// 以请求体与请求头调用路由的脚本，未配置脚本时返回 `None`
+    pub fn evaluate(
+        &self,
+        route: &str,
+        headers: &HeaderMap,
+        body: &serde_json::Value,
+        overrides: &UpstreamOverrides,
+        default_key: &str,
+    ) -> Result<Option<ScriptDecision>, (StatusCode, String)> {
+        let Some((path, ast)) = self.scripts.get(route) else {
+            return Ok(None);
+        };
+        let script_error = |e: String| {
+            tracing::warn!("路由脚本 {} 执行失败: {}", path, e);
+            metrics::counter!("route_script_errors_total", "route" => route.to_string())
+                .increment(1);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("路由脚本执行失败: {}", e),
+            )
+        };
+
+        let request = rhai::serde::to_dynamic(body).map_err(|e| script_error(e.to_string()))?;
+        let mut header_map = Map::new();
+        for (name, value) in headers {
+            if let Ok(value) = value.to_str() {
+                header_map.insert(name.as_str().into(), value.into());
+            }
+        }
+        let result = self
+            .engine
+            .call_fn::<Dynamic>(&mut Scope::new(), ast, ENTRY, (request, header_map))
+            .map_err(|e| script_error(e.to_string()))?;
+        if result.is_unit() {
+            return Ok(Some(ScriptDecision::default()));
+        }
+        let result = result
+            .try_cast::<Map>()
+            .ok_or_else(|| script_error(format!("{} 应返回 () 或对象", ENTRY)))?;
+
+        let string = |key: &str| -> Result<Option<String>, (StatusCode, String)> {
+            match result.get(key) {
+                None => Ok(None),
+                Some(value) if value.is_unit() => Ok(None),
+                Some(value) => value
+                    .clone()
+                    .into_string()
+                    .map(Some)
+                    .map_err(|_| script_error(format!("{} 应为字符串", key))),
+            }
+        };
+
+        if let Some(reason) = string("reject")? {
+            metrics::counter!("route_script_rejections_total", "route" => route.to_string())
+                .increment(1);
+            return Err((StatusCode::FORBIDDEN, reason));
+        }
+
+        let mut decision = ScriptDecision::default();
+        if let Some(rewrites) = result.get("headers") {
+            let rewrites = rewrites
+                .clone()
+                .try_cast::<Map>()
+                .ok_or_else(|| script_error("headers 应为对象".to_string()))?;
+            for (name, value) in rewrites {
+                let name = HeaderName::try_from(name.as_str())
+                    .map_err(|e| script_error(format!("请求头 {} 无效: {}", name, e)))?;
+                let value = if value.is_unit() {
+                    None
+                } else {
+                    let value = value
+                        .into_string()
+                        .map_err(|_| script_error(format!("请求头 {} 的值应为字符串", name)))?;
+                    Some(
+                        HeaderValue::from_str(&value)
+                            .map_err(|e| script_error(format!("请求头 {} 无效: {}", name, e)))?,
+                    )
+                };
+                decision.headers.push((name, value));
+            }
+        }
+
+        let base_url = string("base_url")?;
+        let key_id = string("key_id")?;
+        if base_url.is_some() || key_id.is_some() {
+            decision.upstream = Some(
+                overrides
+                    .target(base_url.as_deref(), key_id.as_deref(), default_key)
+                    .map_err(|(_, e)| script_error(e))?,
+            );
+        }
+        Ok(Some(decision))
+    }
+}
@@ -0,0 +1,292 @@
+use axum::body::Bytes;
+
+/// 一个完整的 SSE 事件
+pub struct SseEvent {
+    /// 事件的原始字节（含结尾空行），原样转发时使用
+    pub raw: Bytes,
+    /// `data` 字段内容，多行时以换行拼接；只有注释等无数据的事件为 `None`
+    pub data: Option<String>,
+    /// `event` 字段，即事件类型；未指定时为 `None`（默认的 `message` 事件）
+    pub event: Option<String>,
+}
+
+/// UTF-8 字节序标记，流开头出现时忽略
+const BOM: &[u8] = b"\xEF\xBB\xBF";
+
+/// 增量 SSE 解析器
+///
+/// 上游的字节流可能在任意位置被切分（包括 `\r\n` 与多字节字符中间），解析器缓存不完整的事件，
+/// 每收到一个空行分隔的完整事件时将其返回。按规范同时支持 `\n`、`\r\n` 与 `\r` 换行，
+/// 以 `:` 开头的注释行不计入数据。
+#[derive(Default)]
+pub struct SseParser {
+    buffer: Vec<u8>,
+    /// 当前事件中已扫描过的字节数，避免每次从头查找
+    scanned: usize,
+    /// 当前行的起始位置
+    line_start: usize,
+    /// 是否已处理过流开头的字节序标记
+    started: bool,
+}
+
+impl SseParser {
+    /// 追加一段字节，返回其中已完整的事件
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<SseEvent> {
+        self.buffer.extend_from_slice(chunk);
+        if !self.started {
+            if self.buffer.len() < BOM.len() && BOM.starts_with(&self.buffer) {
+                return Vec::new();
+            }
+            self.started = true;
+            if self.buffer.starts_with(BOM) {
+                self.buffer.drain(..BOM.len());
+            }
+        }
+
+        let mut events = Vec::new();
+        while let Some(end) = self.next_event_end() {
+            let raw: Vec<u8> = self.buffer.drain(..end).collect();
+            self.scanned = 0;
+            self.line_start = 0;
+            let (event, data) = parse_fields(&raw);
+            events.push(SseEvent {
+                data,
+                event,
+                raw: Bytes::from(raw),
+            });
+        }
+        events
+    }
+
+    /// 查找第一个空行的结尾，即当前事件（含结尾空行）的长度
+    fn next_event_end(&mut self) -> Option<usize> {
+        while self.scanned < self.buffer.len() {
+            let i = self.scanned;
+            let len = match self.buffer[i] {
+                b'\n' => 1,
+                b'\r' => match self.buffer.get(i + 1) {
+                    Some(b'\n') => 2,
+                    Some(_) => 1,
+                    // 末尾的 `\r` 可能与下一段开头的 `\n` 组成一个换行，等待更多数据
+                    None => return None,
+                },
+                _ => {
+                    self.scanned += 1;
+                    continue;
+                }
+            };
+            let blank = i == self.line_start;
+            self.scanned = i + len;
+            self.line_start = self.scanned;
+            if blank {
+                return Some(self.scanned);
+            }
+        }
+        None
+    }
+
+    /// 流结束时取出尚未组成完整事件的剩余字节
+    pub fn finish(&mut self) -> Option<Bytes> {
+        self.scanned = 0;
+        self.line_start = 0;
+        (!self.buffer.is_empty()).then(|| Bytes::from(std::mem::take(&mut self.buffer)))
+    }
+
+    /// 流结束时把缺少结尾空行的剩余字节作为最后一个事件，`raw` 补上结尾空行；只剩换行时返回 `None`
+    pub fn finish_event(&mut self) -> Option<SseEvent> {
+        let rest = self.finish()?;
+        let end = rest
+            .iter()
+            .rposition(|byte| !matches!(byte, b'\n' | b'\r'))?
+            + 1;
+        let mut raw = rest[..end].to_vec();
+        raw.extend_from_slice(b"\n\n");
+        let (event, data) = parse_fields(&raw);
+        Some(SseEvent {
+            data,
+            event,
+            raw: Bytes::from(raw),
+        })
+    }
+}
+
+/// 取出事件的 `event` 字段与所有 `data` 字段，多个 `data` 以换行拼接
+fn parse_fields(raw: &[u8]) -> (Option<String>, Option<String>) {
+    let text = String::from_utf8_lossy(raw);
+    let mut event = None;
+    let mut data: Option<String> = None;
+    for line in text.split(['\n', '\r']) {
+        // 注释行与空行不含字段
+        if line.is_empty() || line.starts_with(':') {
+            continue;
+        }
+        let (field, value) = line.split_once(':').unwrap_or((line, ""));
+        let value = value.strip_prefix(' ').unwrap_or(value);
+        if field == "event" {
+            event = Some(value.to_string());
+            continue;
+        }
+        if field != "data" {
+            continue;
+        }
+        match &mut data {
+            Some(data) => {
+                data.push('\n');
+                data.push_str(value);
+            }
+            None => data = Some(value.to_string()),
+        }
+    }
+    (event, data)
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    /// 每个事件的原始字节与数据，以及流结束时的剩余字节
+    type Parsed = (Vec<(Vec<u8>, Option<String>)>, Option<Bytes>);
+
+    /// 依次推入各段字节
+    fn parse(chunks: &[&[u8]]) -> Parsed {
+        let mut parser = SseParser::default();
+        let mut events = Vec::new();
+        for chunk in chunks {
+            events.extend(
+                parser
+                    .push(chunk)
+                    .into_iter()
+                    .map(|event| (event.raw.to_vec(), event.data)),
+            );
+        }
+        (events, parser.finish())
+    }
+
+    fn data(chunks: &[&[u8]]) -> Vec<Option<String>> {
+        parse(chunks).0.into_iter().map(|(_, data)| data).collect()
+    }
+
+    #[test]
+    fn crlf_split_across_reads() {
+        assert_eq!(
+            data(&[b"data: a\r", b"\n\r", b"\ndata: b\r\n\r\n"]),
+            [Some("a".to_string()), Some("b".to_string())]
+        );
+    }
+
+    #[test]
+    fn bare_cr_line_endings() {
+        let (events, rest) = parse(&[b"data: a\r\rdata: b\r\r", b"data: c"]);
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0], (b"data: a\r\r".to_vec(), Some("a".to_string())));
+        assert_eq!(events[1].1.as_deref(), Some("b"));
+        assert_eq!(rest.as_deref(), Some(&b"data: c"[..]));
+    }
+
+    #[test]
+    fn trailing_cr_waits_for_next_read() {
+        let mut parser = SseParser::default();
+        assert!(parser.push(b"data: a\n\r").is_empty());
+        assert_eq!(parser.push(b"\n").len(), 1);
+    }
+
+    #[test]
+    fn bom_is_stripped_even_when_split() {
+        assert_eq!(
+            data(&[b"\xEF", b"\xBB", b"\xBFdata: a\n\n"]),
+            [Some("a".to_string())]
+        );
+        // 非开头的字节序标记属于数据
+        assert_eq!(
+            data(&[b"data: a\n\n\xEF\xBB\xBFdata: b\n\n"])[1].as_deref(),
+            None
+        );
+    }
+
+    #[test]
+    fn bare_data_line_is_empty_data() {
+        assert_eq!(data(&[b"data\n\n"]), [Some(String::new())]);
+    }
+
+    #[test]
+    fn multi_line_data_is_joined() {
+        assert_eq!(
+            data(&[b"data: a\ndata:b\ndata:  c\n\n"]),
+            [Some("a\nb\n c".to_string())]
+        );
+    }
+
+    #[test]
+    fn comments_and_other_fields_have_no_data() {
+        assert_eq!(data(&[b": ping\n\nevent: x\nid: 1\n\n"]), [None, None]);
+    }
+
+    #[test]
+    fn event_type_is_parsed() {
+        let mut parser = SseParser::default();
+        let events = parser.push(b"event: error\ndata: {}\n\ndata: a\n\n");
+        assert_eq!(events[0].event.as_deref(), Some("error"));
+        assert_eq!(events[0].data.as_deref(), Some("{}"));
+        assert_eq!(events[1].event, None);
+    }
+
+    #[test]
+    fn incomplete_event_is_returned_by_finish() {
+        let (events, rest) = parse(&[b"data: a\n\ndata: b\n"]);
+        assert_eq!(events.len(), 1);
+        assert_eq!(rest.as_deref(), Some(&b"data: b\n"[..]));
+    }
+
+    #[test]
+    fn finish_event_terminates_the_last_event() {
+        let mut parser = SseParser::default();
+        assert_eq!(parser.push(b"data: a\n\ndata: b\r\n").len(), 1);
+        let event = parser.finish_event().unwrap();
+        assert_eq!(event.data.as_deref(), Some("b"));
+        assert_eq!(&event.raw[..], b"data: b\n\n");
+
+        parser.push(b"data: a\n\n\n");
+        assert!(parser.finish_event().is_none());
+    }
+
+    /// 由 SSE 中常见的片段拼成的输入，覆盖各种换行与字节序标记
+    fn sse_input() -> impl Strategy<Value = Vec<u8>> {
+        let piece = prop_oneof![
+            Just(b"data: ".to_vec()),
+            Just(b"data".to_vec()),
+            Just(b": ping".to_vec()),
+            Just(b"id: 1".to_vec()),
+            Just(b"\n".to_vec()),
+            Just(b"\r".to_vec()),
+            Just(b"\r\n".to_vec()),
+            Just(b"\xEF\xBB\xBF".to_vec()),
+            Just("你好".as_bytes().to_vec()),
+            proptest::collection::vec(any::<u8>(), 0..8),
+        ];
+        proptest::collection::vec(piece, 0..32).prop_map(|pieces| pieces.concat())
+    }
+
+    proptest! {
+        /// 任意切分方式得到的事件与一次性解析相同
+        #[test]
+        fn chunking_does_not_change_events(
+            input in sse_input(),
+            cuts in proptest::collection::vec(any::<prop::sample::Index>(), 0..16),
+        ) {
+            let mut cuts: Vec<usize> = cuts.iter().map(|cut| cut.index(input.len() + 1)).collect();
+            cuts.sort_unstable();
+            cuts.dedup();
+            let mut chunks = Vec::new();
+            let mut start = 0;
+            for cut in cuts {
+                chunks.push(&input[start..cut]);
+                start = cut;
+            }
+            chunks.push(&input[start..]);
+
+            prop_assert_eq!(parse(&chunks), parse(&[&input]));
+        }
+    }
+}
@@ -0,0 +1,46 @@
+use axum::body::Bytes;
+use futures::{Stream, StreamExt};
+use tokio::sync::mpsc;
+use tracing::Instrument;
+
+/// 旁路通道容量（数据块个数），通道满时丢弃旁路副本而不是阻塞客户端
+const TEE_CHANNEL_CAPACITY: usize = 256;
+
+/// 将响应流复制一份到后台任务记录日志，不缓冲、不延迟发往客户端的数据
+///
+/// 每个数据块只克隆 `Bytes` 的引用计数；后台最多保留 `max_bytes` 字节，
+/// 超出部分和因通道拥塞丢弃的数据块只在日志中标注，内存占用有上限。
+pub fn tee_to_log<S, E>(stream: S, max_bytes: usize) -> impl Stream<Item = Result<Bytes, E>>
+where
+    S: Stream<Item = Result<Bytes, E>>,
+{
+    let (tx, mut rx) = mpsc::channel::<Bytes>(TEE_CHANNEL_CAPACITY);
+
+    tokio::spawn(
+        async move {
+            let mut captured = Vec::new();
+            let mut total_bytes = 0;
+            while let Some(chunk) = rx.recv().await {
+                total_bytes += chunk.len();
+                let remaining = max_bytes.saturating_sub(captured.len());
+                captured.extend_from_slice(&chunk[..chunk.len().min(remaining)]);
+            }
+            tracing::debug!(
+                total_bytes,
+                truncated = total_bytes > captured.len(),
+                "响应内容: {}",
+                String::from_utf8_lossy(&captured)
+            );
+        }
+        .in_current_span(),
+    );
+
+    stream.map(move |chunk| {
+        if let Ok(bytes) = &chunk
+            && tx.try_send(bytes.clone()).is_err()
+        {
+            tracing::debug!("日志旁路通道已满，丢弃 {} 字节的副本", bytes.len());
+        }
+        chunk
+    })
+}
@@ -0,0 +1,57 @@
+use std::path::PathBuf;
+
+use axum_server::tls_rustls::RustlsConfig;
+
+/// TLS 证书配置
+pub struct TlsPaths {
+    pub cert_path: PathBuf,
+    pub key_path: PathBuf,
+}
+
+impl TlsPaths {
+    /// 从 `TLS_CERT_PATH` / `TLS_KEY_PATH` 环境变量读取证书路径，两者都设置时才启用 TLS
+    pub fn from_env() -> Option<Self> {
+        let cert_path = std::env::var("TLS_CERT_PATH").ok()?;
+        let key_path = std::env::var("TLS_KEY_PATH").ok()?;
+        Some(Self {
+            cert_path: cert_path.into(),
+            key_path: key_path.into(),
+        })
+    }
+
+    /// 加载 PEM 格式的证书与私钥，ALPN 同时声明 h2 与 http/1.1
+    pub async fn load(&self) -> anyhow::Result<RustlsConfig> {
+        let _ = rustls::crypto::ring::default_provider().install_default();
+        let config = RustlsConfig::from_pem_file(&self.cert_path, &self.key_path).await?;
+        Ok(config)
+    }
+
+    /// 收到 SIGHUP 时重新加载证书，无需重启进程即可完成证书轮换
+    #[cfg(unix)]
+    pub fn spawn_reload_on_sighup(self, config: RustlsConfig) {
+        use tokio::signal::unix::{SignalKind, signal};
+
+        tokio::spawn(async move {
+            let mut hangup = match signal(SignalKind::hangup()) {
+                Ok(hangup) => hangup,
+                Err(e) => {
+                    tracing::error!("无法监听 SIGHUP 信号: {}", e);
+                    return;
+                }
+            };
+            while hangup.recv().await.is_some() {
+                match config
+                    .reload_from_pem_file(&self.cert_path, &self.key_path)
+                    .await
+                {
+                    Ok(()) => tracing::info!("TLS 证书已重新加载"),
+                    Err(e) => tracing::error!("TLS 证书重新加载失败，继续使用旧证书: {}", e),
+                }
+            }
+        });
+    }
+
+    /// 非 Unix 平台没有 SIGHUP，证书更新需要重启进程
+    #[cfg(not(unix))]
+    pub fn spawn_reload_on_sighup(self, _config: RustlsConfig) {}
+}
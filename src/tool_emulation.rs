@@ -0,0 +1,403 @@
+use std::collections::HashMap;
+
+use axum::{body::Bytes, http::StatusCode};
+use serde_json::{Map, Value, json};
+
+use crate::chat::{
+    ChatCompletionRequest, ChatCompletionResponse, Choice, Content, Delta, FunctionCall, Message,
+    Tool, ToolCall, ToolChoice,
+};
+
+/// 代码块的结束标记
+const FENCE: &str = "```";
+
+/// 工具调用代码块的起始标记，只有此类代码块会被解析为工具调用
+const TOOL_CALL_FENCE: &str = "```tool_call";
+
+/// 需要由代理模拟工具调用的模型列表
+///
+/// 读取 `TOOL_EMULATION_MODELS`，逗号分隔，`*` 表示所有模型。列表中的模型收到带 `tools` 的请求时，
+/// 代理把工具定义写入系统提示词、把历史中的工具调用与结果改写为普通消息，
+/// 再从模型输出的 ```` ```tool_call ```` 代码块中解析调用，以标准的 `tool_calls` 返回给客户端。
+pub struct ToolEmulation(Vec<String>);
+
+impl ToolEmulation {
+    pub fn from_env() -> Self {
+        let models = std::env::var("TOOL_EMULATION_MODELS")
+            .map(|config| {
+                config
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|s| !s.is_empty())
+                    .map(str::to_string)
+                    .collect()
+            })
+            .unwrap_or_default();
+        Self(models)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn contains(&self, model: &str) -> bool {
+        self.0.iter().any(|m| m == "*" || m == model)
+    }
+
+    /// 需要模拟时改写请求体，返回改写后的请求体与是否启用了模拟
+    pub fn rewrite_request(&self, body: Bytes) -> Result<(Bytes, bool), (StatusCode, String)> {
+        let Some(mut request) = ChatCompletionRequest::parse(&body) else {
+            return Ok((body, false));
+        };
+        let has_tools = request
+            .tools
+            .as_ref()
+            .is_some_and(|tools| !tools.is_empty());
+        if !has_tools || !self.contains(&request.model) {
+            return Ok((body, false));
+        }
+
+        let tools = request.tools.take().unwrap_or_default();
+        let tool_choice = request.tool_choice.take();
+        request.extra.remove("parallel_tool_calls");
+        // 客户端明确不允许调用工具时只需去掉工具定义
+        let emulate = !matches!(&tool_choice, Some(ToolChoice::Mode(mode)) if mode == "none");
+
+        for message in &mut request.messages {
+            flatten_message(message);
+        }
+        if emulate {
+            let prompt = tools_prompt(&tools, tool_choice.as_ref());
+            match request
+                .messages
+                .iter_mut()
+                .find(|message| message.role == "system")
+            {
+                Some(message) => {
+                    message.content = Content::Text(format!("{}\n\n{}", message.text(), prompt));
+                }
+                None => request.messages.insert(0, Message::new("system", prompt)),
+            }
+        }
+
+        Ok((request.to_bytes()?, emulate))
+    }
+}
+
+/// 生成描述工具与调用格式的系统提示词
+fn tools_prompt(tools: &[Tool], tool_choice: Option<&ToolChoice>) -> String {
+    let functions: Vec<_> = tools.iter().map(|tool| &tool.function).collect();
+    let mut prompt = format!(
+        "You have access to the following tools, described as JSON schemas:\n{}\n\n\
+         To call a tool, reply with a fenced code block tagged tool_call containing a JSON object \
+         with the tool name and its arguments, one block per call:\n\
+         ```tool_call\n{{\"name\": \"<tool name>\", \"arguments\": {{}}}}\n```\n\
+         Do not write anything after the tool call blocks. If no tool is needed, answer normally.",
+        serde_json::to_string_pretty(&functions).unwrap_or_default()
+    );
+    match tool_choice {
+        Some(ToolChoice::Mode(mode)) if mode == "required" => {
+            prompt.push_str("\nYou must call at least one tool.");
+        }
+        Some(choice) => {
+            if let Some(name) = choice.function_name() {
+                prompt.push_str(&format!("\nYou must call the tool {}.", name));
+            }
+        }
+        None => {}
+    }
+    prompt
+}
+
+/// 把历史中的工具调用与工具结果改写为模型能理解的普通消息
+fn flatten_message(message: &mut Message) {
+    match message.role.as_str() {
+        "assistant" => {
+            let Some(calls) = message.tool_calls.take() else {
+                return;
+            };
+            let mut content = message.text();
+            for call in calls {
+                let name = call.function.name.unwrap_or_default();
+                let arguments = call
+                    .function
+                    .arguments
+                    .and_then(|arguments| serde_json::from_str(&arguments).ok())
+                    .unwrap_or_else(|| json!({}));
+                let block = json!({ "name": name, "arguments": arguments });
+                content.push_str(&format!("\n{}tool_call\n{}\n{}", FENCE, block, FENCE));
+            }
+            message.content = Content::Text(content.trim_start().to_string());
+        }
+        "tool" => {
+            let id = message.tool_call_id.as_deref().unwrap_or_default();
+            let content = format!("Result of tool call {}:\n{}", id, message.text());
+            *message = Message::new("user", content);
+        }
+        _ => {}
+    }
+}
+
+/// 从文本中取出 `tool_call` 代码块，返回剩余文本与 OpenAI 格式的 `tool_calls`，其他代码块原样保留
+fn extract_tool_calls(text: &str) -> (String, Vec<ToolCall>) {
+    let mut rest = String::new();
+    let mut calls = Vec::new();
+    let mut remaining = text;
+    while let Some(start) = remaining.find(TOOL_CALL_FENCE) {
+        let after = &remaining[start + TOOL_CALL_FENCE.len()..];
+        let Some(end) = after.find(FENCE) else {
+            break;
+        };
+        let block_end = start + TOOL_CALL_FENCE.len() + end + FENCE.len();
+        match parse_call(&after[..end]) {
+            Some(call) => {
+                rest.push_str(&remaining[..start]);
+                calls.push(call);
+            }
+            None => rest.push_str(&remaining[..block_end]),
+        }
+        remaining = &remaining[block_end..];
+    }
+    rest.push_str(remaining);
+    (rest.trim().to_string(), calls)
+}
+
+/// 解析单个工具调用，`arguments` 按 OpenAI 约定序列化为字符串
+fn parse_call(body: &str) -> Option<ToolCall> {
+    let value: Value = serde_json::from_str(body.trim()).ok()?;
+    let name = value.get("name")?.as_str()?;
+    let arguments = match value.get("arguments") {
+        Some(Value::String(arguments)) => arguments.clone(),
+        Some(arguments) => arguments.to_string(),
+        None => "{}".to_string(),
+    };
+    Some(ToolCall {
+        id: Some(format!("call_{}", uuid::Uuid::now_v7().simple())),
+        kind: Some("function".to_string()),
+        function: FunctionCall {
+            name: Some(name.to_string()),
+            arguments: Some(arguments),
+            ..FunctionCall::default()
+        },
+        ..ToolCall::default()
+    })
+}
+
+/// 把非流式响应中的工具调用代码块改写为 `tool_calls`
+pub fn rewrite_response(body: Bytes) -> Bytes {
+    let Some(mut response) = ChatCompletionResponse::parse(&body) else {
+        return body;
+    };
+    let mut rewritten = false;
+    for choice in response.choices_mut() {
+        let Some(message) = choice.message.as_mut() else {
+            continue;
+        };
+        let Content::Text(content) = &message.content else {
+            continue;
+        };
+        let (rest, calls) = extract_tool_calls(content);
+        if calls.is_empty() {
+            continue;
+        }
+        message.content = if rest.is_empty() {
+            Content::Other(Value::Null)
+        } else {
+            Content::Text(rest)
+        };
+        message.tool_calls = Some(calls);
+        choice.finish_reason = Some("tool_calls".to_string());
+        rewritten = true;
+    }
+    if !rewritten {
+        return body;
+    }
+    serde_json::to_vec(&response).map_or(body, Bytes::from)
+}
+
+/// 流式响应的工具调用改写
+///
+/// 正文中出现 `tool_call` 代码块标记后暂存之后的内容，到该 choice 结束时统一解析：
+/// 包含工具调用则以 `delta.tool_calls` 发出并把结束原因改为 `tool_calls`，否则原样补发暂存的正文。
+/// 上游没有发出结束原因就结束时，由 [`finish`](Self::finish) 补发。
+#[derive(Default)]
+pub struct StreamEmulator {
+    pending: HashMap<u64, String>,
+}
+
+impl StreamEmulator {
+    /// 改写一个数据块中各 choice 的正文
+    pub fn process(&mut self, chunk: &mut ChatCompletionResponse) {
+        for choice in chunk.choices_mut() {
+            self.process_choice(choice);
+        }
+    }
+
+    fn process_choice(&mut self, choice: &mut Choice) {
+        let finished = choice.is_finished();
+        let pending = self.pending.remove(&choice.index).unwrap_or_default();
+        // 结束的数据块可能不带 delta，仍需补发暂存的正文
+        let delta = match choice.delta.as_mut() {
+            Some(delta) => delta,
+            None if finished && !pending.is_empty() => choice.delta.insert(Delta::default()),
+            None => return,
+        };
+        let text = pending + delta.content.as_deref().unwrap_or_default();
+        if text.is_empty() {
+            return;
+        }
+
+        if !finished {
+            let split = hold_back_at(&text);
+            let (ready, rest) = text.split_at(split);
+            if !rest.is_empty() {
+                self.pending.insert(choice.index, rest.to_string());
+            }
+            delta.content = Some(ready.to_string());
+            return;
+        }
+
+        complete(choice, text);
+    }
+
+    /// 上游结束时仍未收到结束原因的 choice，按已暂存的正文补发
+    pub fn finish(&mut self) -> Vec<Choice> {
+        let mut pending: Vec<_> = self.pending.drain().collect();
+        pending.sort_by_key(|(index, _)| *index);
+        pending
+            .into_iter()
+            .map(|(index, text)| {
+                let mut choice = Choice {
+                    index,
+                    message: None,
+                    delta: Some(Delta::default()),
+                    finish_reason: None,
+                    extra: Map::new(),
+                };
+                complete(&mut choice, text);
+                choice
+            })
+            .collect()
+    }
+}
+
+/// 解析 choice 的完整暂存正文：包含工具调用则以 `delta.tool_calls` 发出并把结束原因改为 `tool_calls`，否则原样发出
+fn complete(choice: &mut Choice, text: String) {
+    let delta = choice.delta.get_or_insert_with(Delta::default);
+    let (rest, calls) = extract_tool_calls(&text);
+    if calls.is_empty() {
+        delta.content = Some(text);
+        return;
+    }
+    delta.content = Some(rest);
+    // 流式格式的 `tool_calls` 需要带上序号
+    delta.tool_calls = Some(
+        calls
+            .into_iter()
+            .enumerate()
+            .map(|(index, call)| ToolCall {
+                index: Some(index as u64),
+                ..call
+            })
+            .collect(),
+    );
+    choice.finish_reason = Some("tool_calls".to_string());
+}
+
+/// 从第一个 `tool_call` 代码块标记开始暂存；末尾可能是未完整标记的部分同样暂存，其他代码块照常转发
+fn hold_back_at(text: &str) -> usize {
+    if let Some(start) = text.find(TOOL_CALL_FENCE) {
+        return start;
+    }
+    // 标记为 ASCII，末尾与标记前缀相同的部分必然位于字符边界上
+    let partial = (1..TOOL_CALL_FENCE.len())
+        .rev()
+        .find(|&len| text.ends_with(&TOOL_CALL_FENCE[..len]))
+        .unwrap_or(0);
+    text.len() - partial
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(content: Option<&str>, finish_reason: Option<&str>) -> ChatCompletionResponse {
+        let mut choice = json!({ "index": 0, "finish_reason": finish_reason });
+        if let Some(content) = content {
+            choice["delta"] = json!({ "content": content });
+        }
+        serde_json::from_value(json!({ "choices": [choice] })).unwrap()
+    }
+
+    fn delta(chunk: &ChatCompletionResponse) -> &Delta {
+        chunk.choices()[0].delta.as_ref().unwrap()
+    }
+
+    #[test]
+    fn extracts_tool_calls_and_keeps_other_blocks() {
+        let text = "先查天气\n```tool_call\n{\"name\": \"weather\", \"arguments\": {\"city\": \"北京\"}}\n```\n\
+                    ```tool_call\nnot json\n```\n```rust\nfn main() {}\n```";
+        let (rest, calls) = extract_tool_calls(text);
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].function.name.as_deref(), Some("weather"));
+        assert_eq!(
+            calls[0].function.arguments.as_deref(),
+            Some(r#"{"city":"北京"}"#)
+        );
+        // 无法解析的工具调用代码块与其他代码块原样保留
+        assert_eq!(
+            rest,
+            "先查天气\n\n```tool_call\nnot json\n```\n```rust\nfn main() {}\n```"
+        );
+
+        let (rest, calls) = extract_tool_calls("```tool_call\n{\"name\": \"a\"");
+        assert!(calls.is_empty());
+        assert_eq!(rest, "```tool_call\n{\"name\": \"a\"");
+    }
+
+    #[test]
+    fn holds_back_from_split_marker() {
+        assert_eq!(hold_back_at("答案是 42"), "答案是 42".len());
+        assert_eq!(hold_back_at("好的```tool"), "好的".len());
+        assert_eq!(hold_back_at("好的`"), "好的".len());
+        assert_eq!(hold_back_at("a```tool_call\n{}"), 1);
+        // 其他代码块的标记不会被暂存
+        assert_eq!(hold_back_at("```rust\n"), "```rust\n".len());
+    }
+
+    #[test]
+    fn streams_tool_calls_split_across_chunks() {
+        let mut emulator = StreamEmulator::default();
+        let mut first = chunk(Some("好的```tool"), None);
+        emulator.process(&mut first);
+        assert_eq!(delta(&first).content.as_deref(), Some("好的"));
+
+        let mut second = chunk(Some("_call\n{\"name\": \"a\"}\n```"), None);
+        emulator.process(&mut second);
+        assert_eq!(delta(&second).content.as_deref(), Some(""));
+
+        // 结束的数据块不带 delta 时同样补发
+        let mut last = chunk(None, Some("stop"));
+        emulator.process(&mut last);
+        let choice = &last.choices()[0];
+        assert_eq!(choice.finish_reason.as_deref(), Some("tool_calls"));
+        let calls = delta(&last).tool_calls.as_ref().unwrap();
+        assert_eq!(calls[0].index, Some(0));
+        assert_eq!(calls[0].function.name.as_deref(), Some("a"));
+        assert!(emulator.finish().is_empty());
+    }
+
+    #[test]
+    fn flushes_pending_text_at_end_of_stream() {
+        let mut emulator = StreamEmulator::default();
+        let mut chunk = chunk(Some("结尾```tool_call\n{\"name\""), None);
+        emulator.process(&mut chunk);
+        assert_eq!(delta(&chunk).content.as_deref(), Some("结尾"));
+
+        let choices = emulator.finish();
+        assert_eq!(choices.len(), 1);
+        let delta = choices[0].delta.as_ref().unwrap();
+        assert_eq!(delta.content.as_deref(), Some("```tool_call\n{\"name\""));
+        assert_eq!(choices[0].finish_reason, None);
+    }
+}
@@ -0,0 +1,40 @@
+use axum::{
+    body::Bytes,
+    http::{HeaderMap, StatusCode},
+};
+use futures::{StreamExt, stream::BoxStream};
+
+/// 上游响应：状态码、响应头与字节流
+///
+/// 单次调用直接由 `reqwest::Response` 转换而来；n-best 扇出等场景下由多个上游响应合并而成，
+/// 后续的取消、脱敏还原、日志旁路与幂等记录对两者一视同仁。
+pub struct UpstreamResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: BoxStream<'static, reqwest::Result<Bytes>>,
+}
+
+impl From<reqwest::Response> for UpstreamResponse {
+    fn from(response: reqwest::Response) -> Self {
+        Self {
+            status: response.status(),
+            headers: response.headers().clone(),
+            body: response.bytes_stream().boxed(),
+        }
+    }
+}
+
+impl UpstreamResponse {
+    /// 读取完整响应体，超过 `max_bytes` 时返回错误
+    pub async fn bytes(self, max_bytes: usize) -> Result<Bytes, String> {
+        let mut body = self.body;
+        let mut bytes = Vec::new();
+        while let Some(chunk) = body.next().await {
+            bytes.extend_from_slice(&chunk.map_err(|e| e.to_string())?);
+            if bytes.len() > max_bytes {
+                return Err(format!("上游响应体超过 {} 字节", max_bytes));
+            }
+        }
+        Ok(Bytes::from(bytes))
+    }
+}